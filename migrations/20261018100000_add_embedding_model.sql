@@ -0,0 +1,18 @@
+-- Which model produced each chunk embedding; rows from before this migration are
+-- adopted at startup by the configured embedding model
+ALTER TABLE memory_chunks ADD COLUMN embedding_model TEXT;
+ALTER TABLE memory_chunks ADD COLUMN embedding_dim INTEGER;
+CREATE INDEX IF NOT EXISTS idx_memory_chunks_embedding_model ON memory_chunks(embedding_model);
+
+-- Re-embedding runs after an embedding model change; progress is kept so a restart resumes it
+CREATE TABLE IF NOT EXISTS reembed_jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    model TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'running', -- running | done | failed | cancelled
+    total INTEGER NOT NULL DEFAULT 0,
+    processed INTEGER NOT NULL DEFAULT 0,
+    last_chunk_id INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    started_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,4 @@
+-- Long messages are split into several overlapping chunks; chunk_index is their order
+-- within the message (existing rows are single-chunk messages)
+ALTER TABLE memory_chunks ADD COLUMN chunk_index INTEGER NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS idx_memory_chunks_message_chunk ON memory_chunks(message_id, chunk_index);
@@ -0,0 +1,21 @@
+-- Facts about chat members extracted from their messages, one per (chat, user, topic key);
+-- a newer fact under the same key replaces the older one
+CREATE TABLE IF NOT EXISTS user_facts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    chat_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    fact_key TEXT NOT NULL,
+    fact TEXT NOT NULL,
+    confidence REAL NOT NULL DEFAULT 0.5,
+    source_message_ids TEXT NOT NULL DEFAULT '[]', -- JSON array of messages.id
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (chat_id, user_id, fact_key)
+);
+
+-- Last message of each chat already seen by the fact extractor
+CREATE TABLE IF NOT EXISTS fact_extraction_progress (
+    chat_id INTEGER PRIMARY KEY,
+    last_message_id INTEGER NOT NULL DEFAULT 0,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,3 @@
+-- Per-chat retention policy: messages older than this many days are deleted
+-- together with their memory chunks. 0 keeps everything.
+ALTER TABLE chat_settings ADD COLUMN retention_days INTEGER NOT NULL DEFAULT 0;
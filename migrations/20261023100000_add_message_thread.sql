@@ -0,0 +1,5 @@
+-- Forum thread of a message, so short-term history can be rebuilt per thread.
+-- Messages stored before this are treated as outside any thread.
+ALTER TABLE messages ADD COLUMN thread_id INTEGER;
+
+CREATE INDEX IF NOT EXISTS idx_messages_chat_thread ON messages(chat_id, thread_id, id);
@@ -0,0 +1,4 @@
+-- What a message was: 'text', or the kind of media its text was derived from
+-- ('voice', 'video_note', 'animation', 'photo', ...). For media, `text` holds
+-- the caption together with the transcript or vision description.
+ALTER TABLE messages ADD COLUMN content_type TEXT NOT NULL DEFAULT 'text';
@@ -0,0 +1,14 @@
+-- Unanswered group messages used to be saved (and embedded) twice. Keep the first
+-- copy of every (chat_id, message_id); chunks of the dropped copies go with them.
+DELETE FROM memory_chunks WHERE message_id IN (
+    SELECT id FROM messages
+    WHERE id NOT IN (SELECT MIN(id) FROM messages GROUP BY chat_id, message_id)
+);
+
+DELETE FROM messages
+WHERE id NOT IN (SELECT MIN(id) FROM messages GROUP BY chat_id, message_id);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_messages_chat_message ON messages(chat_id, message_id);
+
+-- Set when an edit rewrote the stored text
+ALTER TABLE messages ADD COLUMN edited_at TIMESTAMP;
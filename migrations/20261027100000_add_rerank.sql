@@ -0,0 +1,2 @@
+-- Time budget for reranking retrieved memories with an LLM, in milliseconds; 0 turns reranking off
+ALTER TABLE chat_settings ADD COLUMN rerank_budget_ms INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,7 @@
+-- Two embedding jobs for the same message could both insert its chunks. Keep the
+-- newest copy of every (message_id, chunk_index) and let saves upsert from now on.
+DELETE FROM memory_chunks
+WHERE id NOT IN (SELECT MAX(id) FROM memory_chunks GROUP BY message_id, chunk_index);
+
+DROP INDEX IF EXISTS idx_memory_chunks_message_chunk;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_memory_chunks_message_chunk ON memory_chunks(message_id, chunk_index);
@@ -0,0 +1,4 @@
+-- Importance a pinned chunk had before pinning, restored on unpin; NULL if not pinned.
+-- Chunks pinned so far could only have come from the default importance.
+ALTER TABLE memory_chunks ADD COLUMN importance_before_pin REAL;
+UPDATE memory_chunks SET importance_before_pin = 1.0 WHERE importance_score >= 2.0;
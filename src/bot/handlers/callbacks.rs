@@ -1,5 +1,8 @@
 use crate::state::{AppState, WizardState};
 use crate::db;
+use crate::llm::cancel::CancelReason;
+use crate::memory::{privacy, summarizer};
+use super::{escape_html, rerank_label, retention_label};
 use teloxide::prelude::*;
 use teloxide::types::{ParseMode, InlineKeyboardButton, InlineKeyboardMarkup, MessageId};
 
@@ -13,6 +16,16 @@ pub async fn handle_callback_query(bot: Bot, q: CallbackQuery, state: AppState)
 
     let chat_id = message.chat().id;
     let msg_id = message.id();
+
+    // The ⏹ button under a reply in progress; its requester may press it too
+    if let Some(id) = q.data.as_deref().and_then(|d| d.strip_prefix("stop_gen:")).and_then(|id| id.parse::<u64>().ok()) {
+        return handle_stop_button(&bot, &q, &state, id).await;
+    }
+
+    // /forget_me confirmation; only the user who asked may answer it
+    if let Some(answer) = q.data.as_deref().and_then(|d| d.strip_prefix("forget_me:")) {
+        return handle_forget_me_button(&bot, &q, &state, chat_id, msg_id, answer).await;
+    }
     
     // Check if the user is the owner
     if q.from.id.0 != state.config.owner_id {
@@ -25,7 +38,8 @@ pub async fn handle_callback_query(bot: Bot, q: CallbackQuery, state: AppState)
     let data = q.data.as_deref().unwrap_or("");
     
     // Parse callback data
-    let parts: Vec<&str> = data.split(':').collect();
+    // Only split once: model names like "llama3:8b" contain ':'
+    let parts: Vec<&str> = data.splitn(2, ':').collect();
     let action = parts[0];
     let param = parts.get(1).copied();
 
@@ -89,7 +103,7 @@ pub async fn handle_callback_query(bot: Bot, q: CallbackQuery, state: AppState)
         
         // === CONFIG ===
         "config" => edit_config_menu(&bot, chat_id, msg_id, &state).await?,
-        "cfg_model" => edit_model_select(&bot, chat_id, msg_id, &state).await?,
+        "cfg_model" => edit_model_select(&bot, chat_id, msg_id, &state, false).await?,
         "cfg_set_model" => {
             if let Some(model) = param {
                 let _ = db::set_config(&state.db_pool, "ollama_chat_model", model).await;
@@ -98,6 +112,15 @@ pub async fn handle_callback_query(bot: Bot, q: CallbackQuery, state: AppState)
                 return Ok(());
             }
         }
+        "cfg_vmodel" => edit_model_select(&bot, chat_id, msg_id, &state, true).await?,
+        "cfg_set_vmodel" => {
+            if let Some(model) = param {
+                let _ = db::set_config(&state.db_pool, "ollama_vision_model", model).await;
+                bot.answer_callback_query(q.id.clone()).text(format!("✅ Vision-модель: {}", model)).await?;
+                edit_config_menu(&bot, chat_id, msg_id, &state).await?;
+                return Ok(());
+            }
+        }
         "cfg_temp" => edit_temperature_menu(&bot, chat_id, msg_id, &state).await?,
         "cfg_set_temp" => {
             if let Some(temp) = param {
@@ -116,6 +139,12 @@ pub async fn handle_callback_query(bot: Bot, q: CallbackQuery, state: AppState)
                 return Ok(());
             }
         }
+        "cfg_ann_rebuild" => {
+            state.vector_index.clear();
+            bot.answer_callback_query(q.id.clone()).text("✅ Индексы перестроятся при следующем поиске").await?;
+            edit_config_menu(&bot, chat_id, msg_id, &state).await?;
+            return Ok(());
+        }
         "cfg_toggle" => {
             if let Some(key) = param {
                 let current = db::get_config_bool(&state.db_pool, key, false).await;
@@ -166,6 +195,18 @@ pub async fn handle_callback_query(bot: Bot, q: CallbackQuery, state: AppState)
             edit_chat_menu(&bot, chat_id, msg_id, &state).await?;
             return Ok(());
         }
+        "chat_hybrid_on" => {
+            let _ = db::toggle_hybrid_search_for_chat(&state.db_pool, chat_id.0, true).await;
+            bot.answer_callback_query(q.id.clone()).text("✅ Гибридный поиск включен").await?;
+            edit_chat_menu(&bot, chat_id, msg_id, &state).await?;
+            return Ok(());
+        }
+        "chat_hybrid_off" => {
+            let _ = db::toggle_hybrid_search_for_chat(&state.db_pool, chat_id.0, false).await;
+            bot.answer_callback_query(q.id.clone()).text("✅ Гибридный поиск выключен").await?;
+            edit_chat_menu(&bot, chat_id, msg_id, &state).await?;
+            return Ok(());
+        }
         "chat_cooldown" => edit_cooldown_menu(&bot, chat_id, msg_id).await?,
         "chat_set_cd" => {
             if let Some(cd) = param.and_then(|p| p.parse::<i64>().ok()) {
@@ -192,13 +233,42 @@ pub async fn handle_callback_query(bot: Bot, q: CallbackQuery, state: AppState)
         "chat_set_depth" => {
             if let Some(depth) = param.and_then(|p| p.parse::<i64>().ok()) {
                 let settings = db::get_or_create_chat_settings(&state.db_pool, chat_id.0).await
-                    .unwrap_or(db::ChatSettings { chat_id: chat_id.0, auto_reply_enabled: true, reply_mode: "mention_only".into(), cooldown_seconds: 5, context_depth: 10, rag_enabled: true });
+                    .unwrap_or(db::ChatSettings { chat_id: chat_id.0, auto_reply_enabled: true, reply_mode: "mention_only".into(), cooldown_seconds: 5, context_depth: 10, rag_enabled: true, hybrid_search_enabled: false, retention_days: 0, rerank_budget_ms: 0 });
                 let _ = db::update_rag_settings(&state.db_pool, chat_id.0, settings.rag_enabled, depth).await;
                 bot.answer_callback_query(q.id.clone()).text(format!("✅ Глубина памяти: {}", depth)).await?;
                 edit_chat_menu(&bot, chat_id, msg_id, &state).await?;
                 return Ok(());
             }
         }
+        "chat_retention" => edit_retention_menu(&bot, chat_id, msg_id, &state).await?,
+        "chat_set_retention" => {
+            if let Some(days) = param.and_then(|p| p.parse::<i64>().ok()) {
+                let _ = db::set_retention_for_chat(&state.db_pool, chat_id.0, days).await;
+                bot.answer_callback_query(q.id.clone()).text(format!("✅ Хранение: {}", retention_label(days))).await?;
+                edit_chat_menu(&bot, chat_id, msg_id, &state).await?;
+                return Ok(());
+            }
+        }
+        "chat_rerank" => edit_rerank_menu(&bot, chat_id, msg_id, &state).await?,
+        "chat_set_rerank" => {
+            if let Some(budget_ms) = param.and_then(|p| p.parse::<i64>().ok()) {
+                let _ = db::set_rerank_budget_for_chat(&state.db_pool, chat_id.0, budget_ms).await;
+                bot.answer_callback_query(q.id.clone()).text(format!("✅ Реранк: {}", rerank_label(budget_ms))).await?;
+                edit_chat_menu(&bot, chat_id, msg_id, &state).await?;
+                return Ok(());
+            }
+        }
+        "chat_summaries" => edit_summaries_menu(&bot, chat_id, msg_id, &state, None).await?,
+        "chat_sum_regen" => {
+            bot.answer_callback_query(q.id.clone()).text("⏳ Пересоздаю сводку...").await?;
+            let notice = match summarizer::regenerate_latest(&state, chat_id.0).await {
+                Ok(Some(_)) => "✅ Сводка обновлена".to_string(),
+                Ok(None) => "ℹ️ Нечего сводить или сводка уже создаётся".to_string(),
+                Err(e) => format!("❌ Ошибка: {}", e),
+            };
+            edit_summaries_menu(&bot, chat_id, msg_id, &state, Some(&notice)).await?;
+            return Ok(());
+        }
         
         // === TOOLS ===
         "tools" => edit_tools_menu(&bot, chat_id, msg_id).await?,
@@ -210,12 +280,14 @@ pub async fn handle_callback_query(bot: Bot, q: CallbackQuery, state: AppState)
         "tools_clear_history" => edit_clear_history_menu(&bot, chat_id, msg_id).await?,
         "tools_clear_confirm" => {
             let _ = db::clear_chat_history(&state.db_pool, chat_id.0).await;
+            state.vector_index.invalidate(chat_id.0);
             bot.answer_callback_query(q.id.clone()).text("✅ История очищена").await?;
             edit_tools_menu(&bot, chat_id, msg_id).await?;
             return Ok(());
         }
         "tools_clear_memory" => {
             let _ = db::clear_chat_memory(&state.db_pool, chat_id.0).await;
+            state.vector_index.invalidate(chat_id.0);
             bot.answer_callback_query(q.id.clone()).text("✅ RAG память очищена").await?;
             edit_tools_menu(&bot, chat_id, msg_id).await?;
             return Ok(());
@@ -452,31 +524,106 @@ async fn export_all_personas_inline(bot: &Bot, chat_id: ChatId, state: &AppState
     Ok(())
 }
 
+async fn handle_stop_button(bot: &Bot, q: &CallbackQuery, state: &AppState, id: u64) -> ResponseResult<()> {
+    let text = match state.generations.find(id) {
+        None => "Ответ уже готов.",
+        Some(generation) if q.from.id.0 == state.config.owner_id || generation.user_id == Some(q.from.id.0) => {
+            generation.cancel.cancel(CancelReason::Stopped);
+            "⏹ Остановлено."
+        }
+        Some(_) => "❌ У вас нет прав.",
+    };
+    bot.answer_callback_query(q.id.clone()).text(text).await?;
+    Ok(())
+}
+
+async fn handle_forget_me_button(
+    bot: &Bot,
+    q: &CallbackQuery,
+    state: &AppState,
+    chat_id: ChatId,
+    msg_id: MessageId,
+    answer: &str,
+) -> ResponseResult<()> {
+    let (confirmed, user_id) = match answer.split_once(':') {
+        Some((choice, id)) => (choice == "yes", id.parse::<u64>().ok()),
+        None => (false, None),
+    };
+    if user_id != Some(q.from.id.0) {
+        bot.answer_callback_query(q.id.clone()).text("❌ Это не ваш запрос.").await?;
+        return Ok(());
+    }
+    if !confirmed {
+        bot.answer_callback_query(q.id.clone()).await?;
+        bot.edit_message_text(chat_id, msg_id, "Отменено, ничего не удалено.").await?;
+        return Ok(());
+    }
+
+    bot.answer_callback_query(q.id.clone()).text("⏳ Удаляю...").await?;
+    let text = match privacy::forget_user(state, q.from.id.0).await {
+        Ok(deleted) => format!(
+            "🗑️ Готово. Удалено сообщений: {}, фрагментов памяти: {}, фактов: {}, сводок: {} (чатов: {}).",
+            deleted.messages,
+            deleted.chunks,
+            deleted.facts,
+            deleted.summaries,
+            deleted.chats.len()
+        ),
+        Err(e) => {
+            log::error!("Failed to forget user {}: {}", q.from.id.0, e);
+            "❌ Ошибка базы данных, попробуйте позже.".to_string()
+        }
+    };
+    bot.edit_message_text(chat_id, msg_id, text).await?;
+    Ok(())
+}
+
 async fn edit_config_menu(bot: &Bot, chat_id: ChatId, msg_id: MessageId, state: &AppState) -> ResponseResult<()> {
     let model = db::get_config(&state.db_pool, "ollama_chat_model").await.ok().flatten()
         .unwrap_or_else(|| state.config.ollama_chat_model.clone());
+    let vision_model = db::get_config(&state.db_pool, "ollama_vision_model").await.ok().flatten()
+        .unwrap_or_else(|| state.config.ollama_vision_model.clone());
     let temp = db::get_config_f64(&state.db_pool, "temperature", state.config.temperature).await;
     let tokens = db::get_config_u32(&state.db_pool, "max_tokens", state.config.max_tokens).await;
     let vision = db::get_config_bool(&state.db_pool, "vision_enabled", state.config.vision_enabled).await;
     let voice = db::get_config_bool(&state.db_pool, "voice_enabled", state.config.voice_enabled).await;
     let web = db::get_config_bool(&state.db_pool, "web_search_enabled", state.config.web_search_enabled).await;
+    let tools = db::get_config_bool(&state.db_pool, "tools_enabled", state.config.tools_enabled).await;
+    let auto_cancel = db::get_config_bool(&state.db_pool, "auto_cancel_enabled", state.config.auto_cancel_enabled).await;
+    let rewrite = db::get_config_bool(&state.db_pool, "query_rewrite_enabled", state.config.query_rewrite_enabled).await;
+    let ann = db::get_config_bool(&state.db_pool, "vector_index_enabled", state.config.vector_index_enabled).await;
+    let (ann_chats, ann_vectors) = state.vector_index.stats();
     
     let text = format!(
         "⚙️ <b>Конфигурация</b>\n\n\
         🤖 Модель: <code>{}</code>\n\
+        👁️ Vision-модель: <code>{}</code>\n\
         🌡️ Температура: <code>{}</code>\n\
         📝 Токены: <code>{}</code>\n\n\
         👁️ Vision: {}\n\
         🎤 Voice: {}\n\
-        🌐 Web: {}",
-        model, temp, tokens,
+        🌐 Web: {}\n\
+        🛠️ Tools: {}\n\
+        ⏭️ Автоотмена: {}\n\
+        ✏️ Переписывание запросов: {}\n\
+        🧭 ANN-индекс: {} ({} чатов, {} векторов)",
+        model, vision_model, temp, tokens,
         if vision { "✅" } else { "❌" },
         if voice { "✅" } else { "❌" },
-        if web { "✅" } else { "❌" }
+        if web { "✅" } else { "❌" },
+        if tools { "✅" } else { "❌" },
+        if auto_cancel { "✅" } else { "❌" },
+        if rewrite { "✅" } else { "❌" },
+        if ann { "✅" } else { "❌" },
+        ann_chats,
+        ann_vectors
     );
     
     let kb = InlineKeyboardMarkup::new(vec![
-        vec![InlineKeyboardButton::callback("🤖 Модель", "cfg_model")],
+        vec![
+            InlineKeyboardButton::callback("🤖 Модель", "cfg_model"),
+            InlineKeyboardButton::callback("👁️ Vision-модель", "cfg_vmodel"),
+        ],
         vec![
             InlineKeyboardButton::callback("🌡️ Темп", "cfg_temp"),
             InlineKeyboardButton::callback("📝 Токены", "cfg_tokens"),
@@ -487,6 +634,15 @@ async fn edit_config_menu(bot: &Bot, chat_id: ChatId, msg_id: MessageId, state:
         ],
         vec![
             InlineKeyboardButton::callback(format!("🌐 Web {}", if web { "✅" } else { "❌" }), "cfg_toggle:web_search_enabled"),
+            InlineKeyboardButton::callback(format!("🛠️ Tools {}", if tools { "✅" } else { "❌" }), "cfg_toggle:tools_enabled"),
+        ],
+        vec![
+            InlineKeyboardButton::callback(format!("⏭️ Автоотмена {}", if auto_cancel { "✅" } else { "❌" }), "cfg_toggle:auto_cancel_enabled"),
+            InlineKeyboardButton::callback(format!("✏️ Запросы {}", if rewrite { "✅" } else { "❌" }), "cfg_toggle:query_rewrite_enabled"),
+        ],
+        vec![
+            InlineKeyboardButton::callback(format!("🧭 ANN {}", if ann { "✅" } else { "❌" }), "cfg_toggle:vector_index_enabled"),
+            InlineKeyboardButton::callback("🔁 Перестроить индекс", "cfg_ann_rebuild"),
         ],
         vec![InlineKeyboardButton::callback("🔙 Назад", "main")],
     ]);
@@ -498,25 +654,39 @@ async fn edit_config_menu(bot: &Bot, chat_id: ChatId, msg_id: MessageId, state:
     Ok(())
 }
 
-async fn edit_model_select(bot: &Bot, chat_id: ChatId, msg_id: MessageId, state: &AppState) -> ResponseResult<()> {
-    let models = state.llm_client.list_models().await.unwrap_or_default();
-    let current = db::get_config(&state.db_pool, "ollama_chat_model").await.ok().flatten()
-        .unwrap_or_else(|| state.config.ollama_chat_model.clone());
+/// Model picker; `vision` selects the vision model instead of the chat model.
+/// Only models that can do the job are listed, annotated with size/context/capabilities.
+async fn edit_model_select(bot: &Bot, chat_id: ChatId, msg_id: MessageId, state: &AppState, vision: bool) -> ResponseResult<()> {
+    let (key, default, callback, title) = if vision {
+        ("ollama_vision_model", &state.config.ollama_vision_model, "cfg_set_vmodel", "👁️ <b>Vision-модель</b>")
+    } else {
+        ("ollama_chat_model", &state.config.ollama_chat_model, "cfg_set_model", "🤖 <b>Выбор модели</b>")
+    };
+    let models = state.llm_client.list_models_info().await.unwrap_or_default();
+    let current = db::get_config(&state.db_pool, key).await.ok().flatten()
+        .unwrap_or_else(|| default.clone());
+    let suitable: Vec<_> = models.iter().filter(|m| if vision { m.can_see() } else { m.can_chat() }).collect();
     
     let mut buttons: Vec<Vec<InlineKeyboardButton>> = vec![];
     
     if models.is_empty() {
         buttons.push(vec![InlineKeyboardButton::callback("⚠️ Ollama недоступен", "config")]);
+    } else if suitable.is_empty() {
+        buttons.push(vec![InlineKeyboardButton::callback("⚠️ Нет подходящих моделей", "config")]);
     } else {
-        for model in models.iter().take(12) {
-            let label = if model == &current { format!("✅ {}", model) } else { model.clone() };
-            buttons.push(vec![InlineKeyboardButton::callback(label, format!("cfg_set_model:{}", model))]);
+        for model in suitable.iter().take(12) {
+            let summary = model.summary();
+            let mut label = if summary.is_empty() { model.name.clone() } else { format!("{} ({})", model.name, summary) };
+            if model.name == current {
+                label = format!("✅ {}", label);
+            }
+            buttons.push(vec![InlineKeyboardButton::callback(label, format!("{}:{}", callback, model.name))]);
         }
     }
     buttons.push(vec![InlineKeyboardButton::callback("🔙 Назад", "config")]);
     
     let kb = InlineKeyboardMarkup::new(buttons);
-    bot.edit_message_text(chat_id, msg_id, format!("🤖 <b>Выбор модели</b>\n\nТекущая: <code>{}</code>", current))
+    bot.edit_message_text(chat_id, msg_id, format!("{}\n\nТекущая: <code>{}</code>", title, current))
         .parse_mode(ParseMode::Html)
         .reply_markup(kb)
         .await?;
@@ -579,6 +749,9 @@ async fn edit_chat_menu(bot: &Bot, chat_id: ChatId, msg_id: MessageId, state: &A
             cooldown_seconds: 5,
             context_depth: 10,
             rag_enabled: true,
+            hybrid_search_enabled: false,
+            retention_days: 0,
+            rerank_budget_ms: 0,
         });
     
     let triggers = state.keyword_triggers.lock().await.get(&chat_id).cloned();
@@ -590,15 +763,21 @@ async fn edit_chat_menu(bot: &Bot, chat_id: ChatId, msg_id: MessageId, state: &A
         🔄 Автоответы: {}\n\
         📨 Режим: {}\n\
         🧠 RAG: {}\n\
+        🔎 Гибридный поиск: {}\n\
+        ⚖️ Реранк: {}\n\
         📚 Глубина памяти: {}\n\
         ⏱️ Cooldown: {}с\n\
-        🎯 Триггеры: {}",
+        🎯 Триггеры: {}\n\
+        🗓 Хранение: {}",
         if settings.auto_reply_enabled { "✅" } else { "❌" },
         if settings.reply_mode == "all_messages" { "все сообщения" } else { "только упоминания" },
         if settings.rag_enabled { "✅" } else { "❌" },
+        if settings.hybrid_search_enabled { "✅" } else { "❌" },
+        rerank_label(settings.rerank_budget_ms),
         settings.context_depth,
         settings.cooldown_seconds,
-        triggers_str
+        triggers_str,
+        retention_label(settings.retention_days)
     );
     
     let mut buttons = vec![
@@ -625,10 +804,21 @@ async fn edit_chat_menu(bot: &Bot, chat_id: ChatId, msg_id: MessageId, state: &A
             ),
             InlineKeyboardButton::callback("📚 Глубина", "chat_depth"),
         ],
+        vec![
+            InlineKeyboardButton::callback(
+                format!("🔎 Гибрид {}", if settings.hybrid_search_enabled { "✅" } else { "❌" }),
+                if settings.hybrid_search_enabled { "chat_hybrid_off" } else { "chat_hybrid_on" }
+            ),
+            InlineKeyboardButton::callback("⚖️ Реранк", "chat_rerank"),
+        ],
         vec![
             InlineKeyboardButton::callback("⏱️ Cooldown", "chat_cooldown"),
             InlineKeyboardButton::callback("🎯 Триггеры", "chat_triggers"),
         ],
+        vec![
+            InlineKeyboardButton::callback("📝 Сводки", "chat_summaries"),
+            InlineKeyboardButton::callback("🗓 Хранение", "chat_retention"),
+        ],
     ];
     
     if has_triggers {
@@ -645,6 +835,105 @@ async fn edit_chat_menu(bot: &Bot, chat_id: ChatId, msg_id: MessageId, state: &A
     Ok(())
 }
 
+async fn edit_summaries_menu(bot: &Bot, chat_id: ChatId, msg_id: MessageId, state: &AppState, notice: Option<&str>) -> ResponseResult<()> {
+    // Telegram caps messages at 4096 chars; each summary gets a slice of that
+    const PREVIEW_CHARS: usize = 1000;
+
+    let summaries = db::get_chat_summaries(&state.db_pool, chat_id.0, summarizer::PROMPT_SUMMARIES).await.unwrap_or_default();
+    let pending = db::count_unsummarized_messages(&state.db_pool, chat_id.0).await.unwrap_or(0);
+    let threshold = db::get_config_u32(&state.db_pool, "summary_threshold", state.config.summary_threshold).await;
+
+    let mut text = format!(
+        "📝 <b>Сводки чата</b>\n\nНе сведено: {} из {} сообщений\n",
+        pending,
+        threshold
+    );
+    if let Some(notice) = notice {
+        text.push_str(&format!("{}\n", escape_html(notice)));
+    }
+    if summaries.is_empty() {
+        text.push_str("\nСводок пока нет.");
+    }
+    for summary in summaries.iter().rev() {
+        let preview: String = summary.summary_text.chars().take(PREVIEW_CHARS).collect();
+        let ellipsis = if summary.summary_text.chars().count() > PREVIEW_CHARS { "…" } else { "" };
+        text.push_str(&format!(
+            "\n<b>{}</b> · {} сообщ.\n{}{}\n",
+            summary.created_at.format("%d.%m.%Y %H:%M"),
+            summary.message_count,
+            escape_html(&preview),
+            ellipsis
+        ));
+    }
+
+    let regen_label = if summaries.is_empty() { "▶️ Создать сейчас" } else { "🔄 Пересоздать последнюю" };
+    let kb = InlineKeyboardMarkup::new(vec![
+        vec![InlineKeyboardButton::callback(regen_label, "chat_sum_regen")],
+        vec![InlineKeyboardButton::callback("🔙 Назад", "chat")],
+    ]);
+    bot.edit_message_text(chat_id, msg_id, text)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(kb)
+        .await?;
+    Ok(())
+}
+
+async fn edit_retention_menu(bot: &Bot, chat_id: ChatId, msg_id: MessageId, state: &AppState) -> ResponseResult<()> {
+    let current = db::get_or_create_chat_settings(&state.db_pool, chat_id.0).await
+        .map(|s| s.retention_days)
+        .unwrap_or(0);
+
+    let periods = [0, 30, 90, 180, 365];
+    let buttons: Vec<Vec<InlineKeyboardButton>> = periods.chunks(3).map(|chunk| {
+        chunk.iter().map(|&days| {
+            let label = if days == current { format!("✅ {}", retention_label(days)) } else { retention_label(days) };
+            InlineKeyboardButton::callback(label, format!("chat_set_retention:{}", days))
+        }).collect()
+    }).collect();
+
+    let mut kb_buttons = buttons;
+    kb_buttons.push(vec![InlineKeyboardButton::callback("🔙 Назад", "chat")]);
+
+    let kb = InlineKeyboardMarkup::new(kb_buttons);
+    bot.edit_message_text(chat_id, msg_id, format!(
+        "🗓 <b>Хранение сообщений</b>\n\nТекущее: {}\n\nСообщения старше срока удаляются вместе с памятью раз в час.",
+        retention_label(current)
+    ))
+        .parse_mode(ParseMode::Html)
+        .reply_markup(kb)
+        .await?;
+    Ok(())
+}
+
+async fn edit_rerank_menu(bot: &Bot, chat_id: ChatId, msg_id: MessageId, state: &AppState) -> ResponseResult<()> {
+    let current = db::get_or_create_chat_settings(&state.db_pool, chat_id.0).await
+        .map(|s| s.rerank_budget_ms)
+        .unwrap_or(0);
+
+    let budgets = [0, 1000, 2000, 5000];
+    let buttons: Vec<Vec<InlineKeyboardButton>> = budgets.chunks(2).map(|chunk| {
+        chunk.iter().map(|&budget_ms| {
+            let label = if budget_ms == current { format!("✅ {}", rerank_label(budget_ms)) } else { rerank_label(budget_ms) };
+            InlineKeyboardButton::callback(label, format!("chat_set_rerank:{}", budget_ms))
+        }).collect()
+    }).collect();
+
+    let mut kb_buttons = buttons;
+    kb_buttons.push(vec![InlineKeyboardButton::callback("🔙 Назад", "chat")]);
+
+    let kb = InlineKeyboardMarkup::new(kb_buttons);
+    bot.edit_message_text(chat_id, msg_id, format!(
+        "⚖️ <b>Реранк воспоминаний</b>\n\nБюджет: {}\n\n\
+        Найденные воспоминания оценивает LLM, в промпт идут самые относящиеся к делу. \
+        Если оценка не укладывается в бюджет, используется обычный порядок.",
+        rerank_label(current)
+    ))
+        .parse_mode(ParseMode::Html)
+        .reply_markup(kb)
+        .await?;
+    Ok(())
+}
+
 async fn edit_cooldown_menu(bot: &Bot, chat_id: ChatId, msg_id: MessageId) -> ResponseResult<()> {
     let cooldowns = ["0", "3", "5", "10", "30", "60", "120"];
     let buttons: Vec<Vec<InlineKeyboardButton>> = cooldowns.chunks(4).map(|chunk| {
@@ -666,7 +955,7 @@ async fn edit_cooldown_menu(bot: &Bot, chat_id: ChatId, msg_id: MessageId) -> Re
 
 async fn edit_memory_depth_menu(bot: &Bot, chat_id: ChatId, msg_id: MessageId, state: &AppState) -> ResponseResult<()> {
     let settings = db::get_or_create_chat_settings(&state.db_pool, chat_id.0).await
-        .unwrap_or(db::ChatSettings { chat_id: chat_id.0, auto_reply_enabled: true, reply_mode: "mention_only".into(), cooldown_seconds: 5, context_depth: 10, rag_enabled: true });
+        .unwrap_or(db::ChatSettings { chat_id: chat_id.0, auto_reply_enabled: true, reply_mode: "mention_only".into(), cooldown_seconds: 5, context_depth: 10, rag_enabled: true, hybrid_search_enabled: false, retention_days: 0, rerank_budget_ms: 0 });
     let current = settings.context_depth;
     
     let depths = ["5", "10", "15", "20", "30", "50"];
@@ -749,19 +1038,20 @@ async fn edit_status(bot: &Bot, chat_id: ChatId, msg_id: MessageId, state: &AppS
         • Среднее время: {}мс\n\n\
         <b>Этот чат:</b>\n\
         • Сообщений: {}\n\
-        • RAG чанков: {}",
+        • RAG чанков: {}{}",
         if ollama_ok { "🟢" } else { "🔴" },
         if db_ok { "🟢" } else { "🔴" },
         model,
         persona,
-        state.llm_semaphore.available_permits(),
+        state.llm_queue.available(),
         state.config.max_concurrent_llm_requests.unwrap_or(3),
         stats.total_requests,
         stats.successful_requests,
         stats.failed_requests,
         stats.avg_response_time_ms,
         msg_count,
-        memory_count
+        memory_count,
+        super::commands::format_endpoints_html(&state.llm_client.endpoint_statuses())
     );
     
     let kb = InlineKeyboardMarkup::new(vec![
@@ -1,9 +1,24 @@
 use crate::db;
+use crate::llm::cancel::CancelReason;
+use crate::llm::pool::{CircuitState, EndpointStatus};
+use crate::llm::queue::Priority;
+use crate::memory::jobs::{self, Job};
+use crate::memory::{facts, manage};
 use crate::state::AppState;
+use super::{escape_html, retention_label};
 use teloxide::prelude::*;
 use teloxide::types::ParseMode;
 use teloxide::net::Download;
 
+/// Chunks listed by /memory search
+const MEMORY_SEARCH_RESULTS: u32 = 10;
+
+/// Whether `msg` is /stop, possibly addressed to a bot (`/stop@name`)
+pub fn is_stop_command(msg: &Message) -> bool {
+    let cmd = msg.text().and_then(|text| text.split_whitespace().next()).unwrap_or_default();
+    cmd.split('@').next() == Some("/stop")
+}
+
 pub async fn handle_command(bot: Bot, msg: Message, state: AppState) -> ResponseResult<()> {
     let text = msg.text().unwrap_or_default();
     let chat_id = msg.chat.id;
@@ -37,7 +52,9 @@ pub async fn handle_command(bot: Bot, msg: Message, state: AppState) -> Response
         "/disable_auto_reply", "/reply_to_all", "/reply_to_mention", "/set_cooldown",
         "/menu", "/settings", "/help", "/triggers", "/keywords", "/broadcast",
         "/queue_stats", "/stats", "/models", "/export_persona", "/export_all_personas",
-        "/import_persona", "/block", "/unblock", "/security_status", "/whoami"
+        "/import_persona", "/block", "/unblock", "/security_status", "/whoami",
+        "/stop", "/why", "/memory", "/forget", "/pin", "/unpin", "/forget_me", "/retention",
+        "/backfill_embeddings"
     ];
 
     // In group chats, ignore unknown commands (they might be for other bots)
@@ -47,13 +64,19 @@ pub async fn handle_command(bot: Bot, msg: Message, state: AppState) -> Response
 
     log::info!("⚡ Command from {} ({}): {}", username, user_id.unwrap_or(0), text);
 
-    // /start и /whoami доступны всем
+    // /start, /whoami, /stop и /forget_me доступны всем
     if cmd == "/start" {
         return handle_start(bot, msg, &state).await;
     }
     if cmd == "/whoami" {
         return handle_whoami(bot, msg, &state).await;
     }
+    if cmd == "/stop" {
+        return handle_stop(bot, msg, &state).await;
+    }
+    if cmd == "/forget_me" {
+        return handle_forget_me(bot, msg).await;
+    }
 
     // Остальные команды только для владельца
     if user_id != Some(state.config.owner_id) {
@@ -93,6 +116,13 @@ pub async fn handle_command(bot: Bot, msg: Message, state: AppState) -> Response
         "/broadcast" => handle_broadcast(bot, msg, &state).await,
         "/queue_stats" | "/stats" => handle_queue_stats(bot, msg, &state).await,
         "/models" => handle_list_models(bot, msg, &state).await,
+        "/why" => handle_why(bot, msg, &state).await,
+        "/memory" => handle_memory(bot, msg, &state).await,
+        "/forget" => handle_forget(bot, msg, &state).await,
+        "/pin" => handle_pin(bot, msg, &state, true).await,
+        "/unpin" => handle_pin(bot, msg, &state, false).await,
+        "/retention" => handle_retention(bot, msg, &state).await,
+        "/backfill_embeddings" => handle_backfill_embeddings(bot, msg, &state).await,
         "/export_persona" => handle_export_persona(bot, msg, &state).await,
         "/export_all_personas" => handle_export_all_personas(bot, msg, &state).await,
         "/import_persona" => handle_import_persona(bot, msg, &state).await,
@@ -311,7 +341,7 @@ async fn handle_set_memory_depth(bot: Bot, msg: Message, state: &AppState) -> Re
     };
 
     let settings = db::get_or_create_chat_settings(&state.db_pool, chat_id.0).await
-        .unwrap_or(db::ChatSettings { chat_id: chat_id.0, auto_reply_enabled: true, reply_mode: "mention_only".into(), cooldown_seconds: 5, context_depth: 10, rag_enabled: true });
+        .unwrap_or(db::ChatSettings { chat_id: chat_id.0, auto_reply_enabled: true, reply_mode: "mention_only".into(), cooldown_seconds: 5, context_depth: 10, rag_enabled: true, hybrid_search_enabled: false, retention_days: 0, rerank_budget_ms: 0 });
 
     match db::update_rag_settings(&state.db_pool, chat_id.0, settings.rag_enabled, depth as i64).await {
         Ok(()) => { bot.send_message(chat_id, format!("✅ Глубина памяти: {}", depth)).await?; }
@@ -330,6 +360,14 @@ pub async fn handle_status(bot: Bot, msg: Message, state: &AppState) -> Response
         _ => "Не выбрана".into(),
     };
     let stats = state.queue_stats.lock().await;
+    let reembed = match db::get_latest_reembed_job(&state.db_pool).await {
+        Ok(Some(job)) => format_reembed_html(&job),
+        _ => String::new(),
+    };
+    let jobs = match db::count_jobs_by_status(&state.db_pool).await {
+        Ok(counts) => format_jobs_html(&counts),
+        Err(_) => String::new(),
+    };
 
     let text = format!(
 r#"📊 <b>Статус</b>
@@ -338,19 +376,72 @@ r#"📊 <b>Статус</b>
 <b>Персона:</b> {}
 <b>Очередь:</b> {}/{} | Запросов: {} (✅{} ❌{})
 <b>Модель:</b> {}
-<b>Температура:</b> {} | Токены: {}"#,
+<b>Температура:</b> {} | Токены: {}{}{}{}"#,
         ollama, db_ok, persona,
-        state.llm_semaphore.available_permits(),
+        state.llm_queue.available(),
         state.config.max_concurrent_llm_requests.unwrap_or(3),
         stats.total_requests, stats.successful_requests, stats.failed_requests,
         state.config.ollama_chat_model,
-        state.config.temperature, state.config.max_tokens
+        state.config.temperature, state.config.max_tokens,
+        reembed,
+        jobs,
+        format_endpoints_html(&state.llm_client.endpoint_statuses())
     );
 
     bot.send_message(chat_id, text).parse_mode(ParseMode::Html).await?;
     Ok(())
 }
 
+/// Re-embedding progress line; empty once the job is done
+fn format_reembed_html(job: &db::ReembedJob) -> String {
+    let icon = match job.status.as_str() {
+        "running" => "⏳",
+        "failed" => "❌",
+        _ => return String::new(),
+    };
+    let percent = if job.total > 0 { job.processed * 100 / job.total } else { 100 };
+    let mut text = format!(
+        "\n<b>Переэмбеддинг:</b> {} {}/{} ({}%) → <code>{}</code>",
+        icon, job.processed, job.total, percent, escape_html(&job.model)
+    );
+    if let Some(error) = &job.error {
+        text.push_str(&format!("\n<i>{}</i>", escape_html(error)));
+    }
+    text
+}
+
+/// Background job queue line; empty when the queue is empty
+fn format_jobs_html(counts: &[(String, i64)]) -> String {
+    if counts.is_empty() {
+        return String::new();
+    }
+    let count = |status: &str| counts.iter().find(|(s, _)| s == status).map_or(0, |(_, n)| *n);
+    format!(
+        "\n<b>Фоновые задачи:</b> ⏳{} ▶️{} ❌{}",
+        count("pending"), count("running"), count("failed")
+    )
+}
+
+/// "Ollama hosts" block for status messages; empty when a single host is configured
+pub fn format_endpoints_html(statuses: &[EndpointStatus]) -> String {
+    if statuses.is_empty() {
+        return String::new();
+    }
+    let mut text = String::from("\n\n<b>Узлы Ollama:</b>");
+    for s in statuses {
+        let icon = match s.circuit {
+            CircuitState::Closed if s.healthy => "🟢",
+            CircuitState::Closed | CircuitState::HalfOpen => "🟡",
+            CircuitState::Open => "🔴",
+        };
+        text.push_str(&format!(
+            "\n{} <code>{}</code> — в работе {}, запросов {} (❌{})",
+            icon, s.url, s.in_flight, s.total_requests, s.total_failures
+        ));
+    }
+    text
+}
+
 pub async fn handle_enable_auto_reply(bot: Bot, msg: Message, state: &AppState) -> ResponseResult<()> {
     let chat_id = msg.chat.id;
     match db::toggle_auto_reply_for_chat(&state.db_pool, chat_id.0, true).await {
@@ -479,19 +570,21 @@ async fn handle_broadcast(bot: Bot, msg: Message, state: &AppState) -> ResponseR
 async fn handle_queue_stats(bot: Bot, msg: Message, state: &AppState) -> ResponseResult<()> {
     let chat_id = msg.chat.id;
     let stats = state.queue_stats.lock().await.clone();
-    let available = state.llm_semaphore.available_permits();
-    let max = state.config.max_concurrent_llm_requests.unwrap_or(3);
+    let available = state.llm_queue.available();
+    let max = state.llm_queue.max_concurrent();
+    let waiting = state.llm_queue.waiting();
 
     let text = format!(
 r#"📊 <b>Очередь LLM</b>
 
 Слотов: {}/{}
+Ожидают: {}
 Запросов: {}
 ✅ Успешных: {}
 ❌ Ошибок: {}
 ⏱️ Таймаутов: {}
 ⚡ Среднее время: {}мс"#,
-        available, max, stats.total_requests, stats.successful_requests,
+        available, max, waiting, stats.total_requests, stats.successful_requests,
         stats.failed_requests, stats.queue_timeouts, stats.avg_response_time_ms
     );
 
@@ -501,10 +594,13 @@ r#"📊 <b>Очередь LLM</b>
 
 async fn handle_list_models(bot: Bot, msg: Message, state: &AppState) -> ResponseResult<()> {
     let chat_id = msg.chat.id;
-    match state.llm_client.list_models().await {
+    match state.llm_client.list_models_info().await {
         Ok(models) if !models.is_empty() => {
-            let list = models.iter().map(|m| format!("• {}", m)).collect::<Vec<_>>().join("\n");
-            bot.send_message(chat_id, format!("🤖 <b>Модели:</b>\n\n{}\n\nТекущая: {}", list, state.config.ollama_chat_model))
+            let list = models.iter().map(|m| {
+                let summary = m.summary();
+                if summary.is_empty() { format!("• {}", m.name) } else { format!("• {} — {}", m.name, summary) }
+            }).collect::<Vec<_>>().join("\n");
+            bot.send_message(chat_id, format!("🤖 <b>Модели:</b>\n\n{}\n\nТекущая: {}", list, state.chat_model().await))
                 .parse_mode(ParseMode::Html).await?;
         }
         _ => { bot.send_message(chat_id, "❌ Модели не найдены.").await?; }
@@ -643,10 +739,15 @@ pub async fn send_help_message(bot: Bot, chat_id: ChatId) -> ResponseResult<()>
 <b>⚙️ Модель:</b>
 /set_model, /set_temperature, /set_max_tokens
 /models - список моделей
+/why [текст] - какие воспоминания попали в промпт и почему
 
 <b>🧠 RAG:</b>
 /enable_rag, /disable_rag
 /set_memory_depth 1-50
+/memory search запрос - найти фрагменты памяти (с ID)
+/forget ID, /pin ID, /unpin ID
+/retention дни - хранить сообщения чата N дней (0 - всегда)
+/backfill_embeddings - проиндексировать сообщения без эмбеддингов
 
 <b>💬 Чат:</b>
 /enable_auto_reply, /disable_auto_reply
@@ -661,6 +762,8 @@ pub async fn send_help_message(bot: Bot, chat_id: ChatId) -> ResponseResult<()>
 
 <b>📋 Профиль:</b>
 /whoami - что бот знает о тебе
+/stop - остановить генерацию ответа
+/forget_me - удалить всё, что бот о тебе хранит
 
 <b>🎛️ Меню:</b>
 /menu, /settings
@@ -819,6 +922,298 @@ async fn handle_start(bot: Bot, msg: Message, state: &AppState) -> ResponseResul
     Ok(())
 }
 
+/// Handle /why - show how memories were ranked for the last reply in this chat,
+/// or for the given text
+async fn handle_why(bot: Bot, msg: Message, state: &AppState) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let query = msg.text().unwrap_or_default().split_once(' ').map(|(_, q)| q.trim()).unwrap_or_default();
+
+    let trace = if query.is_empty() {
+        state.last_retrievals.lock().await.get(&chat_id).cloned()
+    } else {
+        match crate::bot::handlers::messages::rank_memories(state, chat_id, query, &[], Priority::Owner).await {
+            Ok(trace) => Some(trace),
+            Err(e) => {
+                bot.send_message(chat_id, format!("❌ {}", e)).await?;
+                return Ok(());
+            }
+        }
+    };
+    let Some(trace) = trace else {
+        bot.send_message(chat_id, "🤷 В этом чате ещё не было поиска по памяти. Используйте /why текст").await?;
+        return Ok(());
+    };
+
+    let mut text = format!(
+        "🔍 <b>Поиск по памяти</b>\n\n\
+        Запрос: <i>{}</i> ({} сек назад)\n\
+        Порог сходства: <code>{:.2}</code> • затухание: <code>{}</code>\n\
+        <i>score = сходство × затухание × важность</i>\n",
+        escape_html(&trace.query.chars().take(200).collect::<String>()),
+        trace.at.elapsed().as_secs(),
+        trace.min_score,
+        trace.decay_rate
+    );
+    for query in &trace.rewritten {
+        text.push_str(&format!("Искали как: <i>{}</i>\n", escape_html(query)));
+    }
+    if trace.hybrid {
+        text.push_str("<i>Гибридный поиск: порядок по RRF (векторы + BM25), совпадения BM25 проходят порог</i>\n");
+    }
+    if trace.rerank_budget_ms > 0 {
+        match &trace.rerank_error {
+            None => text.push_str(&format!(
+                "<i>Реранк: порядок по релевантности от LLM, порог {}</i>\n",
+                crate::memory::rerank::MIN_RELEVANCE
+            )),
+            Some(e) => text.push_str(&format!("<i>Реранк не сработал ({}), порядок по score</i>\n", escape_html(e))),
+        }
+    }
+    if trace.candidates.is_empty() {
+        text.push_str("\nПамять этого чата пуста.");
+    }
+    for (i, chunk) in trace.candidates.iter().enumerate() {
+        let preview: String = chunk.text.chars().take(120).collect();
+        let lexical = match (chunk.lexical_rank, chunk.fused) {
+            (Some(rank), Some(fused)) => format!(" • BM25 #{} • RRF {:.4}", rank, fused),
+            (None, Some(fused)) => format!(" • RRF {:.4}", fused),
+            _ => String::new(),
+        };
+        let relevance = chunk.relevance.map(|r| format!(" • LLM {:.2}", r)).unwrap_or_default();
+        text.push_str(&format!(
+            "\n{} <code>{:.3}</code> = {:.3} × {:.3} × {:.2} • {}{}{}\n{}\n",
+            if trace.is_chosen(i) { "✅" } else { "❌" },
+            chunk.score,
+            chunk.similarity,
+            chunk.decay,
+            chunk.importance,
+            chunk.sent_at.format("%d.%m.%Y"),
+            lexical,
+            relevance,
+            escape_html(&preview)
+        ));
+    }
+
+    bot.send_message(chat_id, text).parse_mode(ParseMode::Html).await?;
+    Ok(())
+}
+
+/// Handle /memory [search <query>] - memory size of this chat, or the best matching chunks with ids
+async fn handle_memory(bot: Bot, msg: Message, state: &AppState) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let args = msg.text().unwrap_or_default().split_once(' ').map(|(_, a)| a.trim()).unwrap_or_default();
+    let query = match args.split_once(' ') {
+        Some(("search", query)) => query.trim(),
+        _ if args == "search" => "",
+        _ => {
+            let count = db::get_memory_count(&state.db_pool, chat_id.0).await.unwrap_or(0);
+            bot.send_message(chat_id, format!(
+                "🧠 <b>Память чата</b>: {} фрагментов\n\n\
+                /memory search запрос — найти фрагменты\n\
+                /forget ID — удалить фрагмент\n\
+                /pin ID, /unpin ID — закрепить (важность ×{}) или открепить",
+                count,
+                db::PINNED_IMPORTANCE
+            ))
+            .parse_mode(ParseMode::Html)
+            .await?;
+            return Ok(());
+        }
+    };
+    if query.is_empty() {
+        bot.send_message(chat_id, "❌ Формат: /memory search запрос").await?;
+        return Ok(());
+    }
+
+    let chunks = match manage::search(state, chat_id.0, query, MEMORY_SEARCH_RESULTS).await {
+        Ok(chunks) => chunks,
+        Err(e) => {
+            bot.send_message(chat_id, format!("❌ {}", e)).await?;
+            return Ok(());
+        }
+    };
+    if chunks.is_empty() {
+        bot.send_message(chat_id, "🤷 Ничего не найдено.").await?;
+        return Ok(());
+    }
+
+    let mut text = format!("🔎 <b>Память по запросу</b> <i>{}</i>\n", escape_html(query));
+    for chunk in &chunks {
+        let preview: String = chunk.text.chars().take(160).collect();
+        text.push_str(&format!(
+            "\n<code>#{}</code> {}<code>{:.3}</code> • {}\n{}\n",
+            chunk.id,
+            if manage::is_pinned(chunk.importance) { "📌 " } else { "" },
+            chunk.score,
+            chunk.sent_at.format("%d.%m.%Y"),
+            escape_html(&preview)
+        ));
+    }
+    bot.send_message(chat_id, text).parse_mode(ParseMode::Html).await?;
+    Ok(())
+}
+
+/// Handle /forget <id> - delete one memory chunk
+async fn handle_forget(bot: Bot, msg: Message, state: &AppState) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let Some(chunk_id) = parse_chunk_id(&msg) else {
+        bot.send_message(chat_id, "❌ Формат: /forget ID (ID из /memory search)").await?;
+        return Ok(());
+    };
+
+    let text = match manage::forget(state, chunk_id).await {
+        Ok(Some(entry)) => format!("🗑️ Фрагмент #{} удалён из памяти чата {}.", chunk_id, entry.chat_id),
+        Ok(None) => format!("❌ Фрагмент #{} не найден.", chunk_id),
+        Err(e) => {
+            log::error!("Failed to delete memory chunk {}: {}", chunk_id, e);
+            "❌ Ошибка базы данных.".to_string()
+        }
+    };
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+/// Handle /pin <id> and /unpin <id> - change the importance of one memory chunk
+async fn handle_pin(bot: Bot, msg: Message, state: &AppState, pinned: bool) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let Some(chunk_id) = parse_chunk_id(&msg) else {
+        let command = if pinned { "/pin" } else { "/unpin" };
+        bot.send_message(chat_id, format!("❌ Формат: {} ID (ID из /memory search)", command)).await?;
+        return Ok(());
+    };
+
+    let text = match manage::set_pinned(state, chunk_id, pinned).await {
+        Ok(Some(_)) if pinned => format!("📌 Фрагмент #{} закреплён: важность {}.", chunk_id, db::PINNED_IMPORTANCE),
+        Ok(Some(_)) => format!("✅ Фрагмент #{} откреплён.", chunk_id),
+        Ok(None) => format!("❌ Фрагмент #{} не найден.", chunk_id),
+        Err(e) => {
+            log::error!("Failed to update memory chunk {}: {}", chunk_id, e);
+            "❌ Ошибка базы данных.".to_string()
+        }
+    };
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+/// Chunk id argument of a memory command; a leading `#` is allowed
+fn parse_chunk_id(msg: &Message) -> Option<i64> {
+    msg.text()?
+        .split_whitespace()
+        .nth(1)
+        .and_then(|arg| arg.trim_start_matches('#').parse().ok())
+}
+
+/// Handle /stop - abort replies being generated in this chat/thread.
+/// Users stop their own requests, the owner stops all of them.
+async fn handle_stop(bot: Bot, msg: Message, state: &AppState) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let user_id = msg.from.as_ref().map(|u| u.id.0);
+    let only_user = if user_id == Some(state.config.owner_id) { None } else { user_id };
+
+    let stopped = state.generations.cancel((chat_id, msg.thread_id), only_user, CancelReason::Stopped);
+    let text = if stopped > 0 { "⏹ Остановлено." } else { "Нечего останавливать." };
+
+    let mut req = bot.send_message(chat_id, text);
+    if let Some(tid) = msg.thread_id {
+        req = req.message_thread_id(tid);
+    }
+    req.await?;
+    Ok(())
+}
+
+/// Handle /forget_me - ask the user to confirm deleting everything stored about them.
+/// The buttons only work for that user.
+async fn handle_forget_me(bot: Bot, msg: Message) -> ResponseResult<()> {
+    use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+    let chat_id = msg.chat.id;
+    let Some(user) = msg.from.as_ref() else {
+        bot.send_message(chat_id, "❌ Не удалось определить пользователя.").await?;
+        return Ok(());
+    };
+
+    let kb = InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback("🗑️ Да, удалить", format!("forget_me:yes:{}", user.id.0)),
+        InlineKeyboardButton::callback("Отмена", format!("forget_me:no:{}", user.id.0)),
+    ]]);
+    let text = format!(
+        "⚠️ <b>{}, удалить всё, что я о тебе храню?</b>\n\n\
+        Твои сообщения, фрагменты памяти и запомненные факты будут удалены во всех чатах. \
+        Отменить это нельзя.",
+        escape_html(&user.first_name)
+    );
+    let mut req = bot.send_message(chat_id, text).parse_mode(ParseMode::Html).reply_markup(kb);
+    if let Some(tid) = msg.thread_id {
+        req = req.message_thread_id(tid);
+    }
+    req.await?;
+    Ok(())
+}
+
+/// Handle /retention [days] - show or set how long this chat's messages are kept
+async fn handle_retention(bot: Bot, msg: Message, state: &AppState) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let arg = msg.text().unwrap_or_default().split_whitespace().nth(1);
+
+    let text = match arg.map(|a| a.parse::<i64>()) {
+        None => {
+            let days = db::get_or_create_chat_settings(&state.db_pool, chat_id.0).await
+                .map(|s| s.retention_days)
+                .unwrap_or(0);
+            format!("🗓 Хранение сообщений: {}\n\nФормат: /retention дни (0 - хранить всегда)", retention_label(days))
+        }
+        Some(Ok(days)) if (0..=db::MAX_RETENTION_DAYS).contains(&days) => {
+            let _ = db::get_or_create_chat_settings(&state.db_pool, chat_id.0).await;
+            match db::set_retention_for_chat(&state.db_pool, chat_id.0, days).await {
+                Ok(()) => format!("✅ Хранение сообщений: {}", retention_label(days)),
+                Err(e) => {
+                    log::error!("Failed to set retention for chat {}: {}", chat_id, e);
+                    "❌ Ошибка базы данных.".to_string()
+                }
+            }
+        }
+        Some(_) => format!("❌ Формат: /retention дни (0 - хранить всегда, не больше {})", db::MAX_RETENTION_DAYS),
+    };
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
+/// Handle /backfill_embeddings - queue embedding of every stored message that has no chunks
+async fn handle_backfill_embeddings(bot: Bot, msg: Message, state: &AppState) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let mut after_id = 0;
+    let (mut messages, mut queued) = (0, 0);
+    loop {
+        let ids = match db::get_messages_without_chunks(&state.db_pool, after_id, jobs::BACKFILL_BATCH).await {
+            Ok(ids) => ids,
+            Err(e) => {
+                log::error!("Failed to list messages without embeddings: {}", e);
+                bot.send_message(chat_id, "❌ Ошибка базы данных.").await?;
+                return Ok(());
+            }
+        };
+        let Some(&last) = ids.last() else {
+            break;
+        };
+        after_id = last;
+        messages += ids.len();
+        let job = Job::Embed { message_ids: ids, replace: false };
+        if let Err(e) = jobs::enqueue(state, &job).await {
+            log::error!("Failed to queue embedding backfill: {}", e);
+            bot.send_message(chat_id, "❌ Ошибка базы данных.").await?;
+            return Ok(());
+        }
+        queued += 1;
+    }
+
+    let text = if messages == 0 {
+        "✅ У всех сообщений уже есть эмбеддинги.".to_string()
+    } else {
+        format!("⏳ В очередь поставлено {} сообщений ({} задач). Прогресс — в /status.", messages, queued)
+    };
+    bot.send_message(chat_id, text).await?;
+    Ok(())
+}
+
 async fn handle_cancel(bot: Bot, msg: Message, state: &AppState) -> ResponseResult<()> {
     let chat_id = msg.chat.id;
     
@@ -918,12 +1313,16 @@ async fn handle_whoami(bot: Bot, msg: Message, state: &AppState) -> ResponseResu
             messages_for_analysis
         );
         
-        match state.llm_client.generate(
-            &state.config.ollama_chat_model,
-            &analysis_prompt,
-            0.3, // Low temperature for factual analysis
-            512,
-        ).await {
+        let generated = match state.acquire_llm_permit(state.llm_priority(user.map(|u| u.id.0), msg.chat.is_private())).await {
+            Ok(_permit) => state.llm_client.generate(
+                &state.config.ollama_chat_model,
+                &analysis_prompt,
+                0.3, // Low temperature for factual analysis
+                512,
+            ).await,
+            Err(e) => Err(e),
+        };
+        match generated {
             Ok(analysis) => analysis.trim().to_string(),
             Err(e) => {
                 log::error!("Failed to generate user profile: {}", e);
@@ -933,6 +1332,20 @@ async fn handle_whoami(bot: Bot, msg: Message, state: &AppState) -> ResponseResu
     } else {
         "Недостаточно данных для анализа (нужно минимум 3 сообщения)".to_string()
     };
+
+    // Facts extracted in the background from this chat
+    let facts = db::get_user_facts(&state.db_pool, chat_id.0, user_id, facts::PROMPT_FACTS)
+        .await
+        .unwrap_or_default();
+    let facts_text = if facts.is_empty() {
+        String::new()
+    } else {
+        let lines: Vec<String> = facts
+            .iter()
+            .map(|f| format!("• {}: {}", escape_html(&f.key), escape_html(&f.fact)))
+            .collect();
+        format!("\n\n📌 <b>Запомнил в этом чате:</b>\n{}", lines.join("\n"))
+    };
     
     let response = format!(
         "📋 <b>Досье: {}</b>\n\n\
@@ -941,7 +1354,7 @@ async fn handle_whoami(bot: Bot, msg: Message, state: &AppState) -> ResponseResu
         🕐 Последний контакт: {}\n\n\
         📊 <b>Статистика:</b>\n\
         💬 Сообщений: {} | 🧠 В памяти: {} | 📍 Чатов: {}\n\n\
-        🔍 <b>Что я о тебе знаю:</b>\n{}",
+        🔍 <b>Что я о тебе знаю:</b>\n{}{}",
         user_name,
         user_id,
         first_seen,
@@ -949,7 +1362,8 @@ async fn handle_whoami(bot: Bot, msg: Message, state: &AppState) -> ResponseResu
         dossier.message_count,
         dossier.memory_count,
         dossier.chats_count,
-        profile_text
+        profile_text,
+        facts_text
     );
     
     bot.send_message(chat_id, response)
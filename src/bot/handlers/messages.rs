@@ -1,19 +1,34 @@
-use crate::db;
+use crate::db::{self, SavedMessage};
+use crate::llm::cancel::{CancelReason, CancelToken};
+use crate::llm::client::{ChatMessage, LlmError, TokenStream};
+use crate::llm::context::{ContextBudget, SpeakerFacts};
+use crate::llm::queue::Priority;
 use crate::logging;
-use crate::state::{AppState, DialogueState, PendingBatch, WizardState};
+use crate::memory::history::Turn;
+use crate::memory::jobs::{self, Job};
+use crate::memory::{chunker, facts, hybrid, index, rerank, rewrite, summarizer};
+use crate::tools;
+use crate::state::{AppState, PendingBatch, RetrievalTrace, WizardState};
 use teloxide::prelude::*;
-use teloxide::types::{ParseMode, ReplyParameters};
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode, ReplyParameters};
 use std::time::Instant;
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use futures::StreamExt;
 
-const MAX_CONTEXT_MESSAGES: usize = 20;
 const MAX_RAG_CHUNKS: u32 = 3;
 const DEFAULT_PERSONA_PROMPT: &str = "You are a helpful AI assistant.";
 const DEBOUNCE_MS: u64 = 1500; // Wait 1.5 seconds for more messages
+const STREAM_EDIT_INTERVAL_MS: u64 = 1500; // Min gap between streaming edits
+const TELEGRAM_MAX_CHARS: usize = 4096;
+const STREAM_PREVIEW_MAX_CHARS: usize = 4000; // Leaves room for the cursor and the stop notice
+const STREAM_PLACEHOLDER: &str = "…";
+const STREAM_CURSOR: &str = " ▌";
+const QUEUE_NOTICE_AFTER_MS: u64 = 3000; // Show queue position after waiting this long
 
 pub async fn handle_message(bot: Bot, msg: Message, state: AppState) -> ResponseResult<()> {
     let chat_id = msg.chat.id;
     let thread_id = msg.thread_id;
+    let priority = state.llm_priority(msg.from.as_ref().map(|u| u.id.0), msg.chat.is_private());
     
     // Check for GIF (animation), video_note (circle video), or voice message
     let media_description = if let Some(animation) = msg.animation() {
@@ -32,6 +47,7 @@ pub async fn handle_message(bot: Bot, msg: Message, state: AppState) -> Response
                 &state,
                 &animation.file.id.0,
                 msg.caption(),
+                priority,
             ).await {
                 Ok(desc) => Some(desc),
                 Err(e) => {
@@ -58,6 +74,7 @@ pub async fn handle_message(bot: Bot, msg: Message, state: AppState) -> Response
                 &bot,
                 &state,
                 &video_note.file.id.0,
+                priority,
             ).await {
                 Ok(desc) => Some(desc),
                 Err(e) => {
@@ -94,8 +111,8 @@ pub async fn handle_message(bot: Bot, msg: Message, state: AppState) -> Response
         None
     };
     
-    // Get text from message OR use media description as context
-    let text = msg.text().unwrap_or_default();
+    // Typed text or the media caption, plus the media description as context
+    let text = msg.text().or_else(|| msg.caption()).unwrap_or_default();
     
     // Build effective message content: combine text with media description
     let effective_text = match (&media_description, text.is_empty()) {
@@ -115,7 +132,7 @@ pub async fn handle_message(bot: Bot, msg: Message, state: AppState) -> Response
         return Ok(());
     }
 
-    if text.starts_with('/') {
+    if msg.text().is_some_and(|t| t.starts_with('/')) {
         // Handle commands
         return crate::bot::handlers::commands::handle_command(bot, msg, state).await;
     }
@@ -143,7 +160,7 @@ pub async fn handle_message(bot: Bot, msg: Message, state: AppState) -> Response
     }
 
     // --- Save incoming message and generate embedding ---
-    save_and_embed_message(&state, &msg).await;
+    save_and_embed_message(&state, &msg, &effective_text).await;
 
     // --- Get Active Persona ---
     let active_persona = db::get_active_persona(&state.db_pool)
@@ -178,6 +195,9 @@ pub async fn handle_message(bot: Bot, msg: Message, state: AppState) -> Response
                 cooldown_seconds: 5,
                 context_depth: 10,
                 rag_enabled: true,
+                hybrid_search_enabled: false,
+                retention_days: 0,
+                rerank_budget_ms: 0,
             }
         });
 
@@ -256,8 +276,7 @@ pub async fn handle_message(bot: Bot, msg: Message, state: AppState) -> Response
     };
 
     if !should_reply {
-        // Still save the message for context, but don't reply
-        save_and_embed_message(&state, &msg).await;
+        // Already saved above for context; just don't reply
         return Ok(());
     }
 
@@ -268,8 +287,12 @@ pub async fn handle_message(bot: Bot, msg: Message, state: AppState) -> Response
         return Ok(());
     }
 
-    // Check cooldown
-    if check_cooldown(&state, chat_id).await {
+    // A newer message from the same user replaces a reply still being generated
+    let superseding = db::get_config_bool(&state.db_pool, "auto_cancel_enabled", state.config.auto_cancel_enabled).await
+        && state.generations.cancel((chat_id, msg.thread_id), Some(user_id), CancelReason::Superseded) > 0;
+
+    // Check cooldown (a superseding message takes the cancelled reply's place)
+    if !superseding && check_cooldown(&state, chat_id).await {
         return Ok(());
     }
 
@@ -296,7 +319,38 @@ pub async fn handle_message(bot: Bot, msg: Message, state: AppState) -> Response
             return Ok(());
         }
     }
-    
+
+    let text_preview = effective_text.chars().take(50).collect::<String>();
+    logging::log_message_received(chat_id.0, &user_name, &text_preview, media_description.is_some());
+
+    // Everything above runs in the chat's update order. The reply is generated in the
+    // background so the chat's next updates (follow-ups joining the batch, superseding
+    // messages) are handled while it is being written.
+    tokio::spawn(async move {
+        if let Err(e) = generate_reply(bot, msg, state, effective_text, chat_settings, persona_prompt, persona_display_name).await {
+            logging::log_error("Reply", &e.to_string());
+        }
+    });
+
+    Ok(())
+}
+
+/// Wait out the debounce window, then answer `msg` together with the messages batched after it
+async fn generate_reply(
+    bot: Bot,
+    msg: Message,
+    state: AppState,
+    effective_text: String,
+    chat_settings: db::ChatSettings,
+    persona_prompt: String,
+    persona_display_name: Option<String>,
+) -> ResponseResult<()> {
+    let chat_id = msg.chat.id;
+    let thread_id = msg.thread_id;
+    let batch_key = (chat_id, thread_id);
+    let priority = state.llm_priority(msg.from.as_ref().map(|u| u.id.0), msg.chat.is_private());
+    let user_id = msg.from.as_ref().map(|u| u.id.0).unwrap_or(0);
+
     // Wait for debounce period
     tokio::time::sleep(std::time::Duration::from_millis(DEBOUNCE_MS)).await;
     
@@ -322,23 +376,34 @@ pub async fn handle_message(bot: Bot, msg: Message, state: AppState) -> Response
         }
     };
 
+    // Use context depth from chat settings; the latest turn includes media descriptions
+    let bot_id = state.bot_info.lock().await.as_ref().map(|info| info.id as i64);
+    let short_term_history = state.dialogues
+        .recent(&state.db_pool, (chat_id, msg.thread_id), Turn::from_message(&msg, &effective_text, bot_id), chat_settings.context_depth as usize, bot_id)
+        .await;
+
     // --- RAG & Context ---
-    let long_term_memories = if chat_settings.rag_enabled {
-        retrieve_memories(&state, chat_id, &combined_text).await
+    let (long_term_memories, summaries) = if chat_settings.rag_enabled {
+        let earlier = &short_term_history[..short_term_history.len().saturating_sub(1)];
+        (retrieve_memories(&state, chat_id, &combined_text, earlier, priority).await, load_summaries(&state, chat_id).await)
     } else {
-        vec![] // Empty vector if RAG is disabled
+        (vec![], vec![]) // Empty if RAG is disabled
+    };
+    // What the bot has learned about the person it is answering
+    let speaker_facts = match msg.from.as_ref() {
+        Some(user) if chat_settings.rag_enabled => facts::speaker_facts(&state, chat_id.0, user.id.0 as i64, &user.first_name).await,
+        _ => None,
     };
-
-    // Use context depth from chat settings
-    let short_term_history = get_and_update_history_with_depth(state.dialogues.clone(), &msg, chat_settings.context_depth as usize).await;
     
     // Get effective name for prompt (persona's display_name or bot's default name)
     let bot_name = state.get_bot_name().await;
     let effective_name = persona_display_name.as_ref()
         .unwrap_or(&bot_name);
-    let prompt = build_prompt(persona_prompt, long_term_memories, short_term_history, effective_name);
+    let chat_model = state.chat_model().await;
+    let budget = ContextBudget::new(state.context_tokens(&chat_model).await, state.config.max_tokens as usize);
+    let chat_messages = build_chat_messages(persona_prompt, summaries, long_term_memories, speaker_facts, short_term_history, effective_name, budget);
 
-    tracing::trace!(target: "llm", "Prompt for chat {}: {} chars", chat_id, prompt.len());
+    tracing::trace!(target: "llm", "Prompt for chat {}: {} messages", chat_id, chat_messages.len());
 
     // --- Show typing indicator ---
     let mut typing_action = bot.send_chat_action(chat_id, teloxide::types::ChatAction::Typing);
@@ -348,67 +413,256 @@ pub async fn handle_message(bot: Bot, msg: Message, state: AppState) -> Response
     let _ = typing_action.await;
 
     // --- Generate Response ---
-    let user_name = msg.from.as_ref().map(|u| u.first_name.clone()).unwrap_or_else(|| "User".to_string());
-    let text_preview = combined_text.chars().take(50).collect::<String>();
-    logging::log_message_received(chat_id.0, &user_name, &text_preview, media_description.is_some());
-    
-    let start_time = std::time::Instant::now();
-    match state.llm_client.generate(&state.config.ollama_chat_model, &prompt, state.config.temperature, state.config.max_tokens).await {
-        Ok(response_text) => {
-            let response_time = start_time.elapsed().as_millis();
+    // Track the generation so /stop, the ⏹ button or a newer message can cancel it
+    let generation = state.generations.start((chat_id, thread_id), Some(user_id));
+    let cancel = generation.token().clone();
+    let stop_markup = InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback("⏹ Стоп", format!("stop_gen:{}", generation.id())),
+    ]]);
 
-            // Apply human-like behavior rules
-            let processed_response = apply_human_behavior_rules(response_text, &state.config.bot_name);
+    // Post a placeholder right away and fill it in as tokens arrive
+    let mut placeholder_req = bot.send_message(chat_id, STREAM_PLACEHOLDER)
+        .reply_parameters(ReplyParameters::new(msg.id))
+        .reply_markup(stop_markup.clone());
+    if let Some(tid) = thread_id {
+        placeholder_req = placeholder_req.message_thread_id(tid);
+    }
+    let placeholder = placeholder_req.await?;
 
-            tracing::debug!(target: "messages", "Response for chat {} in {}ms", chat_id, response_time);
-            
-            // Try to send with MarkdownV2, fallback to plain text if parsing fails
-            let escaped = escape_markdown_v2(&processed_response);
-            
-            // Build send message request with thread_id and reply support
-            let mut send_req = bot.send_message(chat_id, &escaped)
-                .parse_mode(ParseMode::MarkdownV2)
-                .reply_parameters(ReplyParameters::new(msg.id));
-            if let Some(tid) = thread_id {
-                send_req = send_req.message_thread_id(tid);
-            }
-            
-            let send_result = send_req.await;
-            
-            let sent_msg = match send_result {
-                Ok(msg) => Some(msg),
-                Err(_) => {
-                    // Markdown parsing failed, try plain text
-                    tracing::debug!(target: "messages", "Markdown failed, using plain text");
-                    let mut plain_req = bot.send_message(chat_id, &processed_response)
-                        .reply_parameters(ReplyParameters::new(msg.id));
-                    if let Some(tid) = thread_id {
-                        plain_req = plain_req.message_thread_id(tid);
-                    }
-                    plain_req.await.ok()
-                }
-            };
-            
-            if let Some(sent_msg) = sent_msg {
-                save_and_embed_message(&state, &sent_msg).await;
-                add_message_to_history(state.dialogues.clone(), &sent_msg).await;
-            }
+    // Wait for an LLM slot; tell the user their position if it takes a while
+    let notice_bot = bot.clone();
+    let notice_markup = stop_markup.clone();
+    let placeholder_id = placeholder.id;
+    let permit = tokio::select! {
+        permit = state.acquire_llm_permit_with_notice(
+            priority,
+            std::time::Duration::from_millis(QUEUE_NOTICE_AFTER_MS),
+            |position| async move {
+                let _ = notice_bot.edit_message_text(chat_id, placeholder_id, format!("⏳ Вы #{} в очереди…", position))
+                    .reply_markup(notice_markup)
+                    .await;
+            },
+        ) => permit,
+        _ = cancel.cancelled() => Err(LlmError::Cancelled),
+    };
+    let permit = match permit {
+        Ok(permit) => permit,
+        Err(LlmError::Cancelled) => {
+            finish_cancelled(&bot, &placeholder, &cancel, "").await;
+            return Ok(());
         }
-        Err(e) => {
-            let response_time = start_time.elapsed().as_millis();
-            logging::log_error("LLM generation", &format!("Failed after {}ms: {}", response_time, e));
-            let mut err_req = bot.send_message(chat_id, "Не удалось сгенерировать ответ.")
-                .reply_parameters(ReplyParameters::new(msg.id));
-            if let Some(tid) = thread_id {
-                err_req = err_req.message_thread_id(tid);
+        Err(_) => {
+            bot.edit_message_text(chat_id, placeholder.id, "⏳ Слишком много запросов, попробуйте позже.").await?;
+            return Ok(());
+        }
+    };
+
+    let start_time = std::time::Instant::now();
+    let tools_enabled = db::get_config_bool(&state.db_pool, "tools_enabled", state.config.tools_enabled).await;
+    let request = async {
+        if tools_enabled {
+            // Tool rounds are not streamed; the final answer is shown in one edit
+            let web_search_enabled = db::get_config_bool(&state.db_pool, "web_search_enabled", state.config.web_search_enabled).await;
+            let registry = tools::builtin_registry(&state, chat_id, web_search_enabled);
+            state.llm_client
+                .run_tool_loop(&chat_model, chat_messages, &registry, state.config.temperature, state.config.max_tokens)
+                .await
+                .map(|text| Box::pin(futures::stream::once(async move { Ok(text) })) as TokenStream)
+        } else {
+            state.llm_client.chat_stream(&chat_model, &chat_messages, state.config.temperature, state.config.max_tokens).await
+        }
+    };
+    let stream = tokio::select! {
+        stream = request => stream,
+        _ = cancel.cancelled() => Err(LlmError::Cancelled),
+    };
+    let generated = match stream {
+        Ok(stream) => stream_into_message(&bot, &placeholder, stream, &stop_markup, &cancel).await,
+        Err(e) => Err((e, String::new())),
+    };
+
+    let response_text = match generated {
+        Ok(text) => text,
+        Err((LlmError::Cancelled, partial)) => {
+            drop(permit);
+            finish_cancelled(&bot, &placeholder, &cancel, &partial).await;
+            return Ok(());
+        }
+        Err((e, partial)) if !partial.trim().is_empty() => {
+            // Keep what was already shown rather than throwing it away
+            logging::log_error("LLM generation", &format!("Stream interrupted after {}ms: {}", start_time.elapsed().as_millis(), e));
+            partial
+        }
+        Err((e, _)) => {
+            logging::log_error("LLM generation", &format!("Failed after {}ms: {}", start_time.elapsed().as_millis(), e));
+            state.update_queue_stats(false, start_time.elapsed().as_millis() as u64).await;
+            bot.edit_message_text(chat_id, placeholder.id, "Не удалось сгенерировать ответ.").await?;
+            return Ok(());
+        }
+    };
+    drop(permit);
+    // Past this point the reply can no longer be stopped
+    drop(generation);
+
+    tracing::debug!(target: "messages", "Response for chat {} in {}ms", chat_id, start_time.elapsed().as_millis());
+    state.update_queue_stats(true, start_time.elapsed().as_millis() as u64).await;
+
+    // Apply human-like behavior rules
+    let processed_response = apply_human_behavior_rules(response_text, &state.config.bot_name);
+    if processed_response.trim().is_empty() {
+        bot.edit_message_text(chat_id, placeholder.id, "Не удалось сгенерировать ответ.").await?;
+        return Ok(());
+    }
+
+    // Final render: the placeholder takes the first part, a reply too long for one
+    // Telegram message continues in follow-ups
+    for (i, part) in split_message(&processed_response, STREAM_PREVIEW_MAX_CHARS).iter().enumerate() {
+        match render_reply_part(&bot, &placeholder, i == 0, part).await {
+            Ok(sent_msg) => save_and_embed_message(&state, &sent_msg, sent_msg.text().unwrap_or_default()).await,
+            Err(e) => {
+                logging::log_error("Reply delivery", &format!("Failed to send part {} of the reply in chat {}: {}", i + 1, chat_id, e));
+                break;
             }
-            err_req.await?;
         }
     }
 
     Ok(())
 }
 
+/// Split `text` into parts of at most `max_chars` characters,
+/// cutting at a paragraph, line or word boundary where there is one
+fn split_message(text: &str, max_chars: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut rest = text.trim();
+    while rest.chars().count() > max_chars {
+        let limit = rest.char_indices().nth(max_chars).map_or(rest.len(), |(i, _)| i);
+        let head = &rest[..limit];
+        // A boundary in the first half would leave a needlessly short part
+        let cut = head.rfind("\n\n")
+            .or_else(|| head.rfind('\n'))
+            .or_else(|| head.rfind(' '))
+            .filter(|&i| i > limit / 2)
+            .unwrap_or(limit);
+        parts.push(rest[..cut].trim_end().to_string());
+        rest = rest[cut..].trim_start();
+    }
+    if !rest.is_empty() {
+        parts.push(rest.to_string());
+    }
+    parts
+}
+
+/// Put one part of a finished reply into the placeholder (`edit`) or a follow-up message
+/// in the same thread. Tries MarkdownV2 first, falling back to plain text when parsing
+/// fails or escaping pushes the part past Telegram's limit.
+async fn render_reply_part(bot: &Bot, placeholder: &Message, edit: bool, part: &str) -> ResponseResult<Message> {
+    let escaped = escape_markdown_v2(part);
+    if escaped.chars().count() <= TELEGRAM_MAX_CHARS {
+        match send_reply_part(bot, placeholder, edit, &escaped, Some(ParseMode::MarkdownV2)).await {
+            Ok(msg) => return Ok(msg),
+            Err(e) => tracing::debug!(target: "messages", "Markdown failed, using plain text: {}", e),
+        }
+    }
+    send_reply_part(bot, placeholder, edit, part, None).await
+}
+
+async fn send_reply_part(
+    bot: &Bot,
+    placeholder: &Message,
+    edit: bool,
+    text: &str,
+    parse_mode: Option<ParseMode>,
+) -> ResponseResult<Message> {
+    let chat_id = placeholder.chat.id;
+    if edit {
+        let mut req = bot.edit_message_text(chat_id, placeholder.id, text);
+        if let Some(mode) = parse_mode {
+            req = req.parse_mode(mode);
+        }
+        return req.await;
+    }
+    let mut req = bot.send_message(chat_id, text);
+    if let Some(tid) = placeholder.thread_id {
+        req = req.message_thread_id(tid);
+    }
+    if let Some(mode) = parse_mode {
+        req = req.parse_mode(mode);
+    }
+    req.await
+}
+
+/// Consume a token stream, periodically editing `placeholder` with the text so far.
+/// On failure or cancellation returns the error together with whatever text was received.
+async fn stream_into_message(
+    bot: &Bot,
+    placeholder: &Message,
+    mut stream: TokenStream,
+    stop_markup: &InlineKeyboardMarkup,
+    cancel: &CancelToken,
+) -> Result<String, (LlmError, String)> {
+    let mut text = String::new();
+    let mut shown_len = 0;
+    let mut last_edit = Instant::now();
+
+    loop {
+        // Dropping the stream closes the connection, which stops generation on the server
+        let fragment = tokio::select! {
+            fragment = stream.next() => fragment,
+            _ = cancel.cancelled() => return Err((LlmError::Cancelled, text)),
+        };
+        let Some(fragment) = fragment else { break };
+        match fragment {
+            Ok(fragment) => text.push_str(&fragment),
+            Err(e) => return Err((e, text)),
+        }
+
+        // Telegram rate-limits edits, so only push updates every so often
+        if last_edit.elapsed().as_millis() < STREAM_EDIT_INTERVAL_MS as u128 || text.len() == shown_len {
+            continue;
+        }
+        let preview: String = text.chars().take(STREAM_PREVIEW_MAX_CHARS).collect();
+        if preview.trim().is_empty() {
+            continue;
+        }
+        // The cursor keeps the final render from matching the preview ("message is not modified")
+        // Edits drop the keyboard unless it is sent again
+        if let Err(e) = bot.edit_message_text(placeholder.chat.id, placeholder.id, format!("{}{}", preview, STREAM_CURSOR))
+            .reply_markup(stop_markup.clone())
+            .await
+        {
+            tracing::debug!(target: "messages", "Stream preview edit failed: {}", e);
+        }
+        shown_len = text.len();
+        last_edit = Instant::now();
+    }
+
+    Ok(text)
+}
+
+/// Clean up after a cancelled generation: a superseded reply disappears,
+/// a stopped one keeps whatever was generated so far
+async fn finish_cancelled(bot: &Bot, placeholder: &Message, cancel: &CancelToken, partial: &str) {
+    let chat_id = placeholder.chat.id;
+    let result = match cancel.reason() {
+        Some(CancelReason::Superseded) => {
+            tracing::debug!(target: "messages", "Reply in chat {} superseded by a newer message", chat_id);
+            bot.delete_message(chat_id, placeholder.id).await.map(|_| ())
+        }
+        _ => {
+            let partial: String = partial.chars().take(STREAM_PREVIEW_MAX_CHARS).collect();
+            let text = if partial.trim().is_empty() {
+                "⏹ Остановлено.".to_string()
+            } else {
+                format!("{}\n\n⏹ Остановлено.", partial.trim_end())
+            };
+            bot.edit_message_text(chat_id, placeholder.id, text).await.map(|_| ())
+        }
+    };
+    if let Err(e) = result {
+        tracing::debug!(target: "messages", "Failed to finalize cancelled reply: {}", e);
+    }
+}
+
 async fn check_cooldown(state: &AppState, chat_id: ChatId) -> bool {
     let mut rate_limiter = state.rate_limiter.lock().await;
     if let Some(last_request) = rate_limiter.get(&chat_id) {
@@ -424,6 +678,9 @@ async fn check_cooldown(state: &AppState, chat_id: ChatId) -> bool {
                     cooldown_seconds: 5,
                     context_depth: 10,
                     rag_enabled: true,
+                    hybrid_search_enabled: false,
+                    retention_days: 0,
+                    rerank_budget_ms: 0,
                 }
             }
         };
@@ -438,38 +695,203 @@ async fn check_cooldown(state: &AppState, chat_id: ChatId) -> bool {
     false // Not in cooldown
 }
 
-async fn retrieve_memories(state: &AppState, chat_id: ChatId, text: &str) -> Vec<String> {
-    match state.llm_client.generate_embeddings(&state.config.ollama_embedding_model, text).await {
-        Ok(embedding) => {
-            match db::find_similar_chunks(&state.db_pool, chat_id.0, &embedding, MAX_RAG_CHUNKS).await {
-                Ok(chunks) => chunks,
-                Err(e) => {
-                    tracing::warn!(target: "rag", "Failed to retrieve chunks: {}", e);
-                    vec![]
-                }
+/// Rank this chat's memories for `text` by similarity × time decay × importance,
+/// fused with full-text ranking if the chat opted into hybrid search, and reranked
+/// by an LLM within the chat's time budget if it opted into that.
+/// With query rewriting on, `history` (the turns before `text`) is used to turn `text`
+/// into standalone queries first. Candidates less similar than `rag_min_score` are kept in the trace but not used.
+pub async fn rank_memories(
+    state: &AppState,
+    chat_id: ChatId,
+    text: &str,
+    history: &[Turn],
+    priority: Priority,
+) -> Result<RetrievalTrace, String> {
+    let decay_rate = db::get_config_f64(&state.db_pool, "rag_decay_rate", state.config.rag_decay_rate).await;
+    let min_score = db::get_config_f64(&state.db_pool, "rag_min_score", state.config.rag_min_score).await;
+    let rewriting = db::get_config_bool(&state.db_pool, "query_rewrite_enabled", state.config.query_rewrite_enabled).await;
+
+    // A message that opens the conversation has nothing to resolve against
+    let mut rewritten = Vec::new();
+    if rewriting && !history.is_empty() {
+        match rewrite::rewrite(state, history, text, priority).await {
+            Ok(queries) => {
+                tracing::info!(target: "rag", "Chat {}: searching {:?} as {:?}", chat_id, text, queries);
+                rewritten = queries;
             }
+            Err(e) => tracing::debug!(target: "rag", "Query rewriting skipped in chat {}: {}", chat_id, e),
+        }
+    }
+    let queries = if rewritten.is_empty() { vec![text.to_string()] } else { rewritten.clone() };
+
+    let model = state.embedding_model().await;
+    let embeddings = state.embed_batch_with(&model, &queries).await.map_err(|e| format!("Failed to generate embeddings: {}", e))?;
+    let (hybrid, rerank_budget_ms) = db::get_or_create_chat_settings(&state.db_pool, chat_id.0).await
+        .map(|s| (s.hybrid_search_enabled, s.rerank_budget_ms))
+        .unwrap_or((false, 0));
+    // A few extra candidates show what the cutoff dropped; reranking picks from a wider set
+    let limit = if rerank_budget_ms > 0 { rerank::CANDIDATES.max(MAX_RAG_CHUNKS * 2) } else { MAX_RAG_CHUNKS * 2 };
+    let mut rankings = Vec::with_capacity(queries.len());
+    for (query, embedding) in queries.iter().zip(&embeddings) {
+        let ranking = if hybrid {
+            hybrid::find_hybrid(state, chat_id.0, &model, query, embedding, limit, decay_rate).await
+        } else {
+            index::find_similar(state, chat_id.0, &model, embedding, limit, decay_rate).await
+        }
+        .map_err(|e| format!("Failed to retrieve chunks: {}", e))?;
+        rankings.push(ranking);
+    }
+    let candidates = if rankings.len() == 1 {
+        rankings.pop().unwrap_or_default()
+    } else {
+        rewrite::merge(rankings, limit as usize)
+    };
+
+    let mut trace = RetrievalTrace {
+        query: text.to_string(),
+        rewritten,
+        min_score,
+        decay_rate,
+        max_chosen: MAX_RAG_CHUNKS as usize,
+        hybrid,
+        rerank_budget_ms,
+        rerank_error: None,
+        candidates,
+        at: Instant::now(),
+    };
+    if rerank_budget_ms > 0 {
+        // Only candidates that clear the cutoff are judged; the rest stay behind them
+        let (mut eligible, rest): (Vec<_>, Vec<_>) = std::mem::take(&mut trace.candidates)
+            .into_iter()
+            .partition(|chunk| trace.passes_cutoff(chunk));
+        let budget = std::time::Duration::from_millis(rerank_budget_ms as u64);
+        if let Err(e) = rerank::rerank(state, &queries.join("\n"), &mut eligible, priority, budget).await {
+            tracing::debug!(target: "rag", "Reranking skipped in chat {}: {}", chat_id, e);
+            trace.rerank_error = Some(e);
         }
+        eligible.extend(rest);
+        trace.candidates = eligible;
+    }
+    Ok(trace)
+}
+
+async fn retrieve_memories(state: &AppState, chat_id: ChatId, text: &str, history: &[Turn], priority: Priority) -> Vec<String> {
+    let trace = match rank_memories(state, chat_id, text, history, priority).await {
+        Ok(trace) => trace,
         Err(e) => {
-            tracing::warn!(target: "rag", "Failed to generate embeddings: {}", e);
+            tracing::warn!(target: "rag", "{}", e);
+            return vec![];
+        }
+    };
+
+    for (i, chunk) in trace.candidates.iter().enumerate() {
+        tracing::debug!(
+            target: "rag",
+            "{} chunk {}: score {:.3} (sim {:.3} × decay {:.3} × imp {:.2})",
+            if trace.is_chosen(i) { "Using" } else { "Skipping" },
+            chunk.id,
+            chunk.score,
+            chunk.similarity,
+            chunk.decay,
+            chunk.importance
+        );
+    }
+
+    let memories = match chunker::snippets(&state.db_pool, &trace.chosen_ids()).await {
+        Ok(snippets) => snippets,
+        Err(e) => {
+            tracing::warn!(target: "rag", "Failed to load neighbouring chunks: {}", e);
+            trace.chosen()
+        }
+    };
+    state.last_retrievals.lock().await.insert(chat_id, trace);
+    memories
+}
+
+/// Latest chat summaries, newest first
+async fn load_summaries(state: &AppState, chat_id: ChatId) -> Vec<String> {
+    match db::get_chat_summaries(&state.db_pool, chat_id.0, summarizer::PROMPT_SUMMARIES).await {
+        Ok(summaries) => summaries.into_iter().map(|s| s.summary_text).collect(),
+        Err(e) => {
+            tracing::warn!(target: "db", "Failed to load summaries for chat {}: {}", chat_id, e);
             vec![]
         }
     }
 }
 
-async fn save_and_embed_message(state: &AppState, msg: &Message) {
-    if let Some(text) = msg.text() {
-        let state = state.clone();
-        let msg = msg.clone();
-        let text = text.to_string();
-        tokio::spawn(async move {
-            if let Ok(db_id) = db::save_message(&state.db_pool, &msg).await {
-                if let Ok(embedding) = state.llm_client.generate_embeddings(&state.config.ollama_embedding_model, &text).await {
-                    if let Err(e) = db::save_embedding(&state.db_pool, db_id, &text, &embedding).await {
-                        tracing::warn!(target: "db", "Failed to save embedding: {}", e);
-                    }
-                }
+/// Save a message with `text` (for media: caption and transcript/description), record it
+/// in the short-term history and queue its embedding
+async fn save_and_embed_message(state: &AppState, msg: &Message, text: &str) {
+    if !text.trim().is_empty() {
+        let bot_id = state.bot_info.lock().await.as_ref().map(|info| info.id as i64);
+        state.dialogues.record((msg.chat.id, msg.thread_id), Turn::from_message(msg, text, bot_id));
+        // Saved before returning so rows follow the chat's message order, which history relies on
+        let chat_id = msg.chat.id.0;
+        match db::save_message(&state.db_pool, msg, text, content_type(msg)).await {
+            Ok(SavedMessage::New(db_id)) => {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    queue_embedding(&state, db_id).await;
+                    summarizer::maybe_summarize(&state, chat_id).await;
+                    facts::maybe_extract(&state, chat_id).await;
+                });
             }
-        });
+            Ok(SavedMessage::Edited(db_id)) => queue_embedding(state, db_id).await,
+            Ok(SavedMessage::Unchanged(_)) => {}
+            Err(e) => tracing::warn!(target: "db", "Failed to save message: {}", e),
+        }
+    }
+}
+
+/// Handle an edited message: rewrite the stored text and re-embed it. Only messages
+/// already stored are touched, and only text ones: the stored text of media also holds
+/// the transcript or description, which an edited caption doesn't carry.
+pub async fn handle_edited_message(msg: Message, state: AppState) -> ResponseResult<()> {
+    let Some(text) = msg.text() else {
+        return Ok(());
+    };
+    if text.starts_with('/') || text.trim().is_empty() {
+        return Ok(());
+    }
+
+    let chat_id = msg.chat.id.0;
+    match db::update_message_text(&state.db_pool, chat_id, msg.id.0 as i64, text, None).await {
+        Ok(Some(SavedMessage::Edited(db_id))) => {
+            tracing::debug!(target: "messages", "Message {} in chat {} edited", msg.id, chat_id);
+            let bot_id = state.bot_info.lock().await.as_ref().map(|info| info.id as i64);
+            state.dialogues.record((msg.chat.id, msg.thread_id), Turn::from_message(&msg, text, bot_id));
+            queue_embedding(&state, db_id).await;
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!(target: "db", "Failed to update edited message: {}", e),
+    }
+    Ok(())
+}
+
+/// Queue (re-)embedding of a stored message; chunks of its previous text are replaced
+async fn queue_embedding(state: &AppState, db_id: i64) {
+    let job = Job::Embed { message_ids: vec![db_id], replace: true };
+    if let Err(e) = jobs::enqueue(state, &job).await {
+        tracing::warn!(target: "db", "Failed to queue embedding of message {}: {}", db_id, e);
+    }
+}
+
+/// Kind of message content as stored in `messages.content_type`
+fn content_type(msg: &Message) -> &'static str {
+    if msg.voice().is_some() {
+        "voice"
+    } else if msg.video_note().is_some() {
+        "video_note"
+    } else if msg.animation().is_some() {
+        "animation"
+    } else if msg.photo().is_some() {
+        "photo"
+    } else if msg.video().is_some() {
+        "video"
+    } else if msg.document().is_some() {
+        "document"
+    } else {
+        "text"
     }
 }
 
@@ -597,6 +1019,7 @@ pub async fn process_animation(
     state: &AppState,
     file_id: &str,
     caption: Option<&str>,
+    priority: Priority,
 ) -> Result<String, String> {
     if !state.config.vision_enabled {
         return Err("Vision is disabled".to_string());
@@ -631,8 +1054,11 @@ pub async fn process_animation(
     };
     
     // Call vision model
+    let vision_model = state.vision_model().await;
+    let _permit = state.acquire_llm_permit(priority).await
+        .map_err(|e| format!("Vision queue: {}", e))?;
     let description = state.llm_client.generate_vision(
-        &state.config.ollama_vision_model,
+        &vision_model,
         &prompt,
         images_base64,
         state.config.temperature,
@@ -692,6 +1118,7 @@ pub async fn process_video_note(
     bot: &Bot,
     state: &AppState,
     file_id: &str,
+    priority: Priority,
 ) -> Result<String, String> {
     tracing::debug!(target: "media", "Processing video_note: {}", &file_id[..8.min(file_id.len())]);
     
@@ -739,8 +1166,11 @@ pub async fn process_video_note(
                 let prompt = "Это видеосообщение (кружок) из Telegram. Показаны 3 кадра: начало, середина и конец.\n\n\
                     Кратко опиши что видно на видео.";
                 
+                let vision_model = state.vision_model().await;
+                let _permit = state.acquire_llm_permit(priority).await
+                    .map_err(|e| format!("Vision queue: {}", e))?;
                 match state.llm_client.generate_vision(
-                    &state.config.ollama_vision_model,
+                    &vision_model,
                     prompt,
                     images_base64,
                     state.config.temperature,
@@ -767,63 +1197,40 @@ pub async fn process_video_note(
     Ok(result_parts.join("\n\n"))
 }
 
-async fn get_and_update_history_with_depth(dialogues: DialogueState, new_msg: &Message, depth: usize) -> Vec<Message> {
-    let mut dialogues = dialogues.lock().await;
-    let history = dialogues.entry(new_msg.chat.id).or_default();
-    history.push(new_msg.clone());
-    if history.len() > depth {
-        history.remove(0);
-    }
-    history.clone()
-}
-
-async fn add_message_to_history(dialogues: DialogueState, new_msg: &Message) {
-    let mut dialogues = dialogues.lock().await;
-    let history = dialogues.entry(new_msg.chat.id).or_default();
-    history.push(new_msg.clone());
-    if history.len() > MAX_CONTEXT_MESSAGES {
-        history.remove(0);
-    }
-}
-
-fn build_prompt(
+fn build_chat_messages(
     persona_prompt: String,
+    summaries: Vec<String>,
     long_term_memories: Vec<String>,
-    short_term_history: Vec<Message>,
+    speaker_facts: Option<SpeakerFacts>,
+    short_term_history: Vec<Turn>,
     bot_name: &str,
-) -> String {
+    budget: ContextBudget,
+) -> Vec<ChatMessage> {
     // Build system prompt with bot name integration
     // The bot name from config is the "real" name that the persona should use
-    let mut prompt = format!(
-        "System: Тебя зовут {name}. Это твоё имя — используй его когда представляешься или когда спрашивают как тебя зовут. \
+    let system = format!(
+        "Тебя зовут {name}. Это твоё имя — используй его когда представляешься или когда спрашивают как тебя зовут. \
         Ты откликаешься на имя \"{name}\" и его вариации. \
         Когда к тебе обращаются по имени, отвечай как будто это твоё настоящее имя.\n\n\
-        {prompt}\n\n",
+        {prompt}",
         name = bot_name,
         prompt = persona_prompt
     );
-
-    if !long_term_memories.is_empty() {
-        prompt.push_str("### Relevant Past Memories (for context):\n");
-        for memory in long_term_memories {
-            prompt.push_str(&format!("- {}\n", memory.trim()));
-        }
-        prompt.push_str("\n### Current Conversation:\n");
-    }
-
-    for msg in short_term_history {
-        let sender_name = msg.from.as_ref().map(|u| {
-            if u.is_bot {
-                bot_name.to_string()
+    // Roles come from Telegram metadata, so a user typing "Name:" can't fake a bot turn.
+    // The sender name is kept in user turns to tell group members apart.
+    let history = short_term_history
+        .into_iter()
+        .map(|turn| {
+            if turn.from_bot {
+                ChatMessage::assistant(turn.text)
             } else {
-                u.first_name.clone()
+                ChatMessage::user(format!("{}: {}", turn.name, turn.text))
             }
-        }).unwrap_or_else(|| bot_name.to_string());
-        let text = msg.text().unwrap_or("");
-        prompt.push_str(&format!("{}: {}\n", sender_name, text));
-    }
-    prompt.push_str(&format!("{}: ", bot_name));
-    prompt
+        })
+        .collect();
+
+    // Facts, memories and older history are trimmed to the model's context window
+    budget.fit(system, speaker_facts, summaries, long_term_memories, history)
 }
 
 fn apply_human_behavior_rules(response: String, bot_name: &str) -> String {
@@ -1,3 +1,31 @@
 pub mod callbacks;
 pub mod commands;
-pub mod messages;
\ No newline at end of file
+pub mod messages;
+
+use teloxide::types::{ChatId, Update, UpdateKind};
+
+/// Dispatcher key that keeps the updates of one chat in order. /stop and callback
+/// queries get none, so they run right away instead of waiting behind a slow update
+/// such as a voice message being transcribed.
+pub fn distribution_key(update: &Update) -> Option<ChatId> {
+    match &update.kind {
+        UpdateKind::CallbackQuery(_) => None,
+        UpdateKind::Message(msg) if commands::is_stop_command(msg) => None,
+        _ => update.chat().map(|chat| chat.id),
+    }
+}
+
+/// Escape text for Telegram HTML parse mode
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Retention period of a chat for display; 0 days means messages are kept forever
+pub(crate) fn retention_label(days: i64) -> String {
+    if days > 0 { format!("{} дн.", days) } else { "всегда".to_string() }
+}
+
+/// Rerank budget as shown in menus
+pub(crate) fn rerank_label(budget_ms: i64) -> String {
+    if budget_ms > 0 { format!("{} мс", budget_ms) } else { "выкл".to_string() }
+}
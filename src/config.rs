@@ -4,9 +4,24 @@ use serde::Deserialize;
 pub struct Config {
     #[serde(default = "default_ollama_url")]
     pub ollama_url: String,
+    /// Comma-separated Ollama hosts for failover/load balancing (overrides OLLAMA_URL)
+    #[serde(default)]
+    pub ollama_urls: Option<String>,
+    /// Endpoint selection for OLLAMA_URLS: "round_robin" or "least_loaded"
+    #[serde(default = "default_llm_routing")]
+    pub llm_routing: String,
     pub teloxide_token: String,
     pub database_url: String,
     pub owner_id: u64,
+    /// LLM server API: "ollama" or "openai" (llama.cpp server, vLLM, ...)
+    #[serde(default = "default_llm_backend")]
+    pub llm_backend: String,
+    /// Base URL of the OpenAI-compatible server (without /v1)
+    #[serde(default = "default_openai_base_url")]
+    pub openai_base_url: String,
+    /// Bearer token for the OpenAI-compatible server, if it requires one
+    #[serde(default)]
+    pub openai_api_key: Option<String>,
     #[serde(default = "default_ollama_chat_model")]
     pub ollama_chat_model: String,
     #[serde(default = "default_ollama_embedding_model")]
@@ -17,6 +32,9 @@ pub struct Config {
     pub temperature: f64,
     #[serde(default = "default_max_tokens")]
     pub max_tokens: u32,
+    /// Context window of the chat model in tokens; prompts are trimmed to fit it
+    #[serde(default = "default_llm_context_tokens")]
+    pub llm_context_tokens: u32,
     #[serde(default = "default_bot_name")]
     pub bot_name: String,
     /// Maximum concurrent LLM requests (queue limit)
@@ -37,6 +55,15 @@ pub struct Config {
     /// Enable web search for current information
     #[serde(default = "default_web_search_enabled")]
     pub web_search_enabled: bool,
+    /// Let the model call tools (web search, memory, chat history) while answering
+    #[serde(default = "default_tools_enabled")]
+    pub tools_enabled: bool,
+    /// Rewrite follow-up messages into standalone queries (using recent history) before memory search
+    #[serde(default = "default_query_rewrite_enabled")]
+    pub query_rewrite_enabled: bool,
+    /// Cancel a reply still being generated when the same user sends a newer message
+    #[serde(default = "default_auto_cancel_enabled")]
+    pub auto_cancel_enabled: bool,
     /// Enable voice message transcription
     #[serde(default = "default_voice_enabled")]
     pub voice_enabled: bool,
@@ -46,9 +73,24 @@ pub struct Config {
     /// Time decay rate for RAG (0.0 = no decay, 1.0 = fast decay)
     #[serde(default = "default_rag_decay_rate")]
     pub rag_decay_rate: f64,
+    /// Memories less similar to the query than this are not used, however recent or important
+    #[serde(default = "default_rag_min_score")]
+    pub rag_min_score: f64,
+    /// Search large chats through an in-memory ANN index instead of a full scan
+    #[serde(default = "default_vector_index_enabled")]
+    pub vector_index_enabled: bool,
     /// Number of messages before auto-summarization
     #[serde(default = "default_summary_threshold")]
     pub summary_threshold: u32,
+    /// New messages before facts about chat members are extracted (0 = off)
+    #[serde(default = "default_fact_threshold")]
+    pub fact_threshold: u32,
+    /// Model that judges memory relevance in chats with reranking on (defaults to the chat model)
+    #[serde(default)]
+    pub rerank_model: Option<String>,
+    /// Workers running background jobs (embeddings, summaries, fact extraction)
+    #[serde(default = "default_job_workers")]
+    pub job_workers: usize,
     /// WebApp server port
     #[serde(default = "default_webapp_port")]
     pub webapp_port: u16,
@@ -58,6 +100,18 @@ fn default_ollama_url() -> String {
     "http://host.docker.internal:11434".to_string()
 }
 
+fn default_llm_routing() -> String {
+    "round_robin".to_string()
+}
+
+fn default_llm_backend() -> String {
+    "ollama".to_string()
+}
+
+fn default_openai_base_url() -> String {
+    "http://localhost:8000".to_string()
+}
+
 fn default_ollama_chat_model() -> String {
     "gemini-3-flash-preview:cloud".to_string()
 }
@@ -78,6 +132,10 @@ fn default_max_tokens() -> u32 {
     2048
 }
 
+fn default_llm_context_tokens() -> u32 {
+    8192
+}
+
 fn default_bot_name() -> String {
     "PersonaForge".to_string()
 }
@@ -106,6 +164,18 @@ fn default_web_search_enabled() -> bool {
     true
 }
 
+fn default_tools_enabled() -> bool {
+    false
+}
+
+fn default_query_rewrite_enabled() -> bool {
+    false
+}
+
+fn default_auto_cancel_enabled() -> bool {
+    false
+}
+
 fn default_voice_enabled() -> bool {
     false
 }
@@ -118,10 +188,26 @@ fn default_rag_decay_rate() -> f64 {
     0.1 // Slow decay by default
 }
 
+fn default_rag_min_score() -> f64 {
+    0.3
+}
+
+fn default_vector_index_enabled() -> bool {
+    true
+}
+
 fn default_summary_threshold() -> u32 {
     50 // Summarize every 50 messages
 }
 
+fn default_fact_threshold() -> u32 {
+    20
+}
+
+fn default_job_workers() -> usize {
+    2
+}
+
 fn default_webapp_port() -> u16 {
     8080
 }
@@ -130,4 +216,21 @@ impl Config {
     pub fn from_env() -> Result<Self, envy::Error> {
         envy::from_env::<Config>()
     }
+
+    /// Ollama hosts to use: OLLAMA_URLS if set, otherwise OLLAMA_URL
+    pub fn ollama_urls(&self) -> Vec<String> {
+        let urls: Vec<String> = self
+            .ollama_urls
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(|u| u.trim().trim_end_matches('/').to_string())
+            .filter(|u| !u.is_empty())
+            .collect();
+        if urls.is_empty() {
+            vec![self.ollama_url.clone()]
+        } else {
+            urls
+        }
+    }
 }
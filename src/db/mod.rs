@@ -1,8 +1,9 @@
 use bincode::{deserialize, serialize};
 use chrono::{NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{sqlite::SqliteRow, FromRow, Row, SqlitePool};
+use sqlx::{sqlite::SqliteRow, FromRow, Row, SqliteConnection, SqlitePool};
 use teloxide::types::Message;
+use std::collections::{HashMap, HashSet};
 
 // --- Data Structures ---
 
@@ -27,6 +28,56 @@ pub struct MemoryChunk {
     pub created_at: Option<NaiveDateTime>,
 }
 
+/// Memory chunk ranked against a query: `score = similarity × decay × importance`
+#[derive(Debug, Clone)]
+pub struct ScoredChunk {
+    pub id: i64,
+    pub text: String,
+    pub similarity: f64,
+    pub decay: f64,
+    pub importance: f64,
+    pub score: f64,
+    pub sent_at: NaiveDateTime,
+    /// Position in the full-text ranking (1-based), in hybrid retrieval
+    pub lexical_rank: Option<usize>,
+    /// Reciprocal rank fusion score, in hybrid retrieval
+    pub fused: Option<f64>,
+    /// Relevance to the query (0–1) judged by the rerank model, when reranked
+    pub relevance: Option<f64>,
+}
+
+/// Re-embedding of old chunks after an embedding model change
+#[derive(Debug, Clone)]
+pub struct ReembedJob {
+    pub id: i64,
+    pub model: String,
+    pub status: String,
+    pub total: i64,
+    pub processed: i64,
+    /// Chunks up to this id have been handled
+    pub last_chunk_id: i64,
+    pub error: Option<String>,
+    pub started_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// Chunk waiting to be re-embedded
+#[derive(Debug, Clone)]
+pub struct PendingChunk {
+    pub id: i64,
+    pub chat_id: i64,
+    pub text: String,
+}
+
+/// Chunk returned next to a found one; `origin_id` is the found chunk
+#[derive(Debug, Clone)]
+pub struct ChunkNeighbour {
+    pub origin_id: i64,
+    pub message_id: i64,
+    pub chunk_index: i64,
+    pub text: String,
+}
+
 #[derive(Debug, FromRow, Clone)]
 pub struct ChatSummary {
     pub id: i64,
@@ -76,6 +127,9 @@ impl From<Persona> for PersonaExport {
     }
 }
 
+/// Longest retention period a chat can set (about 100 years)
+pub const MAX_RETENTION_DAYS: i64 = 36500;
+
 #[derive(Debug, FromRow, Clone)]
 pub struct ChatSettings {
     pub chat_id: i64,
@@ -84,6 +138,12 @@ pub struct ChatSettings {
     pub cooldown_seconds: i64,
     pub context_depth: i64,
     pub rag_enabled: bool,
+    /// Merge full-text (BM25) and vector rankings when retrieving memories
+    pub hybrid_search_enabled: bool,
+    /// Messages older than this many days are deleted; 0 keeps everything
+    pub retention_days: i64,
+    /// Time allowed for reranking retrieved memories with an LLM; 0 turns reranking off
+    pub rerank_budget_ms: i64,
 }
 
 // --- Public Functions: Personas ---
@@ -220,7 +280,7 @@ pub async fn delete_persona(pool: &SqlitePool, id: i64) -> Result<(), sqlx::Erro
 // --- Public Functions: Chat Settings ---
 
 pub async fn get_all_chat_settings(pool: &SqlitePool) -> Result<Vec<ChatSettings>, sqlx::Error> {
-    sqlx::query("SELECT chat_id, auto_reply_enabled, reply_mode, cooldown_seconds, context_depth, rag_enabled FROM chat_settings WHERE chat_id != 0 ORDER BY chat_id")
+    sqlx::query("SELECT chat_id, auto_reply_enabled, reply_mode, cooldown_seconds, context_depth, rag_enabled, hybrid_search_enabled, retention_days, rerank_budget_ms FROM chat_settings WHERE chat_id != 0 ORDER BY chat_id")
         .map(|row: SqliteRow| ChatSettings {
             chat_id: row.get("chat_id"),
             auto_reply_enabled: row.get("auto_reply_enabled"),
@@ -228,6 +288,9 @@ pub async fn get_all_chat_settings(pool: &SqlitePool) -> Result<Vec<ChatSettings
             cooldown_seconds: row.get("cooldown_seconds"),
             context_depth: row.get("context_depth"),
             rag_enabled: row.get("rag_enabled"),
+            hybrid_search_enabled: row.get("hybrid_search_enabled"),
+            retention_days: row.get("retention_days"),
+            rerank_budget_ms: row.get("rerank_budget_ms"),
         })
         .fetch_all(pool)
         .await
@@ -237,7 +300,7 @@ pub async fn get_or_create_chat_settings(
     pool: &SqlitePool,
     chat_id: i64,
 ) -> Result<ChatSettings, sqlx::Error> {
-    let query = "SELECT chat_id, auto_reply_enabled, reply_mode, cooldown_seconds, context_depth, rag_enabled FROM chat_settings WHERE chat_id = ?";
+    let query = "SELECT chat_id, auto_reply_enabled, reply_mode, cooldown_seconds, context_depth, rag_enabled, hybrid_search_enabled, retention_days, rerank_budget_ms FROM chat_settings WHERE chat_id = ?";
     let existing: Option<ChatSettings> = sqlx::query(query)
         .bind(chat_id)
         .map(|row: SqliteRow| ChatSettings {
@@ -247,6 +310,9 @@ pub async fn get_or_create_chat_settings(
             cooldown_seconds: row.get("cooldown_seconds"),
             context_depth: row.get("context_depth"),
             rag_enabled: row.get("rag_enabled"),
+            hybrid_search_enabled: row.get("hybrid_search_enabled"),
+            retention_days: row.get("retention_days"),
+            rerank_budget_ms: row.get("rerank_budget_ms"),
         })
         .fetch_optional(pool)
         .await?;
@@ -261,6 +327,9 @@ pub async fn get_or_create_chat_settings(
             cooldown_seconds: 5,
             context_depth: 10,
             rag_enabled: true,
+            hybrid_search_enabled: false,
+            retention_days: 0,
+            rerank_budget_ms: 0,
         };
         sqlx::query(
             r#"
@@ -280,6 +349,63 @@ pub async fn get_or_create_chat_settings(
     }
 }
 
+pub async fn toggle_hybrid_search_for_chat(
+    pool: &SqlitePool,
+    chat_id: i64,
+    enabled: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE chat_settings
+        SET hybrid_search_enabled = ?, updated_at = CURRENT_TIMESTAMP
+        WHERE chat_id = ?
+        "#,
+    )
+    .bind(enabled)
+    .bind(chat_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_retention_for_chat(
+    pool: &SqlitePool,
+    chat_id: i64,
+    days: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE chat_settings
+        SET retention_days = ?, updated_at = CURRENT_TIMESTAMP
+        WHERE chat_id = ?
+        "#,
+    )
+    .bind(days.clamp(0, MAX_RETENTION_DAYS))
+    .bind(chat_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_rerank_budget_for_chat(
+    pool: &SqlitePool,
+    chat_id: i64,
+    budget_ms: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE chat_settings
+        SET rerank_budget_ms = ?, updated_at = CURRENT_TIMESTAMP
+        WHERE chat_id = ?
+        "#,
+    )
+    .bind(budget_ms.max(0))
+    .bind(chat_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
 pub async fn update_rag_settings(
     pool: &SqlitePool,
     chat_id: i64,
@@ -385,63 +511,241 @@ pub async fn update_cooldown_for_chat(
 
 // --- Public Functions: Messages & RAG ---
 
-pub async fn save_message(pool: &SqlitePool, msg: &Message) -> Result<i64, sqlx::Error> {
+/// Outcome of saving a message that may already be stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SavedMessage {
+    /// Stored for the first time
+    New(i64),
+    /// Already stored with other text, which was replaced
+    Edited(i64),
+    /// Already stored as is
+    Unchanged(i64),
+}
+
+impl SavedMessage {
+    /// Row id in `messages`
+    pub fn id(self) -> i64 {
+        match self {
+            Self::New(id) | Self::Edited(id) | Self::Unchanged(id) => id,
+        }
+    }
+}
+
+/// Save a message with its text as the bot understood it: typed text, or for media
+/// the caption and the transcript/description, with `content_type` naming the media kind.
+/// A message is stored once per (chat_id, message_id); saving it again updates its text.
+pub async fn save_message(pool: &SqlitePool, msg: &Message, text: &str, content_type: &str) -> Result<SavedMessage, sqlx::Error> {
     let user = msg.from.as_ref();
     let user_id = user.map(|u| u.id.0 as i64);
     let username = user.map(|u| u.full_name());
-    let text = msg.text();
     let sent_at = chrono::DateTime::from_timestamp(msg.date.timestamp(), 0)
         .unwrap()
         .naive_utc();
     
     let message_id_i64 = msg.id.0 as i64;
     let chat_id_i64 = msg.chat.id.0;
+    let thread_id = msg.thread_id.map(|t| t.0 .0 as i64);
 
-    let inserted_id = sqlx::query(
+    if let Some(saved) = update_message_text(pool, chat_id_i64, message_id_i64, text, Some(content_type)).await? {
+        return Ok(saved);
+    }
+
+    let inserted: Option<i64> = sqlx::query(
         r#"
-        INSERT INTO messages (message_id, chat_id, user_id, username, text, sent_at)
-        VALUES (?, ?, ?, ?, ?, ?)
+        INSERT INTO messages (message_id, chat_id, thread_id, user_id, username, text, content_type, sent_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT (chat_id, message_id) DO NOTHING
+        RETURNING id
         "#,
     )
     .bind(message_id_i64)
     .bind(chat_id_i64)
+    .bind(thread_id)
     .bind(user_id)
     .bind(username)
     .bind(text)
+    .bind(content_type)
     .bind(sent_at)
-    .execute(pool)
-    .await?
-    .last_insert_rowid();
+    .map(|row: SqliteRow| row.get("id"))
+    .fetch_optional(pool)
+    .await?;
+
+    match inserted {
+        Some(id) => Ok(SavedMessage::New(id)),
+        // Another save of the same message won the race since the lookup
+        None => update_message_text(pool, chat_id_i64, message_id_i64, text, Some(content_type))
+            .await?
+            .ok_or(sqlx::Error::RowNotFound),
+    }
+}
+
+/// Replace the text of a stored message (and its content type, if given);
+/// `None` if the message isn't stored
+pub async fn update_message_text(
+    pool: &SqlitePool,
+    chat_id: i64,
+    message_id: i64,
+    text: &str,
+    content_type: Option<&str>,
+) -> Result<Option<SavedMessage>, sqlx::Error> {
+    let existing: Option<(i64, Option<String>)> = sqlx::query("SELECT id, text FROM messages WHERE chat_id = ? AND message_id = ?")
+        .bind(chat_id)
+        .bind(message_id)
+        .map(|row: SqliteRow| (row.get("id"), row.get("text")))
+        .fetch_optional(pool)
+        .await?;
+
+    match existing {
+        None => Ok(None),
+        Some((id, old)) if old.as_deref() == Some(text) => Ok(Some(SavedMessage::Unchanged(id))),
+        Some((id, _)) => {
+            sqlx::query(
+                r#"
+                UPDATE messages
+                SET text = ?, content_type = COALESCE(?, content_type), edited_at = CURRENT_TIMESTAMP
+                WHERE id = ?
+                "#,
+            )
+            .bind(text)
+            .bind(content_type)
+            .bind(id)
+            .execute(pool)
+            .await?;
+            Ok(Some(SavedMessage::Edited(id)))
+        }
+    }
+}
 
-    Ok(inserted_id)
+/// Delete all memory chunks of a message; returns how many there were
+pub async fn delete_message_chunks(pool: &SqlitePool, message_db_id: i64) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM memory_chunks WHERE message_id = ?")
+        .bind(message_db_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
 }
 
+/// Store chunk `chunk_index` of a message, embedded by `model`, replacing the text and
+/// embedding of a chunk already stored at that position; returns its id
 pub async fn save_embedding(
     pool: &SqlitePool,
     message_db_id: i64,
+    chunk_index: usize,
     chunk_text: &str,
+    model: &str,
     embedding: &[f64],
-) -> Result<(), anyhow::Error> {
+) -> Result<i64, anyhow::Error> {
     let encoded_embedding = serialize(embedding)?;
 
-    sqlx::query(
+    let chunk_id = sqlx::query(
         r#"
-        INSERT INTO memory_chunks (message_id, chunk_text, embedding)
-        VALUES (?, ?, ?)
+        INSERT INTO memory_chunks (message_id, chunk_index, chunk_text, embedding, embedding_model, embedding_dim)
+        VALUES (?, ?, ?, ?, ?, ?)
+        ON CONFLICT (message_id, chunk_index) DO UPDATE SET
+            chunk_text = excluded.chunk_text,
+            embedding = excluded.embedding,
+            embedding_model = excluded.embedding_model,
+            embedding_dim = excluded.embedding_dim
+        RETURNING id
         "#,
     )
     .bind(message_db_id)
+    .bind(chunk_index as i64)
     .bind(chunk_text)
     .bind(encoded_embedding)
-    .execute(pool)
+    .bind(model)
+    .bind(embedding.len() as i64)
+    .map(|row: SqliteRow| row.get::<i64, _>("id"))
+    .fetch_one(pool)
     .await?;
 
-    Ok(())
+    Ok(chunk_id)
+}
+
+/// Chunks within `radius` positions of the given ones in the same message, the given
+/// ones included, ordered by message and position
+pub async fn get_chunk_neighbours(pool: &SqlitePool, ids: &[i64], radius: i64) -> Result<Vec<ChunkNeighbour>, sqlx::Error> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let placeholders = vec!["?"; ids.len()].join(", ");
+    let sql = format!(
+        r#"
+        SELECT c.id AS origin_id, n.message_id, n.chunk_index, n.chunk_text
+        FROM memory_chunks AS c
+        JOIN memory_chunks n ON n.message_id = c.message_id
+            AND n.chunk_index BETWEEN c.chunk_index - ? AND c.chunk_index + ?
+        WHERE c.id IN ({})
+        ORDER BY n.message_id, n.chunk_index
+        "#,
+        placeholders
+    );
+    let mut query = sqlx::query(&sql).bind(radius).bind(radius);
+    for id in ids {
+        query = query.bind(id);
+    }
+    query
+        .map(|row: SqliteRow| ChunkNeighbour {
+            origin_id: row.get("origin_id"),
+            message_id: row.get("message_id"),
+            chunk_index: row.get("chunk_index"),
+            text: row.get("chunk_text"),
+        })
+        .fetch_all(pool)
+        .await
+}
+
+/// All chunk embeddings of a chat made by `model`, for building the vector index
+pub async fn get_chat_embeddings(pool: &SqlitePool, chat_id: i64, model: &str) -> Result<Vec<(i64, Vec<f64>)>, sqlx::Error> {
+    let rows: Vec<(i64, Vec<u8>)> = sqlx::query(
+        r#"
+        SELECT mc.id, mc.embedding
+        FROM memory_chunks AS mc
+        JOIN messages m ON m.id = mc.message_id
+        WHERE m.chat_id = ? AND mc.embedding IS NOT NULL AND mc.embedding_model = ?
+        "#,
+    )
+    .bind(chat_id)
+    .bind(model)
+    .map(|row: SqliteRow| (row.get("id"), row.get("embedding")))
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|(id, bytes)| match deserialize::<Vec<f64>>(&bytes) {
+            Ok(embedding) => Some((id, embedding)),
+            Err(e) => {
+                tracing::warn!(target: "db", "Failed to deserialize embedding for chunk {}: {}", id, e);
+                None
+            }
+        })
+        .collect())
+}
+
+/// Number of chunks in a chat with an embedding made by `model`
+pub async fn count_chat_embeddings(pool: &SqlitePool, chat_id: i64, model: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT COUNT(*) as cnt
+        FROM memory_chunks AS mc
+        JOIN messages m ON m.id = mc.message_id
+        WHERE m.chat_id = ? AND mc.embedding IS NOT NULL AND mc.embedding_model = ?
+        "#,
+    )
+    .bind(chat_id)
+    .bind(model)
+    .map(|row: SqliteRow| row.get("cnt"))
+    .fetch_one(pool)
+    .await
 }
 
+/// Chunks of a chat most similar to `query_embedding`, among those embedded by `model`
 pub async fn find_similar_chunks(
     pool: &SqlitePool,
     chat_id: i64,
+    model: &str,
     query_embedding: &[f64],
     limit: u32,
 ) -> Result<Vec<String>, sqlx::Error> {
@@ -450,10 +754,11 @@ pub async fn find_similar_chunks(
         SELECT mc.id, mc.message_id, mc.chunk_text, mc.embedding
         FROM memory_chunks AS mc
         JOIN messages ON messages.id = mc.message_id
-        WHERE messages.chat_id = ? AND mc.embedding IS NOT NULL
+        WHERE messages.chat_id = ? AND mc.embedding IS NOT NULL AND mc.embedding_model = ?
         "#,
     )
     .bind(chat_id)
+    .bind(model)
     .map(|row: SqliteRow| MemoryChunk {
         id: row.get("id"),
         message_id: row.get("message_id"),
@@ -471,7 +776,7 @@ pub async fn find_similar_chunks(
             if let Some(embedding_bytes) = chunk.embedding {
                 match deserialize::<Vec<f64>>(&embedding_bytes) {
                     Ok(decoded_embedding) => {
-                        let similarity = cosine_similarity(query_embedding, &decoded_embedding);
+                        let similarity = cosine_similarity(query_embedding, &decoded_embedding)?;
                         Some((similarity, chunk.chunk_text))
                     }
                     Err(e) => {
@@ -496,6 +801,43 @@ pub async fn find_similar_chunks(
     Ok(top_chunks)
 }
 
+/// Search a chat's stored messages, newest first. `query` is matched as a phrase through
+/// `messages_fts`, case-insensitively in any script, with the last word taken as a prefix.
+pub async fn search_chat_messages(
+    pool: &SqlitePool,
+    chat_id: i64,
+    query: Option<&str>,
+    limit: u32,
+) -> Result<Vec<DbMessage>, sqlx::Error> {
+    let phrase = query.map(|q| format!("\"{}\"*", q.replace('"', "\"\"")));
+
+    sqlx::query(
+        r#"
+        SELECT id, message_id, chat_id, user_id, username, text, sent_at
+        FROM messages
+        WHERE chat_id = ? AND text IS NOT NULL AND text != ''
+          AND (? IS NULL OR id IN (SELECT rowid FROM messages_fts WHERE messages_fts MATCH ?))
+        ORDER BY sent_at DESC
+        LIMIT ?
+        "#,
+    )
+    .bind(chat_id)
+    .bind(&phrase)
+    .bind(&phrase)
+    .bind(limit)
+    .map(|row: SqliteRow| DbMessage {
+        id: row.get("id"),
+        message_id: row.get("message_id"),
+        chat_id: row.get("chat_id"),
+        user_id: row.get("user_id"),
+        username: row.get("username"),
+        text: row.get("text"),
+        sent_at: row.get("sent_at"),
+    })
+    .fetch_all(pool)
+    .await
+}
+
 // --- Public Functions: Health Checks ---
 
 pub async fn check_db_health(pool: &SqlitePool) -> Result<bool, sqlx::Error> {
@@ -529,81 +871,323 @@ pub async fn get_chat_stats(pool: &SqlitePool) -> Result<Vec<(i64, i64)>, sqlx::
     Ok(stats)
 }
 
-// --- Private Helpers ---
+// --- Embedding Cache ---
 
-fn cosine_similarity(v1: &[f64], v2: &[f64]) -> f64 {
-    let dot_product = v1.iter().zip(v2).map(|(a, b)| a * b).sum::<f64>();
-    let norm_v1 = v1.iter().map(|x| x.powi(2)).sum::<f64>().sqrt();
-    let norm_v2 = v2.iter().map(|x| x.powi(2)).sum::<f64>().sqrt();
+/// Cache key of a text: hex SHA-256
+pub fn embedding_text_hash(text: &str) -> String {
+    use sha2::{Digest, Sha256};
+    hex::encode(Sha256::digest(text.as_bytes()))
+}
 
-    if norm_v1 == 0.0 || norm_v2 == 0.0 {
-        0.0
-    } else {
-        dot_product / (norm_v1 * norm_v2)
+pub async fn get_cached_embedding(
+    pool: &SqlitePool,
+    model: &str,
+    text_hash: &str,
+) -> Result<Option<Vec<f64>>, anyhow::Error> {
+    let bytes: Option<Vec<u8>> = sqlx::query("SELECT embedding FROM embedding_cache WHERE model = ? AND text_hash = ?")
+        .bind(model)
+        .bind(text_hash)
+        .map(|row: SqliteRow| row.get("embedding"))
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(match bytes {
+        Some(bytes) => Some(deserialize::<Vec<f64>>(&bytes)?),
+        None => None,
+    })
+}
+
+/// Cached embeddings by text hash, for those of `text_hashes` that have one
+pub async fn get_cached_embeddings(
+    pool: &SqlitePool,
+    model: &str,
+    text_hashes: &[String],
+) -> Result<HashMap<String, Vec<f64>>, anyhow::Error> {
+    if text_hashes.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let placeholders = vec!["?"; text_hashes.len()].join(", ");
+    let sql = format!("SELECT text_hash, embedding FROM embedding_cache WHERE model = ? AND text_hash IN ({})", placeholders);
+    let mut query = sqlx::query(&sql).bind(model);
+    for hash in text_hashes {
+        query = query.bind(hash);
+    }
+    let rows: Vec<(String, Vec<u8>)> = query
+        .map(|row: SqliteRow| (row.get("text_hash"), row.get("embedding")))
+        .fetch_all(pool)
+        .await?;
+
+    let mut embeddings = HashMap::with_capacity(rows.len());
+    for (hash, bytes) in rows {
+        embeddings.insert(hash, deserialize::<Vec<f64>>(&bytes)?);
     }
+    Ok(embeddings)
 }
 
-// --- Time-Decay RAG Functions ---
+pub async fn cache_embedding(
+    pool: &SqlitePool,
+    model: &str,
+    text_hash: &str,
+    embedding: &[f64],
+) -> Result<(), anyhow::Error> {
+    let encoded_embedding = serialize(embedding)?;
 
-/// Calculate time decay factor (exponential decay)
-/// decay_rate: how fast memories fade (0.1 = slow, 1.0 = fast)
-/// hours_old: age of the memory in hours
-fn calculate_time_decay(hours_old: f64, decay_rate: f64) -> f64 {
-    (-decay_rate * hours_old / 24.0).exp() // Decay per day
+    sqlx::query("INSERT OR REPLACE INTO embedding_cache (model, text_hash, embedding) VALUES (?, ?, ?)")
+        .bind(model)
+        .bind(text_hash)
+        .bind(encoded_embedding)
+        .execute(pool)
+        .await?;
+
+    Ok(())
 }
 
-/// Find similar chunks with time-decay weighting
-pub async fn find_similar_chunks_with_decay(
+// --- Embedding Model Versioning ---
+
+/// Mark chunks saved before models were recorded as made by `model`; returns how many
+pub async fn adopt_unversioned_chunks(pool: &SqlitePool, model: &str) -> Result<u64, anyhow::Error> {
+    const BATCH: i64 = 500;
+    let mut adopted = 0;
+    loop {
+        let rows: Vec<(i64, Vec<u8>)> = sqlx::query(
+            "SELECT id, embedding FROM memory_chunks WHERE embedding_model IS NULL AND embedding IS NOT NULL LIMIT ?",
+        )
+        .bind(BATCH)
+        .map(|row: SqliteRow| (row.get("id"), row.get("embedding")))
+        .fetch_all(pool)
+        .await?;
+        if rows.is_empty() {
+            return Ok(adopted);
+        }
+
+        let mut tx = pool.begin().await?;
+        for (id, bytes) in rows {
+            let dim = deserialize::<Vec<f64>>(&bytes).ok().map(|e| e.len() as i64);
+            sqlx::query("UPDATE memory_chunks SET embedding_model = ?, embedding_dim = ? WHERE id = ?")
+                .bind(model)
+                .bind(dim)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            adopted += 1;
+        }
+        tx.commit().await?;
+    }
+}
+
+/// Chunks with an embedding from a model other than `model`
+pub async fn count_chunks_to_reembed(pool: &SqlitePool, model: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query("SELECT COUNT(*) as cnt FROM memory_chunks WHERE embedding IS NOT NULL AND embedding_model IS NOT ?")
+        .bind(model)
+        .map(|row: SqliteRow| row.get("cnt"))
+        .fetch_one(pool)
+        .await
+}
+
+/// Next chunks after `after_id` whose embedding is not from `model`, in id order
+pub async fn get_chunks_to_reembed(
     pool: &SqlitePool,
-    chat_id: i64,
-    query_embedding: &[f64],
+    model: &str,
+    after_id: i64,
     limit: u32,
-    decay_rate: f64,
-) -> Result<Vec<String>, sqlx::Error> {
-    let chunks: Vec<(MemoryChunk, NaiveDateTime)> = sqlx::query(
+) -> Result<Vec<PendingChunk>, sqlx::Error> {
+    sqlx::query(
         r#"
-        SELECT mc.id, mc.message_id, mc.chunk_text, mc.embedding, 
-               mc.importance_score, mc.created_at, m.sent_at
+        SELECT mc.id, mc.chunk_text, m.chat_id
         FROM memory_chunks AS mc
         JOIN messages m ON m.id = mc.message_id
-        WHERE m.chat_id = ? AND mc.embedding IS NOT NULL
+        WHERE mc.id > ? AND mc.embedding IS NOT NULL AND mc.embedding_model IS NOT ?
+        ORDER BY mc.id
+        LIMIT ?
         "#,
     )
-    .bind(chat_id)
-    .map(|row: SqliteRow| {
-        let chunk = MemoryChunk {
-            id: row.get("id"),
-            message_id: row.get("message_id"),
-            chunk_text: row.get("chunk_text"),
-            embedding: row.get("embedding"),
-            importance_score: row.get::<Option<f64>, _>("importance_score"),
-            created_at: row.get::<Option<NaiveDateTime>, _>("created_at"),
-        };
-        let sent_at: NaiveDateTime = row.get("sent_at");
-        (chunk, sent_at)
+    .bind(after_id)
+    .bind(model)
+    .bind(limit)
+    .map(|row: SqliteRow| PendingChunk {
+        id: row.get("id"),
+        chat_id: row.get("chat_id"),
+        text: row.get("chunk_text"),
     })
     .fetch_all(pool)
-    .await?;
+    .await
+}
 
-    let now = Utc::now().naive_utc();
-    
-    let mut scored_chunks: Vec<(f64, String)> = chunks
-        .into_iter()
-        .filter_map(|(chunk, sent_at)| {
-            if let Some(embedding_bytes) = chunk.embedding {
-                match deserialize::<Vec<f64>>(&embedding_bytes) {
-                    Ok(decoded_embedding) => {
-                        let similarity = cosine_similarity(query_embedding, &decoded_embedding);
-                        
-                        // Calculate time decay
-                        let hours_old = (now - sent_at).num_hours() as f64;
-                        let time_decay = calculate_time_decay(hours_old, decay_rate);
-                        
-                        // Combine similarity with time decay and importance
-                        let importance = chunk.importance_score.unwrap_or(1.0);
-                        let final_score = similarity * time_decay * importance;
-                        
-                        Some((final_score, chunk.chunk_text))
+/// Replace a chunk's embedding with one from `model`
+pub async fn update_chunk_embedding(
+    pool: &SqlitePool,
+    chunk_id: i64,
+    model: &str,
+    embedding: &[f64],
+) -> Result<(), anyhow::Error> {
+    sqlx::query("UPDATE memory_chunks SET embedding = ?, embedding_model = ?, embedding_dim = ? WHERE id = ?")
+        .bind(serialize(embedding)?)
+        .bind(model)
+        .bind(embedding.len() as i64)
+        .bind(chunk_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+fn map_reembed_job(row: SqliteRow) -> ReembedJob {
+    ReembedJob {
+        id: row.get("id"),
+        model: row.get("model"),
+        status: row.get("status"),
+        total: row.get("total"),
+        processed: row.get("processed"),
+        last_chunk_id: row.get("last_chunk_id"),
+        error: row.get("error"),
+        started_at: row.get("started_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+/// Most recent re-embedding job
+pub async fn get_latest_reembed_job(pool: &SqlitePool) -> Result<Option<ReembedJob>, sqlx::Error> {
+    sqlx::query("SELECT * FROM reembed_jobs ORDER BY id DESC LIMIT 1")
+        .map(map_reembed_job)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Unfinished (running or failed) job for `model`, to resume
+pub async fn get_resumable_reembed_job(pool: &SqlitePool, model: &str) -> Result<Option<ReembedJob>, sqlx::Error> {
+    sqlx::query("SELECT * FROM reembed_jobs WHERE model = ? AND status IN ('running', 'failed') ORDER BY id DESC LIMIT 1")
+        .bind(model)
+        .map(map_reembed_job)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn create_reembed_job(pool: &SqlitePool, model: &str, total: i64) -> Result<i64, sqlx::Error> {
+    let result = sqlx::query("INSERT INTO reembed_jobs (model, total) VALUES (?, ?)")
+        .bind(model)
+        .bind(total)
+        .execute(pool)
+        .await?;
+    Ok(result.last_insert_rowid())
+}
+
+pub async fn update_reembed_progress(
+    pool: &SqlitePool,
+    job_id: i64,
+    processed: i64,
+    last_chunk_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE reembed_jobs SET status = 'running', error = NULL, processed = ?, last_chunk_id = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+    )
+    .bind(processed)
+    .bind(last_chunk_id)
+    .bind(job_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Set a job's final status (`done`, `failed` or `cancelled`)
+pub async fn finish_reembed_job(
+    pool: &SqlitePool,
+    job_id: i64,
+    status: &str,
+    error: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE reembed_jobs SET status = ?, error = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?")
+        .bind(status)
+        .bind(error)
+        .bind(job_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Cancel unfinished jobs for models other than `model`
+pub async fn cancel_other_reembed_jobs(pool: &SqlitePool, model: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE reembed_jobs SET status = 'cancelled', updated_at = CURRENT_TIMESTAMP WHERE model != ? AND status IN ('running', 'failed')",
+    )
+    .bind(model)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// --- Private Helpers ---
+
+/// `None` if the vectors have different dimensions
+fn cosine_similarity(v1: &[f64], v2: &[f64]) -> Option<f64> {
+    if v1.len() != v2.len() {
+        return None;
+    }
+    let dot_product = v1.iter().zip(v2).map(|(a, b)| a * b).sum::<f64>();
+    let norm_v1 = v1.iter().map(|x| x.powi(2)).sum::<f64>().sqrt();
+    let norm_v2 = v2.iter().map(|x| x.powi(2)).sum::<f64>().sqrt();
+
+    if norm_v1 == 0.0 || norm_v2 == 0.0 {
+        Some(0.0)
+    } else {
+        Some(dot_product / (norm_v1 * norm_v2))
+    }
+}
+
+// --- Time-Decay RAG Functions ---
+
+/// Calculate time decay factor (exponential decay)
+/// decay_rate: how fast memories fade (0.1 = slow, 1.0 = fast)
+/// hours_old: age of the memory in hours
+fn calculate_time_decay(hours_old: f64, decay_rate: f64) -> f64 {
+    (-decay_rate * hours_old / 24.0).exp() // Decay per day
+}
+
+/// Find similar chunks with time-decay and importance weighting, best first.
+/// Only chunks embedded by `model` are compared.
+pub async fn find_similar_chunks_with_decay(
+    pool: &SqlitePool,
+    chat_id: i64,
+    model: &str,
+    query_embedding: &[f64],
+    limit: u32,
+    decay_rate: f64,
+) -> Result<Vec<ScoredChunk>, sqlx::Error> {
+    let chunks: Vec<(MemoryChunk, NaiveDateTime)> = sqlx::query(
+        r#"
+        SELECT mc.id, mc.message_id, mc.chunk_text, mc.embedding, 
+               mc.importance_score, mc.created_at, m.sent_at
+        FROM memory_chunks AS mc
+        JOIN messages m ON m.id = mc.message_id
+        WHERE m.chat_id = ? AND mc.embedding IS NOT NULL AND mc.embedding_model = ?
+        "#,
+    )
+    .bind(chat_id)
+    .bind(model)
+    .map(|row: SqliteRow| {
+        let chunk = MemoryChunk {
+            id: row.get("id"),
+            message_id: row.get("message_id"),
+            chunk_text: row.get("chunk_text"),
+            embedding: row.get("embedding"),
+            importance_score: row.get::<Option<f64>, _>("importance_score"),
+            created_at: row.get::<Option<NaiveDateTime>, _>("created_at"),
+        };
+        let sent_at: NaiveDateTime = row.get("sent_at");
+        (chunk, sent_at)
+    })
+    .fetch_all(pool)
+    .await?;
+
+    let now = Utc::now().naive_utc();
+    
+    let mut scored_chunks: Vec<ScoredChunk> = chunks
+        .into_iter()
+        .filter_map(|(chunk, sent_at)| {
+            if let Some(embedding_bytes) = chunk.embedding {
+                match deserialize::<Vec<f64>>(&embedding_bytes) {
+                    Ok(decoded_embedding) => {
+                        let similarity = cosine_similarity(query_embedding, &decoded_embedding)?;
+                        Some(score_chunk(chunk.id, chunk.chunk_text, similarity, chunk.importance_score, sent_at, decay_rate, now))
                     }
                     Err(e) => {
                         tracing::warn!(target: "db", "Failed to deserialize embedding: {}", e);
@@ -616,15 +1200,165 @@ pub async fn find_similar_chunks_with_decay(
         })
         .collect();
 
-    scored_chunks.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    scored_chunks.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    scored_chunks.truncate(limit as usize);
+
+    Ok(scored_chunks)
+}
+
+/// Score chunks found by the vector index; `hits` are (chunk id, similarity).
+/// Chunks deleted since they were indexed are skipped.
+pub async fn score_chunks(
+    pool: &SqlitePool,
+    hits: &[(i64, f64)],
+    limit: u32,
+    decay_rate: f64,
+) -> Result<Vec<ScoredChunk>, sqlx::Error> {
+    if hits.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let placeholders = vec!["?"; hits.len()].join(", ");
+    let sql = format!(
+        r#"
+        SELECT mc.id, mc.chunk_text, mc.importance_score, m.sent_at
+        FROM memory_chunks AS mc
+        JOIN messages m ON m.id = mc.message_id
+        WHERE mc.id IN ({})
+        "#,
+        placeholders
+    );
+    let mut query = sqlx::query(&sql);
+    for (id, _) in hits {
+        query = query.bind(id);
+    }
+    let rows: Vec<(i64, String, Option<f64>, NaiveDateTime)> = query
+        .map(|row: SqliteRow| (row.get("id"), row.get("chunk_text"), row.get("importance_score"), row.get("sent_at")))
+        .fetch_all(pool)
+        .await?;
 
-    Ok(scored_chunks
+    let similarities: HashMap<i64, f64> = hits.iter().copied().collect();
+    let now = Utc::now().naive_utc();
+    let mut scored_chunks: Vec<ScoredChunk> = rows
         .into_iter()
-        .take(limit as usize)
-        .map(|(_, text)| text)
+        .map(|(id, text, importance, sent_at)| {
+            score_chunk(id, text, similarities[&id], importance, sent_at, decay_rate, now)
+        })
+        .collect();
+
+    scored_chunks.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    scored_chunks.truncate(limit as usize);
+
+    Ok(scored_chunks)
+}
+
+/// Chunk ids of a chat matching an FTS5 `match_query`, best BM25 first
+pub async fn search_chunks_fts(
+    pool: &SqlitePool,
+    chat_id: i64,
+    match_query: &str,
+    limit: u32,
+) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT mc.id
+        FROM memory_chunks_fts
+        JOIN memory_chunks mc ON mc.id = memory_chunks_fts.rowid
+        JOIN messages m ON m.id = mc.message_id
+        WHERE memory_chunks_fts MATCH ? AND m.chat_id = ?
+        ORDER BY bm25(memory_chunks_fts)
+        LIMIT ?
+        "#,
+    )
+    .bind(match_query)
+    .bind(chat_id)
+    .bind(limit)
+    .map(|row: SqliteRow| row.get("id"))
+    .fetch_all(pool)
+    .await
+}
+
+/// Score chunks by id against `query_embedding`. Chunks embedded by another model
+/// (or not at all) get similarity 0, so only their decay and importance show.
+pub async fn score_chunks_by_id(
+    pool: &SqlitePool,
+    ids: &[i64],
+    model: &str,
+    query_embedding: &[f64],
+    decay_rate: f64,
+) -> Result<Vec<ScoredChunk>, sqlx::Error> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let placeholders = vec!["?"; ids.len()].join(", ");
+    let sql = format!(
+        r#"
+        SELECT mc.id, mc.chunk_text, mc.embedding, mc.embedding_model, mc.importance_score, m.sent_at
+        FROM memory_chunks AS mc
+        JOIN messages m ON m.id = mc.message_id
+        WHERE mc.id IN ({})
+        "#,
+        placeholders
+    );
+    let mut query = sqlx::query(&sql);
+    for id in ids {
+        query = query.bind(id);
+    }
+    let rows = query
+        .map(|row: SqliteRow| {
+            let embedding: Option<Vec<u8>> = row.get("embedding");
+            let embedding_model: Option<String> = row.get("embedding_model");
+            let similarity = embedding
+                .filter(|_| embedding_model.as_deref() == Some(model))
+                .and_then(|bytes| deserialize::<Vec<f64>>(&bytes).ok())
+                .and_then(|embedding| cosine_similarity(query_embedding, &embedding))
+                .unwrap_or(0.0);
+            (
+                row.get::<i64, _>("id"),
+                row.get::<String, _>("chunk_text"),
+                similarity,
+                row.get::<Option<f64>, _>("importance_score"),
+                row.get::<NaiveDateTime, _>("sent_at"),
+            )
+        })
+        .fetch_all(pool)
+        .await?;
+
+    let now = Utc::now().naive_utc();
+    Ok(rows
+        .into_iter()
+        .map(|(id, text, similarity, importance, sent_at)| score_chunk(id, text, similarity, importance, sent_at, decay_rate, now))
         .collect())
 }
 
+/// Combine similarity with time decay and importance
+fn score_chunk(
+    id: i64,
+    text: String,
+    similarity: f64,
+    importance: Option<f64>,
+    sent_at: NaiveDateTime,
+    decay_rate: f64,
+    now: NaiveDateTime,
+) -> ScoredChunk {
+    let hours_old = (now - sent_at).num_hours() as f64;
+    let decay = calculate_time_decay(hours_old, decay_rate);
+    let importance = importance.unwrap_or(1.0);
+    ScoredChunk {
+        id,
+        text,
+        similarity,
+        decay,
+        importance,
+        score: similarity * decay * importance,
+        sent_at,
+        lexical_rank: None,
+        fused: None,
+        relevance: None,
+    }
+}
+
 /// Update importance score for a memory chunk
 pub async fn update_chunk_importance(
     pool: &SqlitePool,
@@ -639,6 +1373,121 @@ pub async fn update_chunk_importance(
     Ok(())
 }
 
+/// Importance given to pinned memories; unpinning restores what they had before
+pub const PINNED_IMPORTANCE: f64 = 2.0;
+
+/// Pin a chunk, keeping its current importance for later, or unpin it and restore that
+/// importance; returns the new importance, or `None` if there is no such chunk
+pub async fn set_chunk_pinned(pool: &SqlitePool, chunk_id: i64, pinned: bool) -> Result<Option<f64>, sqlx::Error> {
+    let query = if pinned {
+        sqlx::query(
+            r#"
+            UPDATE memory_chunks
+            SET importance_before_pin = COALESCE(importance_before_pin, importance_score, 1.0), importance_score = ?
+            WHERE id = ?
+            RETURNING importance_score
+            "#,
+        )
+        .bind(PINNED_IMPORTANCE)
+    } else {
+        sqlx::query(
+            r#"
+            UPDATE memory_chunks
+            SET importance_score = COALESCE(importance_before_pin, importance_score, 1.0), importance_before_pin = NULL
+            WHERE id = ?
+            RETURNING importance_score
+            "#,
+        )
+    };
+    query.bind(chunk_id).map(|row: SqliteRow| row.get("importance_score")).fetch_optional(pool).await
+}
+
+/// Stored memory chunk with its message, for browsing and editing
+#[derive(Debug, Clone)]
+pub struct MemoryEntry {
+    pub id: i64,
+    pub chat_id: i64,
+    pub text: String,
+    pub importance: f64,
+    pub username: Option<String>,
+    pub sent_at: NaiveDateTime,
+}
+
+fn map_memory_entry(row: SqliteRow) -> MemoryEntry {
+    MemoryEntry {
+        id: row.get("id"),
+        chat_id: row.get("chat_id"),
+        text: row.get("chunk_text"),
+        importance: row.get::<Option<f64>, _>("importance_score").unwrap_or(1.0),
+        username: row.get("username"),
+        sent_at: row.get("sent_at"),
+    }
+}
+
+/// A single memory chunk
+pub async fn get_memory_entry(pool: &SqlitePool, chunk_id: i64) -> Result<Option<MemoryEntry>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT mc.id, m.chat_id, mc.chunk_text, mc.importance_score, m.username, m.sent_at
+        FROM memory_chunks AS mc
+        JOIN messages m ON m.id = mc.message_id
+        WHERE mc.id = ?
+        "#,
+    )
+    .bind(chunk_id)
+    .map(map_memory_entry)
+    .fetch_optional(pool)
+    .await
+}
+
+/// A page of a chat's memory chunks, newest first
+pub async fn get_chat_memory(pool: &SqlitePool, chat_id: i64, limit: u32, offset: u32) -> Result<Vec<MemoryEntry>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT mc.id, m.chat_id, mc.chunk_text, mc.importance_score, m.username, m.sent_at
+        FROM memory_chunks AS mc
+        JOIN messages m ON m.id = mc.message_id
+        WHERE m.chat_id = ?
+        ORDER BY mc.id DESC
+        LIMIT ? OFFSET ?
+        "#,
+    )
+    .bind(chat_id)
+    .bind(limit)
+    .bind(offset)
+    .map(map_memory_entry)
+    .fetch_all(pool)
+    .await
+}
+
+/// Replace a chunk's text together with its embedding
+pub async fn update_chunk_text(
+    pool: &SqlitePool,
+    chunk_id: i64,
+    text: &str,
+    model: &str,
+    embedding: &[f64],
+) -> Result<(), anyhow::Error> {
+    sqlx::query("UPDATE memory_chunks SET chunk_text = ?, embedding = ?, embedding_model = ?, embedding_dim = ? WHERE id = ?")
+        .bind(text)
+        .bind(serialize(embedding)?)
+        .bind(model)
+        .bind(embedding.len() as i64)
+        .bind(chunk_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Delete one memory chunk; returns whether it existed
+pub async fn delete_memory_chunk(pool: &SqlitePool, chunk_id: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM memory_chunks WHERE id = ?")
+        .bind(chunk_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
 // --- Summarization Functions ---
 
 /// Save a chat summary
@@ -678,7 +1527,7 @@ pub async fn get_chat_summaries(
         SELECT id, chat_id, summary_text, messages_from, messages_to, message_count, created_at
         FROM chat_summaries
         WHERE chat_id = ?
-        ORDER BY created_at DESC
+        ORDER BY created_at DESC, id DESC
         LIMIT ?
         "#,
     )
@@ -697,6 +1546,32 @@ pub async fn get_chat_summaries(
     .await
 }
 
+/// Replace the text and message range of a summary; returns whether it still existed
+pub async fn update_chat_summary(
+    pool: &SqlitePool,
+    id: i64,
+    summary_text: &str,
+    messages_from: i64,
+    messages_to: i64,
+    message_count: i64,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE chat_summaries
+        SET summary_text = ?, messages_from = ?, messages_to = ?, message_count = ?
+        WHERE id = ?
+        "#,
+    )
+    .bind(summary_text)
+    .bind(messages_from)
+    .bind(messages_to)
+    .bind(message_count)
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
 /// Get messages for summarization (messages not yet summarized)
 pub async fn get_messages_for_summary(
     pool: &SqlitePool,
@@ -1118,3 +1993,761 @@ pub async fn get_user_memories(
     .fetch_all(pool)
     .await
 }
+
+// --- User Fact Functions ---
+
+/// Fact about a chat member, extracted from their messages
+#[derive(Debug, Clone)]
+pub struct UserFact {
+    pub id: i64,
+    pub chat_id: i64,
+    pub user_id: i64,
+    /// Short topic the fact is about ("работа", "город"); one fact per key
+    pub key: String,
+    pub fact: String,
+    pub confidence: f64,
+    /// `messages.id` of the messages the fact was taken from
+    pub source_message_ids: Vec<i64>,
+    pub updated_at: NaiveDateTime,
+}
+
+/// Facts about a user in a chat, most confident first
+pub async fn get_user_facts(pool: &SqlitePool, chat_id: i64, user_id: i64, limit: u32) -> Result<Vec<UserFact>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT id, chat_id, user_id, fact_key, fact, confidence, source_message_ids, updated_at
+        FROM user_facts
+        WHERE chat_id = ? AND user_id = ?
+        ORDER BY confidence DESC, updated_at DESC
+        LIMIT ?
+        "#,
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .bind(limit)
+    .map(|row: SqliteRow| UserFact {
+        id: row.get("id"),
+        chat_id: row.get("chat_id"),
+        user_id: row.get("user_id"),
+        key: row.get("fact_key"),
+        fact: row.get("fact"),
+        confidence: row.get("confidence"),
+        source_message_ids: serde_json::from_str(row.get::<&str, _>("source_message_ids")).unwrap_or_default(),
+        updated_at: row.get("updated_at"),
+    })
+    .fetch_all(pool)
+    .await
+}
+
+/// Insert a fact or replace the one stored under the same key. Nothing is stored
+/// (`false`) once the user has no messages left in the chat, so an extraction that
+/// was running during /forget_me cannot bring their facts back.
+pub async fn upsert_user_fact(
+    pool: &SqlitePool,
+    chat_id: i64,
+    user_id: i64,
+    key: &str,
+    fact: &str,
+    confidence: f64,
+    source_message_ids: &[i64],
+) -> Result<bool, sqlx::Error> {
+    let sources = serde_json::to_string(source_message_ids).unwrap_or_else(|_| "[]".to_string());
+    let result = sqlx::query(
+        r#"
+        INSERT INTO user_facts (chat_id, user_id, fact_key, fact, confidence, source_message_ids)
+        SELECT ?, ?, ?, ?, ?, ?
+        WHERE EXISTS (SELECT 1 FROM messages WHERE chat_id = ? AND user_id = ?)
+        ON CONFLICT (chat_id, user_id, fact_key) DO UPDATE SET
+            fact = excluded.fact,
+            confidence = excluded.confidence,
+            source_message_ids = excluded.source_message_ids,
+            updated_at = CURRENT_TIMESTAMP
+        "#,
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .bind(key)
+    .bind(fact)
+    .bind(confidence)
+    .bind(sources)
+    .bind(chat_id)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Keep only the `keep` most confident (then newest) facts about a user
+pub async fn trim_user_facts(pool: &SqlitePool, chat_id: i64, user_id: i64, keep: u32) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM user_facts
+        WHERE chat_id = ? AND user_id = ? AND id NOT IN (
+            SELECT id FROM user_facts
+            WHERE chat_id = ? AND user_id = ?
+            ORDER BY confidence DESC, updated_at DESC
+            LIMIT ?
+        )
+        "#,
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .bind(chat_id)
+    .bind(user_id)
+    .bind(keep)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Last message of a chat the fact extractor has seen (0 if none)
+pub async fn get_fact_progress(pool: &SqlitePool, chat_id: i64) -> Result<i64, sqlx::Error> {
+    let last_id: Option<i64> = sqlx::query("SELECT last_message_id FROM fact_extraction_progress WHERE chat_id = ?")
+        .bind(chat_id)
+        .map(|row: SqliteRow| row.get("last_message_id"))
+        .fetch_optional(pool)
+        .await?;
+    Ok(last_id.unwrap_or(0))
+}
+
+pub async fn set_fact_progress(pool: &SqlitePool, chat_id: i64, last_message_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO fact_extraction_progress (chat_id, last_message_id) VALUES (?, ?)
+        ON CONFLICT (chat_id) DO UPDATE SET last_message_id = excluded.last_message_id, updated_at = CURRENT_TIMESTAMP
+        "#,
+    )
+    .bind(chat_id)
+    .bind(last_message_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Latest text messages of a chat thread (`None` = outside threads), oldest first
+pub async fn get_thread_history(
+    pool: &SqlitePool,
+    chat_id: i64,
+    thread_id: Option<i64>,
+    limit: u32,
+) -> Result<Vec<DbMessage>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT * FROM (
+            SELECT id, message_id, chat_id, user_id, username, text, sent_at
+            FROM messages
+            WHERE chat_id = ? AND thread_id IS ? AND text IS NOT NULL
+            ORDER BY id DESC
+            LIMIT ?
+        ) ORDER BY id ASC
+        "#,
+    )
+    .bind(chat_id)
+    .bind(thread_id)
+    .bind(limit)
+    .map(|row: SqliteRow| DbMessage {
+        id: row.get("id"),
+        message_id: row.get("message_id"),
+        chat_id: row.get("chat_id"),
+        user_id: row.get("user_id"),
+        username: row.get("username"),
+        text: row.get("text"),
+        sent_at: row.get("sent_at"),
+    })
+    .fetch_all(pool)
+    .await
+}
+
+/// Text messages of a chat stored after `after_id`
+pub async fn count_messages_after(pool: &SqlitePool, chat_id: i64, after_id: i64) -> Result<i64, sqlx::Error> {
+    sqlx::query("SELECT COUNT(*) as cnt FROM messages WHERE chat_id = ? AND id > ? AND text IS NOT NULL")
+        .bind(chat_id)
+        .bind(after_id)
+        .map(|row: SqliteRow| row.get("cnt"))
+        .fetch_one(pool)
+        .await
+}
+
+/// Oldest text messages of a chat stored after `after_id`
+pub async fn get_messages_after(pool: &SqlitePool, chat_id: i64, after_id: i64, limit: u32) -> Result<Vec<DbMessage>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT id, message_id, chat_id, user_id, username, text, sent_at
+        FROM messages
+        WHERE chat_id = ? AND id > ? AND text IS NOT NULL
+        ORDER BY id ASC
+        LIMIT ?
+        "#,
+    )
+    .bind(chat_id)
+    .bind(after_id)
+    .bind(limit)
+    .map(|row: SqliteRow| DbMessage {
+        id: row.get("id"),
+        message_id: row.get("message_id"),
+        chat_id: row.get("chat_id"),
+        user_id: row.get("user_id"),
+        username: row.get("username"),
+        text: row.get("text"),
+        sent_at: row.get("sent_at"),
+    })
+    .fetch_all(pool)
+    .await
+}
+
+// --- Data Removal Functions ---
+
+/// What a data removal deleted
+#[derive(Debug, Default, Clone)]
+pub struct DeletedData {
+    /// Chats that lost messages or facts
+    pub chats: Vec<i64>,
+    pub messages: u64,
+    pub chunks: u64,
+    pub facts: u64,
+    pub summaries: u64,
+    /// Cached embeddings of deleted chunk texts that no remaining chunk shares
+    pub cached_embeddings: u64,
+}
+
+/// Delete everything stored about a user in every chat: their messages,
+/// the memory chunks made from them with their cached embeddings, the facts
+/// extracted about them and every summary from their first message on
+/// (later summaries build on earlier ones)
+pub async fn delete_user_data(pool: &SqlitePool, user_id: i64) -> Result<DeletedData, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let chats: Vec<i64> = sqlx::query(
+        "SELECT chat_id FROM messages WHERE user_id = ? UNION SELECT chat_id FROM user_facts WHERE user_id = ?",
+    )
+    .bind(user_id)
+    .bind(user_id)
+    .map(|row: SqliteRow| row.get("chat_id"))
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let chunk_texts: Vec<String> = sqlx::query("SELECT chunk_text FROM memory_chunks WHERE message_id IN (SELECT id FROM messages WHERE user_id = ?)")
+        .bind(user_id)
+        .map(|row: SqliteRow| row.get("chunk_text"))
+        .fetch_all(&mut *tx)
+        .await?;
+    let chunks = sqlx::query("DELETE FROM memory_chunks WHERE message_id IN (SELECT id FROM messages WHERE user_id = ?)")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    let summaries = sqlx::query(
+        r#"
+        DELETE FROM chat_summaries
+        WHERE messages_to >= (
+            SELECT MIN(m.id) FROM messages m
+            WHERE m.chat_id = chat_summaries.chat_id AND m.user_id = ?
+        )
+        "#,
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    let messages = sqlx::query("DELETE FROM messages WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    let facts = sqlx::query("DELETE FROM user_facts WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    let cached_embeddings = prune_embedding_cache(&mut tx, chunk_texts).await?;
+    tx.commit().await?;
+
+    Ok(DeletedData { chats, messages, chunks, facts, summaries, cached_embeddings })
+}
+
+/// Chats with a retention policy, as (chat_id, retention_days)
+pub async fn get_retention_policies(pool: &SqlitePool) -> Result<Vec<(i64, i64)>, sqlx::Error> {
+    sqlx::query("SELECT chat_id, retention_days FROM chat_settings WHERE retention_days > 0 ORDER BY chat_id")
+        .map(|row: SqliteRow| (row.get("chat_id"), row.get("retention_days")))
+        .fetch_all(pool)
+        .await
+}
+
+/// Delete a chat's messages sent before `cutoff` together with what was made from them:
+/// memory chunks and their cached embeddings, summaries covering any of them (later
+/// ones are kept) and facts whose source messages are all gone. Facts without recorded
+/// sources go once they were last confirmed before `cutoff`.
+pub async fn delete_messages_before(
+    pool: &SqlitePool,
+    chat_id: i64,
+    cutoff: chrono::NaiveDateTime,
+) -> Result<DeletedData, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let last_deleted: Option<i64> = sqlx::query("SELECT MAX(id) AS last_id FROM messages WHERE chat_id = ? AND sent_at < ?")
+        .bind(chat_id)
+        .bind(cutoff)
+        .map(|row: SqliteRow| row.get("last_id"))
+        .fetch_one(&mut *tx)
+        .await?;
+    let Some(last_deleted) = last_deleted else {
+        return Ok(DeletedData::default());
+    };
+
+    let chunk_texts: Vec<String> = sqlx::query(
+        "SELECT chunk_text FROM memory_chunks WHERE message_id IN (SELECT id FROM messages WHERE chat_id = ? AND sent_at < ?)",
+    )
+    .bind(chat_id)
+    .bind(cutoff)
+    .map(|row: SqliteRow| row.get("chunk_text"))
+    .fetch_all(&mut *tx)
+    .await?;
+    let chunks = sqlx::query(
+        "DELETE FROM memory_chunks WHERE message_id IN (SELECT id FROM messages WHERE chat_id = ? AND sent_at < ?)",
+    )
+    .bind(chat_id)
+    .bind(cutoff)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    let summaries = sqlx::query("DELETE FROM chat_summaries WHERE chat_id = ? AND messages_from <= ?")
+        .bind(chat_id)
+        .bind(last_deleted)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    let messages = sqlx::query("DELETE FROM messages WHERE chat_id = ? AND sent_at < ?")
+        .bind(chat_id)
+        .bind(cutoff)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    let facts = sqlx::query(
+        r#"
+        DELETE FROM user_facts
+        WHERE chat_id = ?
+          AND NOT EXISTS (
+              SELECT 1 FROM json_each(user_facts.source_message_ids) AS source
+              JOIN messages m ON m.id = source.value
+          )
+          AND (json_array_length(source_message_ids) > 0 OR updated_at < ?)
+        "#,
+    )
+    .bind(chat_id)
+    .bind(cutoff)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    let cached_embeddings = prune_embedding_cache(&mut tx, chunk_texts).await?;
+    tx.commit().await?;
+
+    Ok(DeletedData { chats: vec![chat_id], messages, chunks, facts, summaries, cached_embeddings })
+}
+
+/// Drop the cached embeddings of deleted chunk texts unless a remaining chunk still
+/// has the same text; returns how many were dropped
+async fn prune_embedding_cache(conn: &mut SqliteConnection, texts: Vec<String>) -> Result<u64, sqlx::Error> {
+    const BATCH: usize = 400;
+    let texts: Vec<String> = texts.into_iter().collect::<HashSet<_>>().into_iter().collect();
+
+    let mut pruned = 0;
+    for batch in texts.chunks(BATCH) {
+        let placeholders = vec!["?"; batch.len()].join(", ");
+        let sql = format!("SELECT DISTINCT chunk_text FROM memory_chunks WHERE chunk_text IN ({})", placeholders);
+        let mut query = sqlx::query(&sql);
+        for text in batch {
+            query = query.bind(text);
+        }
+        let kept: HashSet<String> = query
+            .map(|row: SqliteRow| row.get("chunk_text"))
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .collect();
+
+        let hashes: Vec<String> = batch.iter().filter(|text| !kept.contains(*text)).map(|text| embedding_text_hash(text)).collect();
+        if hashes.is_empty() {
+            continue;
+        }
+        let placeholders = vec!["?"; hashes.len()].join(", ");
+        let sql = format!("DELETE FROM embedding_cache WHERE text_hash IN ({})", placeholders);
+        let mut query = sqlx::query(&sql);
+        for hash in &hashes {
+            query = query.bind(hash);
+        }
+        pruned += query.execute(&mut *conn).await?.rows_affected();
+    }
+    Ok(pruned)
+}
+
+// --- Job Queue Functions ---
+
+/// A background job taken from the queue
+#[derive(Debug, Clone)]
+pub struct QueuedJob {
+    pub id: i64,
+    pub kind: String,
+    pub payload: String,
+    /// Attempts including the current one
+    pub attempts: i64,
+    pub max_attempts: i64,
+}
+
+/// Queue a job; `false` if a job with the same dedup key is already queued or running
+pub async fn enqueue_job(
+    pool: &SqlitePool,
+    kind: &str,
+    payload: &str,
+    dedup_key: Option<&str>,
+    max_attempts: u32,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO jobs (kind, payload, dedup_key, max_attempts) VALUES (?, ?, ?, ?)
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(kind)
+    .bind(payload)
+    .bind(dedup_key)
+    .bind(max_attempts)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Mark the next due job running and return it
+pub async fn claim_job(pool: &SqlitePool) -> Result<Option<QueuedJob>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE jobs
+        SET status = 'running', attempts = attempts + 1, updated_at = CURRENT_TIMESTAMP
+        WHERE id = (
+            SELECT id FROM jobs
+            WHERE status = 'pending' AND run_after <= CURRENT_TIMESTAMP
+            ORDER BY run_after, id
+            LIMIT 1
+        )
+        RETURNING id, kind, payload, attempts, max_attempts
+        "#,
+    )
+    .map(|row: SqliteRow| QueuedJob {
+        id: row.get("id"),
+        kind: row.get("kind"),
+        payload: row.get("payload"),
+        attempts: row.get("attempts"),
+        max_attempts: row.get("max_attempts"),
+    })
+    .fetch_optional(pool)
+    .await
+}
+
+/// Remove a job that completed
+pub async fn finish_job(pool: &SqlitePool, job_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM jobs WHERE id = ?")
+        .bind(job_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Record a failed attempt: the job runs again after `retry_in_secs`, or stays failed if `None`
+pub async fn fail_job(pool: &SqlitePool, job_id: i64, error: &str, retry_in_secs: Option<u64>) -> Result<(), sqlx::Error> {
+    match retry_in_secs {
+        Some(secs) => {
+            sqlx::query(
+                r#"
+                UPDATE jobs
+                SET status = 'pending', last_error = ?, run_after = datetime('now', '+' || ? || ' seconds'),
+                    updated_at = CURRENT_TIMESTAMP
+                WHERE id = ?
+                "#,
+            )
+            .bind(error)
+            .bind(secs as i64)
+            .bind(job_id)
+            .execute(pool)
+            .await?;
+        }
+        None => {
+            sqlx::query("UPDATE jobs SET status = 'failed', last_error = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?")
+                .bind(error)
+                .bind(job_id)
+                .execute(pool)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Return jobs left running by a previous process to the queue; returns how many
+pub async fn requeue_running_jobs(pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("UPDATE jobs SET status = 'pending', updated_at = CURRENT_TIMESTAMP WHERE status = 'running'")
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Number of queued jobs per status
+pub async fn count_jobs_by_status(pool: &SqlitePool) -> Result<Vec<(String, i64)>, sqlx::Error> {
+    sqlx::query("SELECT status, COUNT(*) AS count FROM jobs GROUP BY status ORDER BY status")
+        .map(|row: SqliteRow| (row.get("status"), row.get("count")))
+        .fetch_all(pool)
+        .await
+}
+
+/// Ids of non-empty messages that have no memory chunks, oldest first, after `after_id`
+pub async fn get_messages_without_chunks(pool: &SqlitePool, after_id: i64, limit: u32) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        SELECT m.id FROM messages m
+        WHERE m.id > ? AND TRIM(COALESCE(m.text, '')) != ''
+          AND NOT EXISTS (SELECT 1 FROM memory_chunks c WHERE c.message_id = m.id)
+        ORDER BY m.id
+        LIMIT ?
+        "#,
+    )
+    .bind(after_id)
+    .bind(limit)
+    .map(|row: SqliteRow| row.get("id"))
+    .fetch_all(pool)
+    .await
+}
+
+/// Messages with the given row ids, each with whether it already has memory chunks
+pub async fn get_messages_for_embedding(pool: &SqlitePool, ids: &[i64]) -> Result<Vec<(DbMessage, bool)>, sqlx::Error> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let placeholders = vec!["?"; ids.len()].join(", ");
+    let sql = format!(
+        r#"
+        SELECT m.id, m.message_id, m.chat_id, m.user_id, m.username, m.text, m.sent_at,
+               EXISTS (SELECT 1 FROM memory_chunks c WHERE c.message_id = m.id) AS has_chunks
+        FROM messages m
+        WHERE m.id IN ({})
+        ORDER BY m.id
+        "#,
+        placeholders
+    );
+    let mut query = sqlx::query(&sql);
+    for id in ids {
+        query = query.bind(id);
+    }
+    query
+        .map(|row: SqliteRow| {
+            let message = DbMessage {
+                id: row.get("id"),
+                message_id: row.get("message_id"),
+                chat_id: row.get("chat_id"),
+                user_id: row.get("user_id"),
+                username: row.get("username"),
+                text: row.get("text"),
+                sent_at: row.get("sent_at"),
+            };
+            (message, row.get("has_chunks"))
+        })
+        .fetch_all(pool)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn test_pool() -> SqlitePool {
+        // One connection, or every query would get its own empty in-memory database
+        let pool = SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        pool
+    }
+
+    /// Store a message with one memory chunk; returns its row id
+    async fn add_message(pool: &SqlitePool, chat_id: i64, message_id: i64, user_id: i64, sent_at: NaiveDateTime) -> i64 {
+        let id: i64 = sqlx::query(
+            "INSERT INTO messages (message_id, chat_id, user_id, username, text, sent_at) VALUES (?, ?, ?, 'user', 'text', ?) RETURNING id",
+        )
+        .bind(message_id)
+        .bind(chat_id)
+        .bind(user_id)
+        .bind(sent_at)
+        .map(|row: SqliteRow| row.get("id"))
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO memory_chunks (message_id, chunk_text) VALUES (?, 'text')")
+            .bind(id)
+            .execute(pool)
+            .await
+            .unwrap();
+        id
+    }
+
+    async fn count(pool: &SqlitePool, sql: &str) -> i64 {
+        sqlx::query(sql).map(|row: SqliteRow| row.get(0)).fetch_one(pool).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_data() {
+        let pool = test_pool().await;
+        let now = Utc::now().naive_utc();
+        let (alice, bob) = (1, 2);
+        let first = add_message(&pool, -10, 1, bob, now).await;
+        let second = add_message(&pool, -10, 2, bob, now).await;
+        let third = add_message(&pool, -10, 3, alice, now).await;
+        let last = add_message(&pool, -10, 4, bob, now).await;
+        add_message(&pool, -20, 1, bob, now).await;
+        save_chat_summary(&pool, -10, "before alice", first, second, 2).await.unwrap();
+        save_chat_summary(&pool, -10, "with alice", third, last, 2).await.unwrap();
+        assert!(upsert_user_fact(&pool, -10, alice, "city", "Kazan", 0.9, &[third]).await.unwrap());
+
+        let deleted = delete_user_data(&pool, alice).await.unwrap();
+        assert_eq!(deleted.chats, vec![-10]);
+        assert_eq!((deleted.messages, deleted.chunks, deleted.facts, deleted.summaries), (1, 1, 1, 1));
+
+        let summaries = get_chat_summaries(&pool, -10, 10).await.unwrap();
+        assert_eq!(summaries.iter().map(|s| s.summary_text.as_str()).collect::<Vec<_>>(), vec!["before alice"]);
+        assert_eq!(count_unsummarized_messages(&pool, -10).await.unwrap(), 1);
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM messages").await, 4);
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM memory_chunks").await, 4);
+
+        // An extraction that was already running writes nothing about a forgotten user
+        assert!(!upsert_user_fact(&pool, -10, alice, "city", "Kazan", 0.9, &[third]).await.unwrap());
+        assert!(get_user_facts(&pool, -10, alice, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_save_embedding_upserts_by_position() {
+        let pool = test_pool().await;
+        let message = add_message(&pool, -10, 1, 1, Utc::now().naive_utc()).await;
+        let first = save_embedding(&pool, message, 1, "old", "model", &[1.0, 0.0]).await.unwrap();
+        // A second job embedding the same message overwrites the chunk instead of adding one
+        let second = save_embedding(&pool, message, 1, "new", "model", &[0.0, 1.0]).await.unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM memory_chunks WHERE chunk_index = 1").await, 1);
+        let text: String = sqlx::query("SELECT chunk_text FROM memory_chunks WHERE id = ?")
+            .bind(first)
+            .map(|row: SqliteRow| row.get(0))
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(text, "new");
+    }
+
+    fn telegram_message(text: &str) -> Message {
+        serde_json::from_value(serde_json::json!({
+            "message_id": 5,
+            "date": 1_700_000_000,
+            "chat": { "id": -10, "type": "group", "title": "Чат" },
+            "from": { "id": 1, "is_bot": false, "first_name": "Оля" },
+            "text": text,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_save_message_upserts() {
+        let pool = test_pool().await;
+        let msg = telegram_message("привет");
+
+        // Both lookups may see nothing before either insert; the loser must not report New
+        let (a, b) = tokio::join!(
+            save_message(&pool, &msg, "привет", "text"),
+            save_message(&pool, &msg, "привет", "text")
+        );
+        let (a, b) = (a.unwrap(), b.unwrap());
+        let id = a.id();
+        assert!(matches!((a, b), (SavedMessage::New(_), SavedMessage::Unchanged(_)) | (SavedMessage::Unchanged(_), SavedMessage::New(_))));
+        assert_eq!(b.id(), id);
+
+        let edited = telegram_message("привет всем");
+        assert_eq!(save_message(&pool, &edited, "привет всем", "text").await.unwrap(), SavedMessage::Edited(id));
+        assert_eq!(save_message(&pool, &edited, "привет всем", "text").await.unwrap(), SavedMessage::Unchanged(id));
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM messages").await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_unpin_restores_importance() {
+        let pool = test_pool().await;
+        let message = add_message(&pool, -10, 1, 1, Utc::now().naive_utc()).await;
+        let chunk = save_embedding(&pool, message, 1, "text", "model", &[1.0]).await.unwrap();
+        update_chunk_importance(&pool, chunk, 1.5).await.unwrap();
+
+        assert_eq!(set_chunk_pinned(&pool, chunk, true).await.unwrap(), Some(PINNED_IMPORTANCE));
+        // Pinning twice must not lose the original importance
+        assert_eq!(set_chunk_pinned(&pool, chunk, true).await.unwrap(), Some(PINNED_IMPORTANCE));
+        assert_eq!(set_chunk_pinned(&pool, chunk, false).await.unwrap(), Some(1.5));
+        assert_eq!(set_chunk_pinned(&pool, chunk, false).await.unwrap(), Some(1.5));
+        assert_eq!(set_chunk_pinned(&pool, chunk + 1, true).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_search_chat_messages() {
+        let pool = test_pool().await;
+        let now = Utc::now().naive_utc();
+        for (i, text) in ["Паша переезжает в Казань", "паша, где ключи?", "ничего"].iter().enumerate() {
+            let id = add_message(&pool, -10, i as i64, 1, now - chrono::Duration::minutes(10 - i as i64)).await;
+            sqlx::query("UPDATE messages SET text = ? WHERE id = ?").bind(text).bind(id).execute(&pool).await.unwrap();
+        }
+
+        let texts = |messages: Vec<DbMessage>| messages.into_iter().filter_map(|m| m.text).collect::<Vec<_>>();
+        let found = search_chat_messages(&pool, -10, Some("ПАША"), 10).await.unwrap();
+        assert_eq!(texts(found), vec!["паша, где ключи?", "Паша переезжает в Казань"]);
+        let found = search_chat_messages(&pool, -10, Some("паша переезж"), 10).await.unwrap();
+        assert_eq!(texts(found), vec!["Паша переезжает в Казань"]);
+        assert!(search_chat_messages(&pool, -10, Some("\"ключи\" OR"), 10).await.unwrap().is_empty());
+        assert_eq!(search_chat_messages(&pool, -10, None, 10).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_delete_messages_before() {
+        let pool = test_pool().await;
+        let now = Utc::now().naive_utc();
+        let old = now - chrono::Duration::days(40);
+        let gone = add_message(&pool, -10, 1, 1, old).await;
+        let kept = add_message(&pool, -10, 2, 1, now).await;
+        add_message(&pool, -20, 1, 1, old).await;
+        sqlx::query("INSERT INTO memory_chunks (message_id, chunk_index, chunk_text) VALUES (?, 1, 'only old')")
+            .bind(gone)
+            .execute(&pool)
+            .await
+            .unwrap();
+        cache_embedding(&pool, "m", &embedding_text_hash("only old"), &[1.0]).await.unwrap();
+        cache_embedding(&pool, "m", &embedding_text_hash("text"), &[1.0]).await.unwrap();
+        save_chat_summary(&pool, -10, "old", gone, gone, 1).await.unwrap();
+        save_chat_summary(&pool, -10, "new", kept, kept, 1).await.unwrap();
+        upsert_user_fact(&pool, -10, 1, "city", "Kazan", 0.9, &[gone]).await.unwrap();
+        upsert_user_fact(&pool, -10, 1, "pet", "cat", 0.9, &[gone, kept]).await.unwrap();
+
+        let deleted = delete_messages_before(&pool, -10, now - chrono::Duration::days(30)).await.unwrap();
+        assert_eq!(deleted.chats, vec![-10]);
+        assert_eq!((deleted.messages, deleted.chunks, deleted.summaries, deleted.facts), (1, 2, 1, 1));
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM messages WHERE chat_id = -10").await, 1);
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM messages WHERE chat_id = -20").await, 1);
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM memory_chunks").await, 2);
+        assert_eq!(get_chat_summaries(&pool, -10, 10).await.unwrap()[0].summary_text, "new");
+        assert_eq!(get_user_facts(&pool, -10, 1, 10).await.unwrap()[0].key, "pet");
+        // Other messages still have chunks reading "text"
+        assert_eq!(deleted.cached_embeddings, 1);
+        assert!(get_cached_embedding(&pool, "m", &embedding_text_hash("text")).await.unwrap().is_some());
+
+        let deleted = delete_messages_before(&pool, -10, now - chrono::Duration::days(30)).await.unwrap();
+        assert!(deleted.chats.is_empty());
+        assert_eq!(deleted.messages, 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_cached_embeddings() {
+        let pool = test_pool().await;
+        cache_embedding(&pool, "a", "h1", &[1.0]).await.unwrap();
+        cache_embedding(&pool, "a", "h2", &[2.0]).await.unwrap();
+        cache_embedding(&pool, "b", "h3", &[3.0]).await.unwrap();
+
+        let hashes = ["h1", "h3", "h4"].map(String::from);
+        let cached = get_cached_embeddings(&pool, "a", &hashes).await.unwrap();
+        assert_eq!(cached.len(), 1);
+        assert_eq!(cached["h1"], vec![1.0]);
+        assert!(get_cached_embeddings(&pool, "a", &[]).await.unwrap().is_empty());
+    }
+}
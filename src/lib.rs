@@ -3,8 +3,10 @@ pub mod config;
 pub mod db;
 pub mod llm;
 pub mod logging;
+pub mod memory;
 pub mod security;
 pub mod state;
+pub mod tools;
 pub mod voice;
 pub mod web;
 pub mod webapp;
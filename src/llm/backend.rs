@@ -0,0 +1,151 @@
+use crate::llm::client::{ChatMessage, LlmError, TokenStream};
+use crate::llm::models::ModelInfo;
+use crate::llm::pool::EndpointStatus;
+use crate::llm::tools::ToolSpec;
+use futures::future::BoxFuture;
+use futures::Stream;
+use reqwest::Client;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// Stream of raw text lines from an HTTP response body
+pub type LineStream = Pin<Box<dyn Stream<Item = Result<String, LlmError>> + Send>>;
+
+/// An LLM server implementation (Ollama, OpenAI-compatible, ...)
+///
+/// Methods return boxed futures so the backend can be stored as `dyn LlmBackend`.
+pub trait LlmBackend: Send + Sync {
+    /// Short name for logs and status output
+    fn name(&self) -> &'static str;
+
+    fn generate<'a>(
+        &'a self,
+        model: &'a str,
+        prompt: &'a str,
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<String, LlmError>>;
+
+    fn generate_stream<'a>(
+        &'a self,
+        model: &'a str,
+        prompt: &'a str,
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<TokenStream, LlmError>>;
+
+    fn chat<'a>(
+        &'a self,
+        model: &'a str,
+        messages: &'a [ChatMessage],
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<String, LlmError>>;
+
+    fn chat_stream<'a>(
+        &'a self,
+        model: &'a str,
+        messages: &'a [ChatMessage],
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<TokenStream, LlmError>>;
+
+    /// Non-streaming chat whose reply is constrained to a JSON object
+    fn chat_json<'a>(
+        &'a self,
+        model: &'a str,
+        messages: &'a [ChatMessage],
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<String, LlmError>>;
+
+    /// One chat round with `tools` offered; the returned assistant message may carry tool calls
+    fn chat_with_tools<'a>(
+        &'a self,
+        model: &'a str,
+        messages: &'a [ChatMessage],
+        tools: &'a [ToolSpec],
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<ChatMessage, LlmError>>;
+
+    fn generate_vision<'a>(
+        &'a self,
+        model: &'a str,
+        prompt: &'a str,
+        images_base64: Vec<String>,
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<String, LlmError>>;
+
+    fn generate_embeddings<'a>(&'a self, model: &'a str, prompt: &'a str) -> BoxFuture<'a, Result<Vec<f64>, LlmError>>;
+
+    /// Embed several texts in one request; results are in input order
+    fn generate_embeddings_batch<'a>(
+        &'a self,
+        model: &'a str,
+        inputs: &'a [String],
+    ) -> BoxFuture<'a, Result<Vec<Vec<f64>>, LlmError>>;
+
+    fn list_models(&self) -> BoxFuture<'_, Result<Vec<String>, LlmError>>;
+
+    fn check_health(&self) -> BoxFuture<'_, Result<bool, LlmError>>;
+
+    /// Capabilities of one model; backends without metadata return [`ModelInfo::unknown`]
+    fn show_model<'a>(&'a self, model: &'a str) -> BoxFuture<'a, Result<ModelInfo, LlmError>> {
+        Box::pin(async move { Ok(ModelInfo::unknown(model)) })
+    }
+
+    /// Per-endpoint state for multi-host backends; empty for single-host ones
+    fn endpoint_statuses(&self) -> Vec<EndpointStatus> {
+        Vec::new()
+    }
+}
+
+/// HTTP client shared by the backends
+pub(crate) fn build_http_client() -> Client {
+    Client::builder()
+        .timeout(Duration::from_secs(180))
+        .connect_timeout(Duration::from_secs(10))
+        .build()
+        .unwrap_or_else(|_| Client::new())
+}
+
+/// Split a response body into non-empty lines as it arrives (NDJSON, SSE)
+pub(crate) fn line_stream(response: reqwest::Response) -> LineStream {
+    Box::pin(futures::stream::unfold(
+        (Some(response), Vec::<u8>::new()),
+        |(mut response, mut buffer)| async move {
+            loop {
+                if let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..=pos).collect();
+                    let line = String::from_utf8_lossy(&line).trim().to_string();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    return Some((Ok(line), (response, buffer)));
+                }
+
+                let Some(body) = response.as_mut() else {
+                    // Body finished: flush a trailing line without newline, then stop
+                    if buffer.iter().all(|b| b.is_ascii_whitespace()) {
+                        return None;
+                    }
+                    buffer.push(b'\n');
+                    continue;
+                };
+
+                match body.chunk().await {
+                    Ok(Some(bytes)) => buffer.extend_from_slice(&bytes),
+                    Ok(None) => response = None,
+                    Err(e) => return Some((Err(e.into()), (None, Vec::new()))),
+                }
+            }
+        },
+    ))
+}
+
+/// Total content length of a conversation, for request logging
+pub(crate) fn messages_len(messages: &[ChatMessage]) -> usize {
+    messages.iter().map(|m| m.content.len()).sum()
+}
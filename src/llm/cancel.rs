@@ -0,0 +1,95 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// Why a generation was cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelReason {
+    /// `/stop` or the ⏹ button
+    Stopped,
+    /// A newer message from the same user replaced the request
+    Superseded,
+}
+
+impl CancelReason {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::Stopped),
+            2 => Some(Self::Superseded),
+            _ => None,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::Stopped => 1,
+            Self::Superseded => 2,
+        }
+    }
+}
+
+/// Cancellation signal shared between a running generation and whoever may stop it
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    reason: AtomicU8,
+    notify: Notify,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel with `reason`; the first reason wins
+    pub fn cancel(&self, reason: CancelReason) {
+        let _ = self.inner.reason.compare_exchange(0, reason.to_u8(), Ordering::SeqCst, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn reason(&self) -> Option<CancelReason> {
+        CancelReason::from_u8(self.inner.reason.load(Ordering::SeqCst))
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.reason().is_some()
+    }
+
+    /// Resolves once the token is cancelled
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.inner.notify.notified();
+            tokio::pin!(notified);
+            // Register before checking the flag so a concurrent cancel isn't missed
+            notified.as_mut().enable();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_wakes_waiter() {
+        let token = CancelToken::new();
+        let waiter = tokio::spawn({
+            let token = token.clone();
+            async move { token.cancelled().await }
+        });
+        tokio::task::yield_now().await;
+
+        token.cancel(CancelReason::Superseded);
+        token.cancel(CancelReason::Stopped);
+        waiter.await.unwrap();
+        assert_eq!(token.reason(), Some(CancelReason::Superseded));
+    }
+}
@@ -1,53 +1,79 @@
+use crate::config::Config;
+use crate::llm::backend::LlmBackend;
+use crate::llm::models::ModelInfo;
+use crate::llm::ollama::OllamaBackend;
+use crate::llm::openai::OpenAiBackend;
+use crate::llm::pool::{EndpointStatus, OllamaPool, RoutingStrategy};
+use crate::llm::tools::{ToolCall, ToolRegistry, ToolSpec, MAX_TOOL_STEPS};
 use crate::logging;
-use reqwest::Client;
+use futures::Stream;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
+/// Most texts sent in one batch embedding request
+pub const EMBED_BATCH_SIZE: usize = 256;
+
+/// Stream of text fragments produced by a streaming generation
+pub type TokenStream = Pin<Box<dyn Stream<Item = Result<String, LlmError>> + Send>>;
+
+/// LLM client; dispatches to the backend selected by `LLM_BACKEND`
 #[derive(Clone)]
 pub struct LlmClient {
-    client: Client,
-    url: Arc<str>,
+    backend: Arc<dyn LlmBackend>,
+    /// `/api/show` results by model name
+    model_info: Arc<RwLock<HashMap<String, ModelInfo>>>,
 }
 
-#[derive(Serialize)]
-struct GenerateRequest<'a> {
-    model: &'a str,
-    prompt: &'a str,
-    stream: bool,
-    options: GenerateOptions,
+/// Speaker of a chat message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    System,
+    User,
+    Assistant,
+    Tool,
 }
 
-#[derive(Serialize)]
-struct GenerateOptions {
-    temperature: f64,
-    num_predict: u32,
+/// A single message of a chat conversation
+#[derive(Debug, Clone)]
+pub struct ChatMessage {
+    pub role: ChatRole,
+    pub content: String,
+    /// Tools requested by the assistant in this turn
+    pub tool_calls: Vec<ToolCall>,
+    /// For `Tool` messages: the call being answered
+    pub tool_call_id: Option<String>,
+    pub tool_name: Option<String>,
 }
 
-#[derive(Deserialize)]
-struct GenerateResponse {
-    response: String,
-}
+impl ChatMessage {
+    fn new(role: ChatRole, content: String) -> Self {
+        Self { role, content, tool_calls: Vec::new(), tool_call_id: None, tool_name: None }
+    }
 
-#[derive(Serialize)]
-struct EmbeddingRequest<'a> {
-    model: &'a str,
-    prompt: &'a str,
-}
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new(ChatRole::System, content.into())
+    }
 
-#[derive(Deserialize)]
-struct EmbeddingResponse {
-    embedding: Vec<f64>,
-}
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new(ChatRole::User, content.into())
+    }
 
-/// Vision request for multimodal models
-#[derive(Serialize)]
-struct VisionRequest<'a> {
-    model: &'a str,
-    prompt: &'a str,
-    images: Vec<String>, // Base64 encoded images
-    stream: bool,
-    options: GenerateOptions,
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new(ChatRole::Assistant, content.into())
+    }
+
+    /// Result of executing `call`, to be fed back to the model
+    pub fn tool_result(call: &ToolCall, content: impl Into<String>) -> Self {
+        Self {
+            tool_call_id: call.id.clone(),
+            tool_name: Some(call.name.clone()),
+            ..Self::new(ChatRole::Tool, content.into())
+        }
+    }
 }
 
 /// Error types for LLM operations
@@ -56,6 +82,8 @@ pub enum LlmError {
     Network(reqwest::Error),
     Timeout,
     QueueFull,
+    /// Stopped by the user or superseded by a newer request
+    Cancelled,
     InvalidResponse(String),
 }
 
@@ -65,6 +93,7 @@ impl std::fmt::Display for LlmError {
             LlmError::Network(e) => write!(f, "Network error: {}", e),
             LlmError::Timeout => write!(f, "Request timed out"),
             LlmError::QueueFull => write!(f, "Queue is full, try again later"),
+            LlmError::Cancelled => write!(f, "Generation cancelled"),
             LlmError::InvalidResponse(msg) => write!(f, "Invalid response: {}", msg),
         }
     }
@@ -83,52 +112,105 @@ impl From<reqwest::Error> for LlmError {
 }
 
 impl LlmClient {
-    pub fn new(ollama_url: String) -> Self {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(180))
-            .connect_timeout(Duration::from_secs(10))
-            .build()
-            .unwrap_or_else(|_| Client::new());
-        
-        Self {
-            client,
-            url: ollama_url.into(),
-        }
+    /// Client for an Ollama server
+    pub fn new(ollama_url: String, context_tokens: u32) -> Self {
+        Self::with_backend(Arc::new(OllamaBackend::new(ollama_url, context_tokens)))
     }
 
-    pub async fn generate(&self, model: &str, prompt: &str, temperature: f64, max_tokens: u32) -> Result<String, LlmError> {
-        let start_time = std::time::Instant::now();
-        let request_url = format!("{}/api/generate", self.url);
-        let request_body = GenerateRequest {
-            model,
-            prompt,
-            stream: false,
-            options: GenerateOptions {
-                temperature,
-                num_predict: max_tokens,
-            },
+    pub fn with_backend(backend: Arc<dyn LlmBackend>) -> Self {
+        Self { backend, model_info: Arc::new(RwLock::new(HashMap::new())) }
+    }
+
+    /// Build the client for the backend configured via `LLM_BACKEND`
+    pub fn from_config(config: &Config) -> Self {
+        let backend: Arc<dyn LlmBackend> = match config.llm_backend.to_lowercase().as_str() {
+            "openai" => Arc::new(OpenAiBackend::new(config.openai_base_url.clone(), config.openai_api_key.clone())),
+            // A single host goes through the pool too, so its health shows in /status
+            "ollama" => Self::ollama_pool(config.ollama_urls(), config),
+            other => {
+                tracing::warn!(target: "llm", "Unknown LLM_BACKEND '{}', falling back to ollama", other);
+                Self::ollama_pool(config.ollama_urls(), config)
+            }
         };
+        tracing::info!(target: "llm", "LLM backend: {}", backend.name());
+        Self::with_backend(backend)
+    }
+
+    fn ollama_pool(urls: Vec<String>, config: &Config) -> Arc<dyn LlmBackend> {
+        let pool = Arc::new(OllamaPool::new(urls, RoutingStrategy::parse(&config.llm_routing), config.llm_context_tokens));
+        pool.spawn_health_monitor();
+        pool
+    }
+
+    /// Name of the active backend ("ollama", "ollama-pool", "openai")
+    pub fn backend_name(&self) -> &'static str {
+        self.backend.name()
+    }
+
+    /// Per-endpoint health of the Ollama hosts; empty for OpenAI-compatible backends
+    pub fn endpoint_statuses(&self) -> Vec<EndpointStatus> {
+        self.backend.endpoint_statuses()
+    }
+
+    pub async fn generate(&self, model: &str, prompt: &str, temperature: f64, max_tokens: u32) -> Result<String, LlmError> {
+        self.backend.generate(model, prompt, temperature, max_tokens).await
+    }
+
+    /// Generate with streaming enabled, yielding text fragments as they are produced
+    pub async fn generate_stream(&self, model: &str, prompt: &str, temperature: f64, max_tokens: u32) -> Result<TokenStream, LlmError> {
+        self.backend.generate_stream(model, prompt, temperature, max_tokens).await
+    }
 
-        logging::log_llm_request(model, prompt.len());
+    /// Chat completion with typed messages, letting the model apply its own chat template
+    pub async fn chat(&self, model: &str, messages: &[ChatMessage], temperature: f64, max_tokens: u32) -> Result<String, LlmError> {
+        self.backend.chat(model, messages, temperature, max_tokens).await
+    }
+
+    /// Chat completion in JSON mode: the reply is a single JSON object
+    pub async fn chat_json(&self, model: &str, messages: &[ChatMessage], temperature: f64, max_tokens: u32) -> Result<String, LlmError> {
+        self.backend.chat_json(model, messages, temperature, max_tokens).await
+    }
+
+    /// Streaming variant of [`chat`](Self::chat)
+    pub async fn chat_stream(&self, model: &str, messages: &[ChatMessage], temperature: f64, max_tokens: u32) -> Result<TokenStream, LlmError> {
+        self.backend.chat_stream(model, messages, temperature, max_tokens).await
+    }
+
+    /// Single chat round offering `tools`; the reply may contain tool calls instead of text
+    pub async fn chat_with_tools(&self, model: &str, messages: &[ChatMessage], tools: &[ToolSpec], temperature: f64, max_tokens: u32) -> Result<ChatMessage, LlmError> {
+        self.backend.chat_with_tools(model, messages, tools, temperature, max_tokens).await
+    }
+
+    /// Multi-step tool loop: let the model call tools from `registry`, feed the results
+    /// back and repeat until it answers with plain text (at most `MAX_TOOL_STEPS` rounds)
+    pub async fn run_tool_loop(
+        &self,
+        model: &str,
+        mut messages: Vec<ChatMessage>,
+        registry: &ToolRegistry,
+        temperature: f64,
+        max_tokens: u32,
+    ) -> Result<String, LlmError> {
+        let specs = registry.specs();
 
-        let response = self
-            .client
-            .post(&request_url)
-            .json(&request_body)
-            .send()
-            .await?;
+        for step in 1..=MAX_TOOL_STEPS {
+            let reply = self.chat_with_tools(model, &messages, &specs, temperature, max_tokens).await?;
+            if reply.tool_calls.is_empty() {
+                return Ok(reply.content);
+            }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            logging::log_error("LLM API", &format!("HTTP {}: {}", status, body));
-            return Err(LlmError::InvalidResponse(format!("HTTP {}: {}", status, body)));
+            tracing::debug!(target: "llm", "Tool step {}: {} call(s)", step, reply.tool_calls.len());
+            let calls = reply.tool_calls.clone();
+            messages.push(reply);
+            for call in &calls {
+                let result = registry.execute(call).await;
+                messages.push(ChatMessage::tool_result(call, result));
+            }
         }
 
-        let response_body = response.json::<GenerateResponse>().await?;
-        let duration = start_time.elapsed();
-        logging::log_llm_response(duration.as_millis() as u64, response_body.response.len());
-        Ok(response_body.response)
+        // Out of steps: ask for an answer with what has been gathered so far
+        tracing::debug!(target: "llm", "Tool step limit reached, requesting final answer");
+        self.chat(model, &messages, temperature, max_tokens).await
     }
 
     /// Generate with timeout wrapper
@@ -164,106 +246,45 @@ impl LlmClient {
         temperature: f64,
         max_tokens: u32,
     ) -> Result<String, LlmError> {
-        let start_time = std::time::Instant::now();
-        let request_url = format!("{}/api/generate", self.url);
-        let frame_count = images_base64.len();
-        let request_body = VisionRequest {
-            model,
-            prompt,
-            images: images_base64,
-            stream: false,
-            options: GenerateOptions {
-                temperature,
-                num_predict: max_tokens,
-            },
-        };
-
-        let response = self
-            .client
-            .post(&request_url)
-            .json(&request_body)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            logging::log_error("Vision API", &format!("HTTP {}: {}", status, body));
-            return Err(LlmError::InvalidResponse(format!("HTTP {}: {}", status, body)));
-        }
-
-        let response_body = response.json::<GenerateResponse>().await?;
-        let duration = start_time.elapsed();
-        logging::log_vision_analysis(duration.as_millis() as u64, frame_count);
-        Ok(response_body.response)
+        self.backend.generate_vision(model, prompt, images_base64, temperature, max_tokens).await
     }
 
     pub async fn generate_embeddings(&self, model: &str, prompt: &str) -> Result<Vec<f64>, LlmError> {
-        let start_time = std::time::Instant::now();
-        let request_url = format!("{}/api/embeddings", self.url);
-        let request_body = EmbeddingRequest {
-            model,
-            prompt,
-        };
-
-        let response = self
-            .client
-            .post(&request_url)
-            .json(&request_body)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            return Err(LlmError::InvalidResponse(format!("HTTP {}: {}", status, body)));
-        }
+        self.backend.generate_embeddings(model, prompt).await
+    }
 
-        let response_body = response.json::<EmbeddingResponse>().await?;
-        let duration = start_time.elapsed();
-        logging::log_embedding(duration.as_millis() as u64);
-        Ok(response_body.embedding)
+    /// Embed up to `EMBED_BATCH_SIZE` texts in one request; results are in input order
+    pub async fn generate_embeddings_batch(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f64>>, LlmError> {
+        self.backend.generate_embeddings_batch(model, inputs).await
     }
 
     pub async fn check_health(&self) -> Result<bool, LlmError> {
-        let start_time = std::time::Instant::now();
-        let request_url = format!("{}/api/tags", self.url);
-
-        match self.client.get(&request_url).send().await {
-            Ok(response) => {
-                let duration = start_time.elapsed();
-                tracing::debug!(target: "llm", "Health check completed in {}ms", duration.as_millis());
-                Ok(response.status().is_success())
-            }
-            Err(e) => {
-                let duration = start_time.elapsed();
-                tracing::debug!(target: "llm", "Health check failed after {}ms: {}", duration.as_millis(), e);
-                Ok(false)
-            }
-        }
+        self.backend.check_health().await
     }
 
     /// List available models
     pub async fn list_models(&self) -> Result<Vec<String>, LlmError> {
-        let request_url = format!("{}/api/tags", self.url);
-        
-        let response = self.client.get(&request_url).send().await?;
-        
-        if !response.status().is_success() {
-            return Ok(vec![]);
-        }
-
-        #[derive(Deserialize)]
-        struct ModelsResponse {
-            models: Vec<ModelInfo>,
-        }
+        self.backend.list_models().await
+    }
 
-        #[derive(Deserialize)]
-        struct ModelInfo {
-            name: String,
+    /// Capabilities of `model`; fetched once, then served from cache
+    pub async fn model_info(&self, model: &str) -> Result<ModelInfo, LlmError> {
+        if let Some(info) = self.model_info.read().unwrap().get(model) {
+            return Ok(info.clone());
         }
+        let info = self.backend.show_model(model).await?;
+        self.model_info.write().unwrap().insert(model.to_string(), info.clone());
+        Ok(info)
+    }
 
-        let models: ModelsResponse = response.json().await?;
-        Ok(models.models.into_iter().map(|m| m.name).collect())
+    /// Installed models with their capabilities
+    pub async fn list_models_info(&self) -> Result<Vec<ModelInfo>, LlmError> {
+        let names = self.list_models().await?;
+        let infos = futures::future::join_all(names.iter().map(|name| self.model_info(name))).await;
+        Ok(names
+            .iter()
+            .zip(infos)
+            .map(|(name, info)| info.unwrap_or_else(|_| ModelInfo::unknown(name)))
+            .collect())
     }
 }
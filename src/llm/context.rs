@@ -0,0 +1,241 @@
+use crate::llm::client::ChatMessage;
+
+/// Per-message overhead of chat templates (role markers, separators)
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+/// Tokens kept free on top of the reply reservation, since estimates are rough
+const SAFETY_MARGIN_TOKENS: usize = 256;
+/// Share of the free budget memories may take before history is filled
+const MEMORY_SHARE_PERCENT: usize = 25;
+/// Share of the free budget chat summaries may take
+const SUMMARY_SHARE_PERCENT: usize = 15;
+/// Share of the free budget facts about the speaker may take
+const FACT_SHARE_PERCENT: usize = 10;
+
+/// Rough token count without a tokenizer: ~4 chars per token for Latin text,
+/// ~2 for Cyrillic and other non-ASCII text
+pub fn estimate_tokens(text: &str) -> usize {
+    let (ascii, other) = text.chars().fold((0usize, 0usize), |(a, o), c| {
+        if c.is_ascii() { (a + 1, o) } else { (a, o + 1) }
+    });
+    (ascii + 3) / 4 + (other + 1) / 2
+}
+
+fn message_tokens(msg: &ChatMessage) -> usize {
+    estimate_tokens(&msg.content) + MESSAGE_OVERHEAD_TOKENS
+}
+
+/// What is known about the user being answered, most confident first
+#[derive(Debug, Clone, Default)]
+pub struct SpeakerFacts {
+    pub name: String,
+    /// "key: fact" lines
+    pub facts: Vec<String>,
+}
+
+/// Token budget of one request: model context minus the reply reservation
+#[derive(Debug, Clone, Copy)]
+pub struct ContextBudget {
+    pub context_tokens: usize,
+    pub reply_tokens: usize,
+}
+
+impl ContextBudget {
+    pub fn new(context_tokens: usize, reply_tokens: usize) -> Self {
+        Self { context_tokens, reply_tokens }
+    }
+
+    /// Tokens available for the prompt
+    pub fn available(&self) -> usize {
+        self.context_tokens
+            .saturating_sub(self.reply_tokens)
+            .saturating_sub(SAFETY_MARGIN_TOKENS)
+    }
+
+    /// Assemble the conversation within budget.
+    ///
+    /// `system` (the persona prompt) and the last entry of `history` (the latest user turn)
+    /// are always kept. The rest is filled by priority: facts about the speaker, chat summaries
+    /// and memories up to their shares of what is left, then history from newest to oldest,
+    /// then any memories that didn't fit before. `summaries` are expected newest-first,
+    /// `memories` best-first, `history` oldest-first.
+    pub fn fit(
+        &self,
+        system: String,
+        speaker: Option<SpeakerFacts>,
+        summaries: Vec<String>,
+        memories: Vec<String>,
+        history: Vec<ChatMessage>,
+    ) -> Vec<ChatMessage> {
+        let mut history = history;
+        let latest = history.pop();
+
+        let mandatory = estimate_tokens(&system)
+            + MESSAGE_OVERHEAD_TOKENS
+            + latest.as_ref().map(message_tokens).unwrap_or(0);
+        let mut remaining = self.available().saturating_sub(mandatory);
+        if mandatory > self.available() {
+            tracing::warn!(
+                target: "llm",
+                "Persona prompt and latest message alone need ~{} tokens, budget is {}",
+                mandatory,
+                self.available()
+            );
+        }
+
+        // Facts about the speaker: capped share, most confident first; the heading only counts once one fits
+        let speaker = speaker.unwrap_or_default();
+        let heading = format!("### What You Know About {}:", speaker.name);
+        let fact_cap = remaining * FACT_SHARE_PERCENT / 100;
+        let mut fact_used = 0;
+        let mut kept_facts = Vec::new();
+        for fact in &speaker.facts {
+            let overhead = if kept_facts.is_empty() { estimate_tokens(&heading) + 2 } else { 0 };
+            let tokens = estimate_tokens(fact) + 2 + overhead;
+            if fact_used + tokens <= fact_cap {
+                fact_used += tokens;
+                kept_facts.push(fact);
+            }
+        }
+        remaining -= fact_used;
+
+        // Summaries: capped share, newest first, stopping at the first that doesn't fit
+        let summary_cap = remaining * SUMMARY_SHARE_PERCENT / 100;
+        let mut summary_used = 0;
+        let mut kept_summaries = Vec::new();
+        for summary in &summaries {
+            let tokens = estimate_tokens(summary) + 2;
+            if summary_used + tokens > summary_cap {
+                break;
+            }
+            summary_used += tokens;
+            kept_summaries.push(summary);
+        }
+        kept_summaries.reverse();
+        remaining -= summary_used;
+
+        // Memories, first pass: capped share
+        let memory_tokens: Vec<usize> = memories.iter().map(|m| estimate_tokens(m) + 2).collect();
+        let mut keep_memory = vec![false; memories.len()];
+        let memory_cap = remaining * MEMORY_SHARE_PERCENT / 100;
+        let mut memory_used = 0;
+        for (i, tokens) in memory_tokens.iter().enumerate() {
+            if memory_used + tokens <= memory_cap {
+                memory_used += tokens;
+                keep_memory[i] = true;
+            }
+        }
+        remaining -= memory_used;
+
+        // History, newest first, stopping at the first message that doesn't fit
+        let mut kept_history = Vec::new();
+        for msg in history.iter().rev() {
+            let tokens = message_tokens(msg);
+            if tokens > remaining {
+                break;
+            }
+            remaining -= tokens;
+            kept_history.push(msg.clone());
+        }
+        kept_history.reverse();
+
+        // Memories, second pass: whatever still fits
+        for (i, tokens) in memory_tokens.iter().enumerate() {
+            if !keep_memory[i] && *tokens <= remaining {
+                remaining -= tokens;
+                keep_memory[i] = true;
+            }
+        }
+
+        let kept_memories: Vec<&String> = memories.iter().zip(&keep_memory).filter(|(_, k)| **k).map(|(m, _)| m).collect();
+
+        tracing::debug!(
+            target: "llm",
+            "Context budget {}: kept {}/{} history, {}/{} facts, {}/{} summaries, {}/{} memories, ~{} tokens free",
+            self.available(),
+            kept_history.len(),
+            history.len(),
+            kept_facts.len(),
+            speaker.facts.len(),
+            kept_summaries.len(),
+            summaries.len(),
+            kept_memories.len(),
+            memories.len(),
+            remaining
+        );
+
+        let mut system = system;
+        if !kept_facts.is_empty() {
+            system.push_str("\n\n");
+            system.push_str(&heading);
+            for fact in kept_facts {
+                system.push_str(&format!("\n- {}", fact));
+            }
+        }
+        if !kept_summaries.is_empty() {
+            let summaries: Vec<&str> = kept_summaries.iter().map(|s| s.trim()).collect();
+            system.push_str("\n\n### Earlier in This Chat (summaries, oldest first):\n");
+            system.push_str(&summaries.join("\n\n"));
+        }
+        if !kept_memories.is_empty() {
+            system.push_str("\n\n### Relevant Past Memories (for context):\n");
+            for memory in kept_memories {
+                system.push_str(&format!("- {}\n", memory.trim()));
+            }
+        }
+
+        let mut messages = Vec::with_capacity(kept_history.len() + 2);
+        messages.push(ChatMessage::system(system));
+        messages.extend(kept_history);
+        messages.extend(latest);
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ASCII text starting with `tag` that estimates to exactly `tokens` tokens
+    fn text(tag: &str, tokens: usize) -> String {
+        format!("{:.<width$}", tag, width = tokens * 4)
+    }
+
+    fn contents(messages: &[ChatMessage]) -> Vec<&str> {
+        messages.iter().map(|m| m.content.as_str()).collect()
+    }
+
+    #[test]
+    fn test_tiny_budget_keeps_persona_and_latest_turn() {
+        let budget = ContextBudget::new(SAFETY_MARGIN_TOKENS + 20, 0);
+        let system = text("persona", 100);
+        let history = vec![ChatMessage::user(text("old", 10)), ChatMessage::user("latest")];
+        let speaker = SpeakerFacts { name: "Bob".to_string(), facts: vec![text("job", 5)] };
+        let messages = budget.fit(system.clone(), Some(speaker), vec![text("summary", 5)], vec![text("memory", 5)], history);
+
+        assert_eq!(contents(&messages), vec![system.as_str(), "latest"]);
+    }
+
+    #[test]
+    fn test_shares_and_oldest_first_trimming() {
+        // Persona and latest turn take 5 tokens each, leaving 1000
+        let budget = ContextBudget::new(SAFETY_MARGIN_TOKENS + 1010, 0);
+        // 50 tokens each with their separators
+        let summaries: Vec<String> = (0..5).map(|i| text(&format!("summary{}", i), 48)).collect();
+        let memories: Vec<String> = (0..10).map(|i| text(&format!("memory{}", i), 48)).collect();
+        let mut history: Vec<ChatMessage> = (0..20).map(|i| ChatMessage::user(text(&format!("h{}", i), 46))).collect();
+        history.push(ChatMessage::user("q"));
+
+        let messages = budget.fit("s".to_string(), None, summaries, memories, history);
+
+        // Summaries get 15% (150), the 3 newest; memories 25% of the rest (212), the best 4
+        let system = &messages[0].content;
+        assert!(system.contains("summary2") && !system.contains("summary3"));
+        assert!(system.find("summary2") < system.find("summary0"));
+        assert!(system.contains("memory3") && !system.contains("memory4"));
+
+        // History fills the remaining 650 from the newest turn back
+        let kept: Vec<&str> = contents(&messages[1..]).iter().map(|c| c.trim_end_matches('.')).collect();
+        let expected: Vec<String> = (7..20).map(|i| format!("h{}", i)).chain(["q".to_string()]).collect();
+        assert_eq!(kept, expected);
+    }
+}
@@ -1 +1,10 @@
+pub mod backend;
+pub mod cancel;
 pub mod client;
+pub mod context;
+pub mod models;
+pub mod ollama;
+pub mod openai;
+pub mod pool;
+pub mod queue;
+pub mod tools;
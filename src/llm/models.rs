@@ -0,0 +1,150 @@
+use serde::Serialize;
+use serde_json::Value;
+
+/// What a model is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelKind {
+    Generation,
+    Embedding,
+}
+
+/// Capabilities of an installed model, as reported by the backend
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelInfo {
+    pub name: String,
+    pub family: Option<String>,
+    /// Human-readable size, e.g. "8.0B"
+    pub parameter_size: Option<String>,
+    pub context_length: Option<u32>,
+    pub kind: ModelKind,
+    pub vision: bool,
+    /// False when the backend can't describe models; filters then let the model through
+    pub discovered: bool,
+}
+
+impl ModelInfo {
+    /// Placeholder for backends without model metadata; guesses the kind from the name
+    pub fn unknown(name: &str) -> Self {
+        let kind = if name.to_lowercase().contains("embed") { ModelKind::Embedding } else { ModelKind::Generation };
+        Self {
+            name: name.to_string(),
+            family: None,
+            parameter_size: None,
+            context_length: None,
+            kind,
+            vision: false,
+            discovered: false,
+        }
+    }
+
+    /// Parse an Ollama `/api/show` response.
+    ///
+    /// Newer Ollama versions list `capabilities`; older ones are recognised by the
+    /// vision projector and BERT-style architectures.
+    pub(crate) fn from_ollama_show(name: &str, show: &Value) -> Self {
+        let details = &show["details"];
+        let model_info = &show["model_info"];
+        let families: Vec<&str> = details["families"]
+            .as_array()
+            .map(|f| f.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        let architecture = model_info["general.architecture"].as_str().unwrap_or_default();
+
+        let context_length = model_info
+            .get(format!("{}.context_length", architecture))
+            .or_else(|| {
+                model_info
+                    .as_object()
+                    .and_then(|o| o.iter().find(|(k, _)| k.ends_with(".context_length")).map(|(_, v)| v))
+            })
+            .and_then(Value::as_u64)
+            .map(|n| n.min(u32::MAX as u64) as u32);
+
+        let (kind, vision) = match show["capabilities"].as_array() {
+            Some(caps) => {
+                let has = |c: &str| caps.iter().any(|v| v.as_str() == Some(c));
+                let kind = if has("embedding") && !has("completion") { ModelKind::Embedding } else { ModelKind::Generation };
+                (kind, has("vision"))
+            }
+            None => {
+                let vision = show.get("projector_info").is_some()
+                    || families.iter().any(|f| matches!(*f, "clip" | "mllama"));
+                let embedding = architecture.contains("bert")
+                    || model_info
+                        .as_object()
+                        .is_some_and(|o| o.keys().any(|k| k.ends_with(".pooling_type")));
+                let kind = if embedding { ModelKind::Embedding } else { ModelKind::Generation };
+                (kind, vision)
+            }
+        };
+
+        Self {
+            name: name.to_string(),
+            family: details["family"].as_str().map(str::to_string),
+            parameter_size: details["parameter_size"].as_str().filter(|s| !s.is_empty()).map(str::to_string),
+            context_length,
+            kind,
+            vision,
+            discovered: true,
+        }
+    }
+
+    /// Usable as the conversation model
+    pub fn can_chat(&self) -> bool {
+        self.kind == ModelKind::Generation
+    }
+
+    /// Usable as the vision model
+    pub fn can_see(&self) -> bool {
+        self.can_chat() && (self.vision || !self.discovered)
+    }
+
+    /// Short annotation for pickers, e.g. "8.0B · 128k · 👁"
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(size) = &self.parameter_size {
+            parts.push(size.clone());
+        }
+        if let Some(ctx) = self.context_length {
+            parts.push(if ctx >= 1024 { format!("{}k", ctx / 1024) } else { ctx.to_string() });
+        }
+        if self.kind == ModelKind::Embedding {
+            parts.push("emb".to_string());
+        }
+        if self.vision {
+            parts.push("👁".to_string());
+        }
+        parts.join(" · ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_show_with_capabilities() {
+        let show = json!({
+            "details": { "family": "llama", "families": ["llama", "clip"], "parameter_size": "13B" },
+            "model_info": { "general.architecture": "llama", "llama.context_length": 4096 },
+            "capabilities": ["completion", "vision"]
+        });
+        let info = ModelInfo::from_ollama_show("llava:13b", &show);
+        assert_eq!(info.context_length, Some(4096));
+        assert!(info.can_chat() && info.can_see());
+        assert_eq!(info.summary(), "13B · 4k · 👁");
+    }
+
+    #[test]
+    fn test_parse_show_legacy_embedding() {
+        let show = json!({
+            "details": { "family": "nomic-bert", "parameter_size": "137M" },
+            "model_info": { "general.architecture": "nomic-bert", "nomic-bert.context_length": 2048, "nomic-bert.pooling_type": 1 }
+        });
+        let info = ModelInfo::from_ollama_show("nomic-embed-text", &show);
+        assert_eq!(info.kind, ModelKind::Embedding);
+        assert!(!info.can_chat() && !info.can_see());
+    }
+}
@@ -0,0 +1,548 @@
+use crate::llm::backend::{build_http_client, line_stream, messages_len, LlmBackend};
+use crate::llm::client::{ChatMessage, ChatRole, LlmError, TokenStream};
+use crate::llm::models::ModelInfo;
+use crate::llm::tools::{ToolCall, ToolSpec};
+use crate::logging;
+use futures::future::BoxFuture;
+use futures::StreamExt;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Ollama native API (`/api/generate`, `/api/chat`, `/api/embeddings`, `/api/embed`, `/api/tags`, `/api/show`)
+#[derive(Clone)]
+pub struct OllamaBackend {
+    client: Client,
+    url: Arc<str>,
+    /// Context window requested with every generation (`num_ctx`)
+    context_tokens: u32,
+}
+
+#[derive(Serialize)]
+struct GenerateRequest<'a> {
+    model: &'a str,
+    prompt: &'a str,
+    stream: bool,
+    options: GenerateOptions,
+}
+
+#[derive(Serialize)]
+struct GenerateOptions {
+    temperature: f64,
+    num_predict: u32,
+    /// Without it Ollama loads the model with its own default window (often 2048–4096)
+    /// and silently cuts prompts that `ContextBudget` sized for `LLM_CONTEXT_TOKENS`
+    num_ctx: u32,
+}
+
+#[derive(Deserialize)]
+struct GenerateResponse {
+    response: String,
+}
+
+/// One NDJSON line of a streaming `/api/generate` response
+#[derive(Deserialize)]
+struct GenerateChunk {
+    #[serde(default)]
+    response: String,
+    #[serde(default)]
+    done: bool,
+    error: Option<String>,
+}
+
+/// `/api/chat` message as Ollama expects it on the wire
+#[derive(Serialize, Deserialize)]
+struct OllamaMessage {
+    role: ChatRole,
+    #[serde(default)]
+    content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OllamaToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OllamaToolCall {
+    function: OllamaFunctionCall,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OllamaFunctionCall {
+    name: String,
+    #[serde(default)]
+    arguments: serde_json::Value,
+}
+
+impl From<&ChatMessage> for OllamaMessage {
+    fn from(msg: &ChatMessage) -> Self {
+        Self {
+            role: msg.role,
+            content: msg.content.clone(),
+            tool_calls: msg
+                .tool_calls
+                .iter()
+                .map(|call| OllamaToolCall {
+                    function: OllamaFunctionCall {
+                        name: call.name.clone(),
+                        arguments: call.arguments.clone(),
+                    },
+                })
+                .collect(),
+            tool_name: msg.tool_name.clone(),
+        }
+    }
+}
+
+impl From<OllamaMessage> for ChatMessage {
+    fn from(msg: OllamaMessage) -> Self {
+        let mut message = match msg.role {
+            ChatRole::System => ChatMessage::system(msg.content),
+            ChatRole::User => ChatMessage::user(msg.content),
+            _ => ChatMessage::assistant(msg.content),
+        };
+        message.tool_calls = msg
+            .tool_calls
+            .into_iter()
+            .map(|call| ToolCall {
+                id: None,
+                name: call.function.name,
+                arguments: call.function.arguments,
+            })
+            .collect();
+        message
+    }
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<OllamaMessage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<serde_json::Value>,
+    /// `"json"` constrains the reply to valid JSON
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'static str>,
+    stream: bool,
+    options: GenerateOptions,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    message: OllamaMessage,
+}
+
+/// One NDJSON line of a streaming `/api/chat` response
+#[derive(Deserialize)]
+struct ChatChunk {
+    message: Option<OllamaMessage>,
+    #[serde(default)]
+    done: bool,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    prompt: &'a str,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    embedding: Vec<f64>,
+}
+
+/// `/api/embed` takes an array of inputs
+#[derive(Serialize)]
+struct BatchEmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct BatchEmbeddingResponse {
+    embeddings: Vec<Vec<f64>>,
+}
+
+/// Vision request for multimodal models
+#[derive(Serialize)]
+struct VisionRequest<'a> {
+    model: &'a str,
+    prompt: &'a str,
+    images: Vec<String>, // Base64 encoded images
+    stream: bool,
+    options: GenerateOptions,
+}
+
+impl OllamaBackend {
+    /// `context_tokens` is `LLM_CONTEXT_TOKENS`; Ollama caps it at the model's own context
+    /// length, the same limit `AppState::context_tokens` applies to the prompt budget
+    pub fn new(ollama_url: String, context_tokens: u32) -> Self {
+        Self {
+            client: build_http_client(),
+            url: ollama_url.into(),
+            context_tokens,
+        }
+    }
+
+    /// POST a JSON body and fail on non-2xx status
+    async fn post<T: Serialize>(&self, path: &str, body: &T, context: &str) -> Result<reqwest::Response, LlmError> {
+        let request_url = format!("{}{}", self.url, path);
+        let response = self.client.post(&request_url).json(body).send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            logging::log_error(context, &format!("HTTP {}: {}", status, body));
+            return Err(LlmError::InvalidResponse(format!("HTTP {}: {}", status, body)));
+        }
+        Ok(response)
+    }
+}
+
+impl OllamaBackend {
+    /// Non-streaming `/api/chat` without tools; `format` as in [`ChatRequest`]
+    async fn chat_once(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+        format: Option<&'static str>,
+        temperature: f64,
+        max_tokens: u32,
+    ) -> Result<String, LlmError> {
+        let start_time = std::time::Instant::now();
+        let request_body = ChatRequest {
+            model,
+            messages: messages.iter().map(OllamaMessage::from).collect(),
+            tools: Vec::new(),
+            format,
+            stream: false,
+            options: GenerateOptions {
+                temperature,
+                num_predict: max_tokens,
+                num_ctx: self.context_tokens,
+            },
+        };
+
+        logging::log_llm_request(model, messages_len(messages));
+
+        let response = self.post("/api/chat", &request_body, "LLM API").await?;
+        let response_body = response.json::<ChatResponse>().await?;
+        let duration = start_time.elapsed();
+        logging::log_llm_response(duration.as_millis() as u64, response_body.message.content.len());
+        Ok(response_body.message.content)
+    }
+}
+
+impl LlmBackend for OllamaBackend {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn generate<'a>(
+        &'a self,
+        model: &'a str,
+        prompt: &'a str,
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<String, LlmError>> {
+        Box::pin(async move {
+            let start_time = std::time::Instant::now();
+            let request_body = GenerateRequest {
+                model,
+                prompt,
+                stream: false,
+                options: GenerateOptions {
+                    temperature,
+                    num_predict: max_tokens,
+                    num_ctx: self.context_tokens,
+                },
+            };
+
+            logging::log_llm_request(model, prompt.len());
+
+            let response = self.post("/api/generate", &request_body, "LLM API").await?;
+            let response_body = response.json::<GenerateResponse>().await?;
+            let duration = start_time.elapsed();
+            logging::log_llm_response(duration.as_millis() as u64, response_body.response.len());
+            Ok(response_body.response)
+        })
+    }
+
+    fn generate_stream<'a>(
+        &'a self,
+        model: &'a str,
+        prompt: &'a str,
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<TokenStream, LlmError>> {
+        Box::pin(async move {
+            let start_time = std::time::Instant::now();
+            let request_body = GenerateRequest {
+                model,
+                prompt,
+                stream: true,
+                options: GenerateOptions {
+                    temperature,
+                    num_predict: max_tokens,
+                    num_ctx: self.context_tokens,
+                },
+            };
+
+            logging::log_llm_request(model, prompt.len());
+
+            let response = self.post("/api/generate", &request_body, "LLM API").await?;
+
+            let mut response_len = 0;
+            let tokens = line_stream(response).map(move |line| {
+                let chunk: GenerateChunk = serde_json::from_str(&line?)
+                    .map_err(|e| LlmError::InvalidResponse(format!("Bad stream chunk: {}", e)))?;
+                if let Some(error) = chunk.error {
+                    return Err(LlmError::InvalidResponse(error));
+                }
+                response_len += chunk.response.len();
+                if chunk.done {
+                    logging::log_llm_response(start_time.elapsed().as_millis() as u64, response_len);
+                }
+                Ok(chunk.response)
+            });
+
+            Ok(Box::pin(tokens) as TokenStream)
+        })
+    }
+
+    fn chat<'a>(
+        &'a self,
+        model: &'a str,
+        messages: &'a [ChatMessage],
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<String, LlmError>> {
+        Box::pin(self.chat_once(model, messages, None, temperature, max_tokens))
+    }
+
+    fn chat_json<'a>(
+        &'a self,
+        model: &'a str,
+        messages: &'a [ChatMessage],
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<String, LlmError>> {
+        Box::pin(self.chat_once(model, messages, Some("json"), temperature, max_tokens))
+    }
+
+    fn chat_stream<'a>(
+        &'a self,
+        model: &'a str,
+        messages: &'a [ChatMessage],
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<TokenStream, LlmError>> {
+        Box::pin(async move {
+            let start_time = std::time::Instant::now();
+            let request_body = ChatRequest {
+                model,
+                messages: messages.iter().map(OllamaMessage::from).collect(),
+                tools: Vec::new(),
+                format: None,
+                stream: true,
+                options: GenerateOptions {
+                    temperature,
+                    num_predict: max_tokens,
+                    num_ctx: self.context_tokens,
+                },
+            };
+
+            logging::log_llm_request(model, messages_len(messages));
+
+            let response = self.post("/api/chat", &request_body, "LLM API").await?;
+
+            let mut response_len = 0;
+            let tokens = line_stream(response).map(move |line| {
+                let chunk: ChatChunk = serde_json::from_str(&line?)
+                    .map_err(|e| LlmError::InvalidResponse(format!("Bad stream chunk: {}", e)))?;
+                if let Some(error) = chunk.error {
+                    return Err(LlmError::InvalidResponse(error));
+                }
+                let content = chunk.message.map(|m| m.content).unwrap_or_default();
+                response_len += content.len();
+                if chunk.done {
+                    logging::log_llm_response(start_time.elapsed().as_millis() as u64, response_len);
+                }
+                Ok(content)
+            });
+
+            Ok(Box::pin(tokens) as TokenStream)
+        })
+    }
+
+    fn chat_with_tools<'a>(
+        &'a self,
+        model: &'a str,
+        messages: &'a [ChatMessage],
+        tools: &'a [ToolSpec],
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<ChatMessage, LlmError>> {
+        Box::pin(async move {
+            let start_time = std::time::Instant::now();
+            let request_body = ChatRequest {
+                model,
+                messages: messages.iter().map(OllamaMessage::from).collect(),
+                tools: tools.iter().map(ToolSpec::to_function_json).collect(),
+                format: None,
+                stream: false,
+                options: GenerateOptions {
+                    temperature,
+                    num_predict: max_tokens,
+                    num_ctx: self.context_tokens,
+                },
+            };
+
+            logging::log_llm_request(model, messages_len(messages));
+
+            let response = self.post("/api/chat", &request_body, "LLM API").await?;
+            let response_body = response.json::<ChatResponse>().await?;
+            let duration = start_time.elapsed();
+            logging::log_llm_response(duration.as_millis() as u64, response_body.message.content.len());
+            Ok(response_body.message.into())
+        })
+    }
+
+    fn generate_vision<'a>(
+        &'a self,
+        model: &'a str,
+        prompt: &'a str,
+        images_base64: Vec<String>,
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<String, LlmError>> {
+        Box::pin(async move {
+            let start_time = std::time::Instant::now();
+            let frame_count = images_base64.len();
+            let request_body = VisionRequest {
+                model,
+                prompt,
+                images: images_base64,
+                stream: false,
+                options: GenerateOptions {
+                    temperature,
+                    num_predict: max_tokens,
+                    num_ctx: self.context_tokens,
+                },
+            };
+
+            let response = self.post("/api/generate", &request_body, "Vision API").await?;
+            let response_body = response.json::<GenerateResponse>().await?;
+            let duration = start_time.elapsed();
+            logging::log_vision_analysis(duration.as_millis() as u64, frame_count);
+            Ok(response_body.response)
+        })
+    }
+
+    fn generate_embeddings<'a>(&'a self, model: &'a str, prompt: &'a str) -> BoxFuture<'a, Result<Vec<f64>, LlmError>> {
+        Box::pin(async move {
+            let start_time = std::time::Instant::now();
+            let request_url = format!("{}/api/embeddings", self.url);
+            let request_body = EmbeddingRequest {
+                model,
+                prompt,
+            };
+
+            let response = self
+                .client
+                .post(&request_url)
+                .json(&request_body)
+                .send()
+                .await?;
+
+            if !response.status().is_success() {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                return Err(LlmError::InvalidResponse(format!("HTTP {}: {}", status, body)));
+            }
+
+            let response_body = response.json::<EmbeddingResponse>().await?;
+            let duration = start_time.elapsed();
+            logging::log_embedding(duration.as_millis() as u64);
+            Ok(response_body.embedding)
+        })
+    }
+
+    fn generate_embeddings_batch<'a>(
+        &'a self,
+        model: &'a str,
+        inputs: &'a [String],
+    ) -> BoxFuture<'a, Result<Vec<Vec<f64>>, LlmError>> {
+        Box::pin(async move {
+            let start_time = std::time::Instant::now();
+            let request_body = BatchEmbeddingRequest { model, input: inputs };
+
+            let response = self.post("/api/embed", &request_body, "Embedding API").await?;
+            let response_body = response.json::<BatchEmbeddingResponse>().await?;
+            if response_body.embeddings.len() != inputs.len() {
+                return Err(LlmError::InvalidResponse(format!(
+                    "Expected {} embeddings, got {}",
+                    inputs.len(),
+                    response_body.embeddings.len()
+                )));
+            }
+
+            logging::log_embedding(start_time.elapsed().as_millis() as u64);
+            Ok(response_body.embeddings)
+        })
+    }
+
+    fn list_models(&self) -> BoxFuture<'_, Result<Vec<String>, LlmError>> {
+        Box::pin(async move {
+            let request_url = format!("{}/api/tags", self.url);
+
+            let response = self.client.get(&request_url).send().await?;
+
+            if !response.status().is_success() {
+                return Ok(vec![]);
+            }
+
+            #[derive(Deserialize)]
+            struct ModelsResponse {
+                models: Vec<ModelInfo>,
+            }
+
+            #[derive(Deserialize)]
+            struct ModelInfo {
+                name: String,
+            }
+
+            let models: ModelsResponse = response.json().await?;
+            Ok(models.models.into_iter().map(|m| m.name).collect())
+        })
+    }
+
+    fn show_model<'a>(&'a self, model: &'a str) -> BoxFuture<'a, Result<ModelInfo, LlmError>> {
+        Box::pin(async move {
+            let response = self.post("/api/show", &serde_json::json!({ "model": model }), "Model Info").await?;
+            let show: serde_json::Value = response.json().await?;
+            Ok(ModelInfo::from_ollama_show(model, &show))
+        })
+    }
+
+    fn check_health(&self) -> BoxFuture<'_, Result<bool, LlmError>> {
+        Box::pin(async move {
+            let start_time = std::time::Instant::now();
+            let request_url = format!("{}/api/tags", self.url);
+
+            match self.client.get(&request_url).send().await {
+                Ok(response) => {
+                    let duration = start_time.elapsed();
+                    tracing::debug!(target: "llm", "Health check completed in {}ms", duration.as_millis());
+                    Ok(response.status().is_success())
+                }
+                Err(e) => {
+                    let duration = start_time.elapsed();
+                    tracing::debug!(target: "llm", "Health check failed after {}ms: {}", duration.as_millis(), e);
+                    Ok(false)
+                }
+            }
+        })
+    }
+}
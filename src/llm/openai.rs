@@ -0,0 +1,491 @@
+use crate::llm::backend::{build_http_client, line_stream, messages_len, LlmBackend};
+use crate::llm::client::{ChatMessage, ChatRole, LlmError, TokenStream};
+use crate::llm::tools::{ToolCall, ToolSpec};
+use crate::logging;
+use futures::future::BoxFuture;
+use futures::StreamExt;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// OpenAI-compatible API (llama.cpp server, vLLM, LM Studio, ...)
+///
+/// `base_url` is the server root; `/v1/...` paths are appended.
+#[derive(Clone)]
+pub struct OpenAiBackend {
+    client: Client,
+    base_url: Arc<str>,
+    api_key: Option<Arc<str>>,
+}
+
+#[derive(Serialize)]
+struct ChatCompletionRequest<'a, M: Serialize> {
+    model: &'a str,
+    messages: M,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<serde_json::Value>,
+    temperature: f64,
+    max_tokens: u32,
+    stream: bool,
+    /// `{"type": "json_object"}` constrains the reply to valid JSON
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<serde_json::Value>,
+}
+
+/// Chat message in OpenAI wire format
+#[derive(Serialize)]
+struct OpenAiMessage<'a> {
+    role: ChatRole,
+    content: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OpenAiToolCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<&'a str>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OpenAiToolCall {
+    #[serde(default)]
+    id: String,
+    #[serde(rename = "type", default = "default_tool_type")]
+    kind: String,
+    function: OpenAiFunctionCall,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OpenAiFunctionCall {
+    name: String,
+    /// JSON-encoded arguments object
+    #[serde(default)]
+    arguments: String,
+}
+
+fn default_tool_type() -> String {
+    "function".to_string()
+}
+
+impl<'a> From<&'a ChatMessage> for OpenAiMessage<'a> {
+    fn from(msg: &'a ChatMessage) -> Self {
+        Self {
+            role: msg.role,
+            content: &msg.content,
+            tool_calls: msg
+                .tool_calls
+                .iter()
+                .map(|call| OpenAiToolCall {
+                    id: call.id.clone().unwrap_or_default(),
+                    kind: default_tool_type(),
+                    function: OpenAiFunctionCall {
+                        name: call.name.clone(),
+                        arguments: call.arguments.to_string(),
+                    },
+                })
+                .collect(),
+            tool_call_id: msg.tool_call_id.as_deref(),
+        }
+    }
+}
+
+fn wire_messages(messages: &[ChatMessage]) -> Vec<OpenAiMessage<'_>> {
+    messages.iter().map(OpenAiMessage::from).collect()
+}
+
+#[derive(Deserialize)]
+struct ChatCompletionResponse {
+    choices: Vec<Choice>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ChoiceMessage,
+}
+
+#[derive(Deserialize)]
+struct ChoiceMessage {
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<OpenAiToolCall>,
+}
+
+/// One SSE `data:` payload of a streaming chat completion
+#[derive(Deserialize)]
+struct ChatCompletionChunk {
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+}
+
+#[derive(Deserialize)]
+struct ChunkChoice {
+    delta: ChunkDelta,
+}
+
+#[derive(Deserialize)]
+struct ChunkDelta {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+#[derive(Serialize)]
+struct EmbeddingRequest<'a, I: Serialize + ?Sized> {
+    model: &'a str,
+    /// A single string or an array of strings
+    input: &'a I,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f64>,
+    #[serde(default)]
+    index: usize,
+}
+
+impl OpenAiBackend {
+    pub fn new(base_url: String, api_key: Option<String>) -> Self {
+        Self {
+            client: build_http_client(),
+            base_url: base_url.trim_end_matches('/').into(),
+            api_key: api_key.filter(|k| !k.is_empty()).map(Into::into),
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let builder = self.client.request(method, format!("{}{}", self.base_url, path));
+        match &self.api_key {
+            Some(key) => builder.bearer_auth(key),
+            None => builder,
+        }
+    }
+
+    /// POST a JSON body and fail on non-2xx status
+    async fn post<T: Serialize>(&self, path: &str, body: &T, context: &str) -> Result<reqwest::Response, LlmError> {
+        let response = self.request(reqwest::Method::POST, path).json(body).send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            logging::log_error(context, &format!("HTTP {}: {}", status, body));
+            return Err(LlmError::InvalidResponse(format!("HTTP {}: {}", status, body)));
+        }
+        Ok(response)
+    }
+
+    async fn complete<M: Serialize>(
+        &self,
+        model: &str,
+        messages: M,
+        tools: &[ToolSpec],
+        temperature: f64,
+        max_tokens: u32,
+        context: &str,
+    ) -> Result<ChoiceMessage, LlmError> {
+        let request_body = ChatCompletionRequest {
+            model,
+            messages,
+            tools: tools.iter().map(ToolSpec::to_function_json).collect(),
+            temperature,
+            max_tokens,
+            stream: false,
+            response_format: None,
+        };
+        self.send_completion(&request_body, context).await
+    }
+
+    async fn send_completion<M: Serialize>(
+        &self,
+        request_body: &ChatCompletionRequest<'_, M>,
+        context: &str,
+    ) -> Result<ChoiceMessage, LlmError> {
+        let response = self.post("/v1/chat/completions", request_body, context).await?;
+        let response_body = response.json::<ChatCompletionResponse>().await?;
+        response_body
+            .choices
+            .into_iter()
+            .next()
+            .map(|c| c.message)
+            .ok_or_else(|| LlmError::InvalidResponse("No choices in completion".to_string()))
+    }
+
+    async fn complete_stream(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+        temperature: f64,
+        max_tokens: u32,
+    ) -> Result<TokenStream, LlmError> {
+        let start_time = std::time::Instant::now();
+        let request_body = ChatCompletionRequest {
+            model,
+            messages: wire_messages(messages),
+            tools: Vec::new(),
+            temperature,
+            max_tokens,
+            stream: true,
+            response_format: None,
+        };
+
+        logging::log_llm_request(model, messages_len(messages));
+
+        let response = self.post("/v1/chat/completions", &request_body, "LLM API").await?;
+
+        let mut response_len = 0;
+        let tokens = line_stream(response).filter_map(move |line| {
+            let item = match line {
+                Err(e) => Some(Err(e)),
+                Ok(line) => match line.strip_prefix("data:").map(str::trim) {
+                    // Comments, event names and keep-alives carry no tokens
+                    None => None,
+                    Some("[DONE]") => {
+                        logging::log_llm_response(start_time.elapsed().as_millis() as u64, response_len);
+                        None
+                    }
+                    Some(payload) => match serde_json::from_str::<ChatCompletionChunk>(payload) {
+                        Ok(chunk) => {
+                            let content: String = chunk
+                                .choices
+                                .into_iter()
+                                .filter_map(|c| c.delta.content)
+                                .collect();
+                            response_len += content.len();
+                            Some(Ok(content))
+                        }
+                        Err(e) => Some(Err(LlmError::InvalidResponse(format!("Bad stream chunk: {}", e)))),
+                    },
+                },
+            };
+            futures::future::ready(item)
+        });
+
+        Ok(Box::pin(tokens))
+    }
+}
+
+impl LlmBackend for OpenAiBackend {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn generate<'a>(
+        &'a self,
+        model: &'a str,
+        prompt: &'a str,
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<String, LlmError>> {
+        Box::pin(async move {
+            let messages = [ChatMessage::user(prompt)];
+            self.chat(model, &messages, temperature, max_tokens).await
+        })
+    }
+
+    fn generate_stream<'a>(
+        &'a self,
+        model: &'a str,
+        prompt: &'a str,
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<TokenStream, LlmError>> {
+        Box::pin(async move {
+            let messages = [ChatMessage::user(prompt)];
+            self.complete_stream(model, &messages, temperature, max_tokens).await
+        })
+    }
+
+    fn chat<'a>(
+        &'a self,
+        model: &'a str,
+        messages: &'a [ChatMessage],
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<String, LlmError>> {
+        Box::pin(async move {
+            let start_time = std::time::Instant::now();
+            logging::log_llm_request(model, messages_len(messages));
+
+            let reply = self.complete(model, wire_messages(messages), &[], temperature, max_tokens, "LLM API").await?;
+            let content = reply.content.unwrap_or_default();
+            logging::log_llm_response(start_time.elapsed().as_millis() as u64, content.len());
+            Ok(content)
+        })
+    }
+
+    fn chat_json<'a>(
+        &'a self,
+        model: &'a str,
+        messages: &'a [ChatMessage],
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<String, LlmError>> {
+        Box::pin(async move {
+            let start_time = std::time::Instant::now();
+            logging::log_llm_request(model, messages_len(messages));
+
+            let request_body = ChatCompletionRequest {
+                model,
+                messages: wire_messages(messages),
+                tools: Vec::new(),
+                temperature,
+                max_tokens,
+                stream: false,
+                response_format: Some(serde_json::json!({ "type": "json_object" })),
+            };
+            let reply = self.send_completion(&request_body, "LLM API").await?;
+            let content = reply.content.unwrap_or_default();
+            logging::log_llm_response(start_time.elapsed().as_millis() as u64, content.len());
+            Ok(content)
+        })
+    }
+
+    fn chat_stream<'a>(
+        &'a self,
+        model: &'a str,
+        messages: &'a [ChatMessage],
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<TokenStream, LlmError>> {
+        Box::pin(self.complete_stream(model, messages, temperature, max_tokens))
+    }
+
+    fn chat_with_tools<'a>(
+        &'a self,
+        model: &'a str,
+        messages: &'a [ChatMessage],
+        tools: &'a [ToolSpec],
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<ChatMessage, LlmError>> {
+        Box::pin(async move {
+            let start_time = std::time::Instant::now();
+            logging::log_llm_request(model, messages_len(messages));
+
+            let reply = self.complete(model, wire_messages(messages), tools, temperature, max_tokens, "LLM API").await?;
+            let mut message = ChatMessage::assistant(reply.content.unwrap_or_default());
+            logging::log_llm_response(start_time.elapsed().as_millis() as u64, message.content.len());
+
+            for call in reply.tool_calls {
+                let arguments = serde_json::from_str(&call.function.arguments)
+                    .unwrap_or(serde_json::Value::Object(Default::default()));
+                message.tool_calls.push(ToolCall {
+                    id: Some(call.id),
+                    name: call.function.name,
+                    arguments,
+                });
+            }
+            Ok(message)
+        })
+    }
+
+    fn generate_vision<'a>(
+        &'a self,
+        model: &'a str,
+        prompt: &'a str,
+        images_base64: Vec<String>,
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<String, LlmError>> {
+        Box::pin(async move {
+            let start_time = std::time::Instant::now();
+            let frame_count = images_base64.len();
+
+            // Frames are extracted as JPEG, sent inline as data URLs
+            let mut content = vec![serde_json::json!({ "type": "text", "text": prompt })];
+            content.extend(images_base64.into_iter().map(|image| {
+                serde_json::json!({
+                    "type": "image_url",
+                    "image_url": { "url": format!("data:image/jpeg;base64,{}", image) }
+                })
+            }));
+            let messages = [serde_json::json!({ "role": "user", "content": content })];
+
+            let reply = self.complete(model, &messages[..], &[], temperature, max_tokens, "Vision API").await?;
+            let description = reply.content.unwrap_or_default();
+            logging::log_vision_analysis(start_time.elapsed().as_millis() as u64, frame_count);
+            Ok(description)
+        })
+    }
+
+    fn generate_embeddings<'a>(&'a self, model: &'a str, prompt: &'a str) -> BoxFuture<'a, Result<Vec<f64>, LlmError>> {
+        Box::pin(async move {
+            let start_time = std::time::Instant::now();
+            let request_body = EmbeddingRequest { model, input: prompt };
+
+            let response = self.post("/v1/embeddings", &request_body, "Embedding API").await?;
+            let response_body = response.json::<EmbeddingResponse>().await?;
+            let embedding = response_body
+                .data
+                .into_iter()
+                .next()
+                .map(|d| d.embedding)
+                .ok_or_else(|| LlmError::InvalidResponse("No embedding in response".to_string()))?;
+
+            logging::log_embedding(start_time.elapsed().as_millis() as u64);
+            Ok(embedding)
+        })
+    }
+
+    fn generate_embeddings_batch<'a>(
+        &'a self,
+        model: &'a str,
+        inputs: &'a [String],
+    ) -> BoxFuture<'a, Result<Vec<Vec<f64>>, LlmError>> {
+        Box::pin(async move {
+            let start_time = std::time::Instant::now();
+            let request_body = EmbeddingRequest { model, input: inputs };
+
+            let response = self.post("/v1/embeddings", &request_body, "Embedding API").await?;
+            let mut data = response.json::<EmbeddingResponse>().await?.data;
+            if data.len() != inputs.len() {
+                return Err(LlmError::InvalidResponse(format!("Expected {} embeddings, got {}", inputs.len(), data.len())));
+            }
+            data.sort_by_key(|d| d.index);
+
+            logging::log_embedding(start_time.elapsed().as_millis() as u64);
+            Ok(data.into_iter().map(|d| d.embedding).collect())
+        })
+    }
+
+    fn list_models(&self) -> BoxFuture<'_, Result<Vec<String>, LlmError>> {
+        Box::pin(async move {
+            let response = self.request(reqwest::Method::GET, "/v1/models").send().await?;
+
+            if !response.status().is_success() {
+                return Ok(vec![]);
+            }
+
+            #[derive(Deserialize)]
+            struct ModelsResponse {
+                data: Vec<ModelInfo>,
+            }
+
+            #[derive(Deserialize)]
+            struct ModelInfo {
+                id: String,
+            }
+
+            let models: ModelsResponse = response.json().await?;
+            Ok(models.data.into_iter().map(|m| m.id).collect())
+        })
+    }
+
+    fn check_health(&self) -> BoxFuture<'_, Result<bool, LlmError>> {
+        Box::pin(async move {
+            let start_time = std::time::Instant::now();
+
+            match self.request(reqwest::Method::GET, "/v1/models").send().await {
+                Ok(response) => {
+                    tracing::debug!(target: "llm", "Health check completed in {}ms", start_time.elapsed().as_millis());
+                    Ok(response.status().is_success())
+                }
+                Err(e) => {
+                    tracing::debug!(target: "llm", "Health check failed after {}ms: {}", start_time.elapsed().as_millis(), e);
+                    Ok(false)
+                }
+            }
+        })
+    }
+}
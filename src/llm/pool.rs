@@ -0,0 +1,554 @@
+use crate::llm::backend::LlmBackend;
+use crate::llm::client::{ChatMessage, LlmError, TokenStream};
+use crate::llm::models::ModelInfo;
+use crate::llm::ollama::OllamaBackend;
+use crate::llm::tools::ToolSpec;
+use futures::future::BoxFuture;
+use futures::StreamExt;
+use serde::Serialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Consecutive failures that open an endpoint's circuit
+const FAILURE_THRESHOLD: u32 = 3;
+/// How long an open circuit rejects requests before a probe is allowed
+const OPEN_DURATION: Duration = Duration::from_secs(30);
+/// Interval of background health checks
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How the pool picks the first endpoint to try
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoutingStrategy {
+    RoundRobin,
+    LeastLoaded,
+}
+
+impl RoutingStrategy {
+    pub fn parse(value: &str) -> Self {
+        match value.to_lowercase().as_str() {
+            "least_loaded" | "least-loaded" => RoutingStrategy::LeastLoaded,
+            _ => RoutingStrategy::RoundRobin,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// Snapshot of one endpoint for `/status` and the web app
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointStatus {
+    pub url: String,
+    pub healthy: bool,
+    pub circuit: CircuitState,
+    pub in_flight: usize,
+    pub total_requests: u64,
+    pub total_failures: u64,
+}
+
+#[derive(Default)]
+struct Breaker {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    /// A half-open probe request is running
+    probing: bool,
+    healthy: bool,
+}
+
+struct Endpoint {
+    url: String,
+    backend: OllamaBackend,
+    breaker: Mutex<Breaker>,
+    in_flight: AtomicUsize,
+    total_requests: AtomicU64,
+    total_failures: AtomicU64,
+}
+
+impl Endpoint {
+    fn circuit(breaker: &Breaker) -> CircuitState {
+        match breaker.opened_at {
+            None => CircuitState::Closed,
+            Some(at) if at.elapsed() < OPEN_DURATION => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Whether a request may be sent now; half-open circuits admit a single probe
+    fn try_acquire(&self) -> bool {
+        let mut breaker = self.breaker.lock().unwrap();
+        match Self::circuit(&breaker) {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen if breaker.probing => false,
+            CircuitState::HalfOpen => {
+                breaker.probing = true;
+                true
+            }
+        }
+    }
+
+    fn record_success(&self) {
+        let mut breaker = self.breaker.lock().unwrap();
+        if breaker.opened_at.is_some() {
+            tracing::info!(target: "llm", "LLM endpoint {} recovered, closing circuit", self.url);
+        }
+        *breaker = Breaker { healthy: true, ..Breaker::default() };
+    }
+
+    fn record_failure(&self) {
+        self.total_failures.fetch_add(1, Ordering::Relaxed);
+        let mut breaker = self.breaker.lock().unwrap();
+        breaker.consecutive_failures += 1;
+        breaker.probing = false;
+        breaker.healthy = false;
+        let was_half_open = Self::circuit(&breaker) == CircuitState::HalfOpen;
+        if was_half_open || breaker.consecutive_failures >= FAILURE_THRESHOLD {
+            if breaker.opened_at.is_none() || was_half_open {
+                tracing::warn!(target: "llm", "LLM endpoint {} failing, opening circuit", self.url);
+            }
+            breaker.opened_at = Some(Instant::now());
+        }
+    }
+
+    fn status(&self) -> EndpointStatus {
+        let breaker = self.breaker.lock().unwrap();
+        EndpointStatus {
+            url: self.url.clone(),
+            healthy: breaker.healthy,
+            circuit: Self::circuit(&breaker),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            total_requests: self.total_requests.load(Ordering::Relaxed),
+            total_failures: self.total_failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Keeps an endpoint's in-flight counter raised while a request (or stream) is alive
+struct InFlight(Arc<Endpoint>);
+
+impl InFlight {
+    fn new(endpoint: Arc<Endpoint>) -> Self {
+        endpoint.in_flight.fetch_add(1, Ordering::Relaxed);
+        endpoint.total_requests.fetch_add(1, Ordering::Relaxed);
+        Self(endpoint)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// What a failed request says about the endpoint that served it
+#[derive(Debug, PartialEq, Eq)]
+enum FailureKind {
+    /// Connection errors and 5xx answers: the host is down, try the next one
+    Unavailable,
+    /// The host is up but slow. Counts against it, but isn't retried elsewhere:
+    /// rerunning a long generation on every host would multiply the wait.
+    Timeout,
+    /// The host answered 404, usually because the model isn't pulled there; another may have it
+    ModelMissing,
+    /// Anything else is the request's fault
+    Request,
+}
+
+fn classify(err: &LlmError) -> FailureKind {
+    match err {
+        LlmError::Network(_) => FailureKind::Unavailable,
+        LlmError::Timeout => FailureKind::Timeout,
+        LlmError::InvalidResponse(msg) if msg.starts_with("HTTP 5") => FailureKind::Unavailable,
+        LlmError::InvalidResponse(msg) if msg.starts_with("HTTP 404") => FailureKind::ModelMissing,
+        LlmError::InvalidResponse(_) | LlmError::QueueFull | LlmError::Cancelled => FailureKind::Request,
+    }
+}
+
+/// Several Ollama hosts behind one backend: routing, circuit breakers and failover
+pub struct OllamaPool {
+    endpoints: Vec<Arc<Endpoint>>,
+    strategy: RoutingStrategy,
+    next: AtomicUsize,
+}
+
+impl OllamaPool {
+    pub fn new(urls: Vec<String>, strategy: RoutingStrategy, context_tokens: u32) -> Self {
+        let endpoints = urls
+            .into_iter()
+            .map(|url| {
+                Arc::new(Endpoint {
+                    backend: OllamaBackend::new(url.clone(), context_tokens),
+                    url,
+                    // Assume healthy until the first check says otherwise
+                    breaker: Mutex::new(Breaker { healthy: true, ..Breaker::default() }),
+                    in_flight: AtomicUsize::new(0),
+                    total_requests: AtomicU64::new(0),
+                    total_failures: AtomicU64::new(0),
+                })
+            })
+            .collect();
+
+        Self {
+            endpoints,
+            strategy,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Periodically health-check every endpoint; stops once the pool is dropped
+    pub fn spawn_health_monitor(self: &Arc<Self>) {
+        let pool = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(pool) = pool.upgrade() else { break };
+                for endpoint in &pool.endpoints {
+                    if endpoint.backend.check_health().await.unwrap_or(false) {
+                        endpoint.record_success();
+                    } else {
+                        endpoint.record_failure();
+                    }
+                }
+            }
+        });
+    }
+
+    pub fn statuses(&self) -> Vec<EndpointStatus> {
+        self.endpoints.iter().map(|e| e.status()).collect()
+    }
+
+    /// Endpoints in the order they should be tried
+    fn candidates(&self) -> Vec<Arc<Endpoint>> {
+        let n = self.endpoints.len();
+        if n == 0 {
+            return Vec::new();
+        }
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % n;
+        let mut ordered: Vec<Arc<Endpoint>> = (0..n).map(|i| self.endpoints[(start + i) % n].clone()).collect();
+        if self.strategy == RoutingStrategy::LeastLoaded {
+            // Stable sort keeps the rotation as tie-breaker
+            ordered.sort_by_key(|e| e.in_flight.load(Ordering::Relaxed));
+        }
+        ordered
+    }
+
+    /// Run `op` against endpoints until one succeeds, skipping open circuits.
+    /// Moves on when a host is down or lacks the model, not when it times out.
+    async fn route<T, F, Fut>(&self, op: F) -> Result<(T, InFlight), LlmError>
+    where
+        F: Fn(Arc<Endpoint>) -> Fut,
+        Fut: Future<Output = Result<T, LlmError>>,
+    {
+        let mut last_error = None;
+
+        for endpoint in self.candidates() {
+            if !endpoint.try_acquire() {
+                continue;
+            }
+            let guard = InFlight::new(endpoint.clone());
+            let error = match op(endpoint.clone()).await {
+                Ok(value) => {
+                    endpoint.record_success();
+                    return Ok((value, guard));
+                }
+                Err(e) => e,
+            };
+            match classify(&error) {
+                FailureKind::Unavailable => {
+                    tracing::warn!(target: "llm", "LLM endpoint {} failed: {}, trying next", endpoint.url, error);
+                    endpoint.record_failure();
+                    last_error = Some(error);
+                }
+                FailureKind::Timeout => {
+                    endpoint.record_failure();
+                    return Err(error);
+                }
+                FailureKind::ModelMissing => {
+                    // The host itself is fine
+                    tracing::debug!(target: "llm", "LLM endpoint {} lacks the model: {}, trying next", endpoint.url, error);
+                    endpoint.record_success();
+                    last_error = Some(error);
+                }
+                FailureKind::Request => {
+                    // The host answered; the request itself was bad
+                    endpoint.record_success();
+                    return Err(error);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| LlmError::InvalidResponse("All LLM endpoints are unavailable".to_string())))
+    }
+
+    /// Attach the in-flight guard to a stream so load is counted until it finishes
+    fn guarded_stream(stream: TokenStream, guard: InFlight) -> TokenStream {
+        Box::pin(stream.map(move |item| {
+            let _ = &guard;
+            item
+        }))
+    }
+}
+
+impl LlmBackend for OllamaPool {
+    fn name(&self) -> &'static str {
+        if self.endpoints.len() > 1 {
+            "ollama-pool"
+        } else {
+            "ollama"
+        }
+    }
+
+    fn generate<'a>(
+        &'a self,
+        model: &'a str,
+        prompt: &'a str,
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<String, LlmError>> {
+        Box::pin(async move {
+            self.route(|e| async move { e.backend.generate(model, prompt, temperature, max_tokens).await })
+                .await
+                .map(|(value, _)| value)
+        })
+    }
+
+    fn generate_stream<'a>(
+        &'a self,
+        model: &'a str,
+        prompt: &'a str,
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<TokenStream, LlmError>> {
+        Box::pin(async move {
+            self.route(|e| async move { e.backend.generate_stream(model, prompt, temperature, max_tokens).await })
+                .await
+                .map(|(stream, guard)| Self::guarded_stream(stream, guard))
+        })
+    }
+
+    fn chat<'a>(
+        &'a self,
+        model: &'a str,
+        messages: &'a [ChatMessage],
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<String, LlmError>> {
+        Box::pin(async move {
+            self.route(|e| async move { e.backend.chat(model, messages, temperature, max_tokens).await })
+                .await
+                .map(|(value, _)| value)
+        })
+    }
+
+    fn chat_json<'a>(
+        &'a self,
+        model: &'a str,
+        messages: &'a [ChatMessage],
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<String, LlmError>> {
+        Box::pin(async move {
+            self.route(|e| async move { e.backend.chat_json(model, messages, temperature, max_tokens).await })
+                .await
+                .map(|(value, _)| value)
+        })
+    }
+
+    fn chat_stream<'a>(
+        &'a self,
+        model: &'a str,
+        messages: &'a [ChatMessage],
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<TokenStream, LlmError>> {
+        Box::pin(async move {
+            self.route(|e| async move { e.backend.chat_stream(model, messages, temperature, max_tokens).await })
+                .await
+                .map(|(stream, guard)| Self::guarded_stream(stream, guard))
+        })
+    }
+
+    fn chat_with_tools<'a>(
+        &'a self,
+        model: &'a str,
+        messages: &'a [ChatMessage],
+        tools: &'a [ToolSpec],
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<ChatMessage, LlmError>> {
+        Box::pin(async move {
+            self.route(|e| async move { e.backend.chat_with_tools(model, messages, tools, temperature, max_tokens).await })
+                .await
+                .map(|(value, _)| value)
+        })
+    }
+
+    fn generate_vision<'a>(
+        &'a self,
+        model: &'a str,
+        prompt: &'a str,
+        images_base64: Vec<String>,
+        temperature: f64,
+        max_tokens: u32,
+    ) -> BoxFuture<'a, Result<String, LlmError>> {
+        Box::pin(async move {
+            let images = &images_base64;
+            self.route(|e| async move { e.backend.generate_vision(model, prompt, images.clone(), temperature, max_tokens).await })
+                .await
+                .map(|(value, _)| value)
+        })
+    }
+
+    fn generate_embeddings<'a>(&'a self, model: &'a str, prompt: &'a str) -> BoxFuture<'a, Result<Vec<f64>, LlmError>> {
+        Box::pin(async move {
+            self.route(|e| async move { e.backend.generate_embeddings(model, prompt).await })
+                .await
+                .map(|(value, _)| value)
+        })
+    }
+
+    fn generate_embeddings_batch<'a>(
+        &'a self,
+        model: &'a str,
+        inputs: &'a [String],
+    ) -> BoxFuture<'a, Result<Vec<Vec<f64>>, LlmError>> {
+        Box::pin(async move {
+            self.route(|e| async move { e.backend.generate_embeddings_batch(model, inputs).await })
+                .await
+                .map(|(value, _)| value)
+        })
+    }
+
+    fn list_models(&self) -> BoxFuture<'_, Result<Vec<String>, LlmError>> {
+        Box::pin(async move {
+            self.route(|e| async move { e.backend.list_models().await })
+                .await
+                .map(|(value, _)| value)
+        })
+    }
+
+    fn show_model<'a>(&'a self, model: &'a str) -> BoxFuture<'a, Result<ModelInfo, LlmError>> {
+        Box::pin(async move {
+            self.route(|e| async move { e.backend.show_model(model).await })
+                .await
+                .map(|(value, _)| value)
+        })
+    }
+
+    /// Healthy if at least one endpoint is
+    fn check_health(&self) -> BoxFuture<'_, Result<bool, LlmError>> {
+        Box::pin(async move {
+            let checks = self.endpoints.iter().map(|e| e.backend.check_health());
+            let results = futures::future::join_all(checks).await;
+            Ok(results.into_iter().any(|r| r.unwrap_or(false)))
+        })
+    }
+
+    fn endpoint_statuses(&self) -> Vec<EndpointStatus> {
+        self.statuses()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(urls: &[&str]) -> OllamaPool {
+        OllamaPool::new(urls.iter().map(|u| u.to_string()).collect(), RoutingStrategy::RoundRobin, 4096)
+    }
+
+    #[test]
+    fn test_circuit_opens_and_admits_one_probe() {
+        let pool = pool(&["http://a"]);
+        let endpoint = &pool.endpoints[0];
+        for _ in 1..FAILURE_THRESHOLD {
+            endpoint.record_failure();
+        }
+        assert!(endpoint.try_acquire());
+        endpoint.record_failure();
+        assert_eq!(endpoint.status().circuit, CircuitState::Open);
+        assert!(!endpoint.try_acquire());
+
+        // Once the open period is over, a single probe gets through
+        endpoint.breaker.lock().unwrap().opened_at = Instant::now().checked_sub(OPEN_DURATION);
+        assert!(endpoint.try_acquire());
+        assert!(!endpoint.try_acquire());
+        // A failed probe reopens the circuit right away
+        endpoint.record_failure();
+        assert_eq!(endpoint.status().circuit, CircuitState::Open);
+
+        endpoint.record_success();
+        assert_eq!(endpoint.status().circuit, CircuitState::Closed);
+        assert!(endpoint.status().healthy);
+        assert_eq!(endpoint.status().total_failures, FAILURE_THRESHOLD as u64 + 1);
+    }
+
+    #[tokio::test]
+    async fn test_route_fails_over_and_skips_open_circuits() {
+        let pool = pool(&["http://a", "http://b"]);
+        let tried = Mutex::new(Vec::new());
+        let op = |endpoint: Arc<Endpoint>| {
+            tried.lock().unwrap().push(endpoint.url.clone());
+            async move {
+                match endpoint.url.as_str() {
+                    "http://a" => Err(LlmError::InvalidResponse("HTTP 503: down".to_string())),
+                    url => Ok(url.to_string()),
+                }
+            }
+        };
+        for _ in 0..FAILURE_THRESHOLD {
+            let (url, _) = pool.route(&op).await.unwrap();
+            assert_eq!(url, "http://b");
+        }
+        // Round robin started at a for some of those requests, and a kept failing
+        assert!(pool.endpoints[0].status().total_failures > 0);
+
+        for _ in 0..FAILURE_THRESHOLD {
+            pool.endpoints[0].record_failure();
+        }
+        tried.lock().unwrap().clear();
+        for _ in 0..4 {
+            pool.route(&op).await.unwrap();
+        }
+        assert!(tried.lock().unwrap().iter().all(|url| url == "http://b"));
+
+        // A bad request is the caller's fault: returned as is, not counted against the host
+        let result = pool.route(|_| async { Err::<(), _>(LlmError::InvalidResponse("HTTP 400: bad".to_string())) }).await;
+        assert!(matches!(result, Err(LlmError::InvalidResponse(_))));
+        assert_eq!(pool.endpoints[1].status().total_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn test_route_fails_over_on_missing_model_but_not_on_timeout() {
+        let pool = pool(&["http://a", "http://b"]);
+        let missing_on_a = |endpoint: Arc<Endpoint>| async move {
+            match endpoint.url.as_str() {
+                "http://a" => Err(LlmError::InvalidResponse("HTTP 404 Not Found: model not found".to_string())),
+                url => Ok(url.to_string()),
+            }
+        };
+        for _ in 0..FAILURE_THRESHOLD + 1 {
+            let (url, _) = pool.route(missing_on_a).await.unwrap();
+            assert_eq!(url, "http://b");
+        }
+        assert_eq!(pool.endpoints[0].status().total_failures, 0);
+
+        let tried = Mutex::new(Vec::new());
+        let result = pool
+            .route(|endpoint: Arc<Endpoint>| {
+                tried.lock().unwrap().push(endpoint.url.clone());
+                async { Err::<(), _>(LlmError::Timeout) }
+            })
+            .await;
+        assert!(matches!(result, Err(LlmError::Timeout)));
+        assert_eq!(tried.lock().unwrap().len(), 1);
+    }
+}
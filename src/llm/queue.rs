@@ -0,0 +1,274 @@
+use crate::llm::client::LlmError;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Who is waiting for an LLM slot; higher is served first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Housekeeping such as summarization; yields to every reply
+    Background,
+    Group,
+    Private,
+    Owner,
+}
+
+struct Waiter {
+    priority: Priority,
+    seq: u64,
+    tx: oneshot::Sender<LlmPermit>,
+}
+
+impl Waiter {
+    /// Higher priority first, then FIFO
+    fn key(&self) -> (Priority, std::cmp::Reverse<u64>) {
+        (self.priority, std::cmp::Reverse(self.seq))
+    }
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+struct Inner {
+    running: usize,
+    next_seq: u64,
+    waiting: BinaryHeap<Waiter>,
+}
+
+struct Shared {
+    max_concurrent: usize,
+    inner: Mutex<Inner>,
+}
+
+impl Shared {
+    /// Hand the freed slot to the best live waiter, or return it to the pool
+    fn release(self: &Arc<Self>) {
+        let mut inner = self.inner.lock().unwrap();
+        while let Some(waiter) = inner.waiting.pop() {
+            if waiter.tx.is_closed() {
+                continue;
+            }
+            drop(inner);
+            // If the waiter vanished in between, dropping the permit releases the slot again
+            let _ = waiter.tx.send(LlmPermit { shared: self.clone() });
+            return;
+        }
+        inner.running = inner.running.saturating_sub(1);
+    }
+}
+
+/// Slot in the LLM queue; the slot is released on drop
+pub struct LlmPermit {
+    shared: Arc<Shared>,
+}
+
+impl Drop for LlmPermit {
+    fn drop(&mut self) {
+        self.shared.release();
+    }
+}
+
+/// Concurrency limit for LLM calls with priority ordering of waiters
+#[derive(Clone)]
+pub struct LlmQueue {
+    shared: Arc<Shared>,
+}
+
+impl LlmQueue {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                max_concurrent: max_concurrent.max(1),
+                inner: Mutex::new(Inner {
+                    running: 0,
+                    next_seq: 0,
+                    waiting: BinaryHeap::new(),
+                }),
+            }),
+        }
+    }
+
+    pub fn max_concurrent(&self) -> usize {
+        self.shared.max_concurrent
+    }
+
+    /// Free slots right now
+    pub fn available(&self) -> usize {
+        let inner = self.shared.inner.lock().unwrap();
+        self.shared.max_concurrent.saturating_sub(inner.running)
+    }
+
+    /// Requests waiting for a slot
+    pub fn waiting(&self) -> usize {
+        self.shared.inner.lock().unwrap().waiting.len()
+    }
+
+    /// Wait for a slot for at most `timeout`; `LlmError::QueueFull` when it runs out
+    pub async fn acquire(&self, priority: Priority, timeout: Duration) -> Result<LlmPermit, LlmError> {
+        self.acquire_with_notice(priority, timeout, Duration::MAX, |_| async {}).await
+    }
+
+    /// Like [`acquire`](Self::acquire), calling `on_notice` with the 1-based queue position
+    /// once the request has waited `notice_after`
+    pub async fn acquire_with_notice<F, Fut>(
+        &self,
+        priority: Priority,
+        timeout: Duration,
+        notice_after: Duration,
+        on_notice: F,
+    ) -> Result<LlmPermit, LlmError>
+    where
+        F: FnOnce(usize) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let (seq, mut rx) = match self.enqueue(priority) {
+            Ok(permit) => return Ok(permit),
+            Err(pending) => pending,
+        };
+        // Leaves the queue if this future is dropped, e.g. when the reply is stopped
+        let _waiting = Waiting { queue: self, seq };
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        if notice_after < timeout {
+            tokio::select! {
+                permit = &mut rx => return permit.map_err(|_| LlmError::QueueFull),
+                _ = tokio::time::sleep(notice_after) => {
+                    if let Some(position) = self.position(seq) {
+                        on_notice(position).await;
+                    }
+                }
+            }
+        }
+
+        match tokio::time::timeout_at(deadline, &mut rx).await {
+            Ok(permit) => permit.map_err(|_| LlmError::QueueFull),
+            Err(_) => {
+                if self.remove(seq) {
+                    Err(LlmError::QueueFull)
+                } else {
+                    // Granted right as the timeout fired
+                    rx.await.map_err(|_| LlmError::QueueFull)
+                }
+            }
+        }
+    }
+
+    fn enqueue(&self, priority: Priority) -> Result<LlmPermit, (u64, oneshot::Receiver<LlmPermit>)> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        if inner.running < self.shared.max_concurrent && inner.waiting.is_empty() {
+            inner.running += 1;
+            return Ok(LlmPermit { shared: self.shared.clone() });
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let seq = inner.next_seq;
+        inner.next_seq += 1;
+        inner.waiting.push(Waiter { priority, seq, tx });
+        Err((seq, rx))
+    }
+
+    /// 1-based position of a waiter, `None` if it already left the queue
+    fn position(&self, seq: u64) -> Option<usize> {
+        let inner = self.shared.inner.lock().unwrap();
+        let me = inner.waiting.iter().find(|w| w.seq == seq)?;
+        Some(inner.waiting.iter().filter(|w| w.key() > me.key()).count() + 1)
+    }
+
+    /// Drop a waiter; false if it was already served
+    fn remove(&self, seq: u64) -> bool {
+        let mut inner = self.shared.inner.lock().unwrap();
+        let before = inner.waiting.len();
+        inner.waiting.retain(|w| w.seq != seq);
+        inner.waiting.len() != before
+    }
+}
+
+/// Removes a waiter from the queue when its acquire future goes away, so it no
+/// longer counts in `waiting` and positions
+struct Waiting<'a> {
+    queue: &'a LlmQueue,
+    seq: u64,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.queue.remove(self.seq);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_priority_order() {
+        let queue = LlmQueue::new(1);
+        let running = queue.acquire(Priority::Group, Duration::from_secs(1)).await.unwrap();
+
+        let (group_seq, mut group_rx) = queue.enqueue(Priority::Group).err().unwrap();
+        let (owner_seq, mut owner_rx) = queue.enqueue(Priority::Owner).err().unwrap();
+        assert_eq!(queue.position(owner_seq), Some(1));
+        assert_eq!(queue.position(group_seq), Some(2));
+
+        drop(running);
+        let owner_permit = owner_rx.try_recv().expect("owner should be served first");
+        assert!(group_rx.try_recv().is_err());
+
+        drop(owner_permit);
+        assert!(group_rx.try_recv().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_queue_timeout() {
+        let queue = LlmQueue::new(1);
+        let _running = queue.acquire(Priority::Owner, Duration::from_secs(1)).await.unwrap();
+
+        let result = queue.acquire(Priority::Owner, Duration::from_millis(20)).await;
+        assert!(matches!(result, Err(LlmError::QueueFull)));
+        assert_eq!(queue.waiting(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_waiter_leaves_queue() {
+        let queue = LlmQueue::new(1);
+        let _running = queue.acquire(Priority::Group, Duration::from_secs(1)).await.unwrap();
+
+        let waiting = queue.acquire(Priority::Owner, Duration::from_secs(10));
+        let cancelled = tokio::time::timeout(Duration::from_millis(20), waiting).await;
+        assert!(cancelled.is_err());
+        assert_eq!(queue.waiting(), 0);
+
+        let (seq, _rx) = queue.enqueue(Priority::Group).err().unwrap();
+        assert_eq!(queue.position(seq), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_slot_returned_on_drop() {
+        let queue = LlmQueue::new(2);
+        let a = queue.acquire(Priority::Group, Duration::from_secs(1)).await.unwrap();
+        let _b = queue.acquire(Priority::Group, Duration::from_secs(1)).await.unwrap();
+        assert_eq!(queue.available(), 0);
+        drop(a);
+        assert_eq!(queue.available(), 1);
+    }
+}
@@ -0,0 +1,112 @@
+use futures::future::BoxFuture;
+use serde_json::Value;
+use std::future::Future;
+use std::sync::Arc;
+
+/// Max model/tool round-trips before forcing a plain answer
+pub const MAX_TOOL_STEPS: usize = 4;
+
+/// Max characters of a single tool result fed back to the model
+const MAX_TOOL_RESULT_CHARS: usize = 4000;
+
+/// Tool description offered to the model
+#[derive(Debug, Clone)]
+pub struct ToolSpec {
+    pub name: String,
+    pub description: String,
+    /// JSON schema of the arguments object
+    pub parameters: Value,
+}
+
+impl ToolSpec {
+    /// `{"type": "function", "function": {...}}` form used by both Ollama and OpenAI APIs
+    pub fn to_function_json(&self) -> Value {
+        serde_json::json!({
+            "type": "function",
+            "function": {
+                "name": self.name,
+                "description": self.description,
+                "parameters": self.parameters,
+            }
+        })
+    }
+}
+
+/// Tool invocation requested by the model
+#[derive(Debug, Clone)]
+pub struct ToolCall {
+    /// Call id (OpenAI-compatible backends only)
+    pub id: Option<String>,
+    pub name: String,
+    pub arguments: Value,
+}
+
+pub type ToolExecutor = Arc<dyn Fn(Value) -> BoxFuture<'static, Result<String, String>> + Send + Sync>;
+
+struct RegisteredTool {
+    spec: ToolSpec,
+    executor: ToolExecutor,
+}
+
+/// Set of tools available for one conversation
+#[derive(Clone, Default)]
+pub struct ToolRegistry {
+    tools: Vec<Arc<RegisteredTool>>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a tool; a later registration with the same name replaces the earlier one
+    pub fn register<F, Fut>(&mut self, name: &str, description: &str, parameters: Value, executor: F)
+    where
+        F: Fn(Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String, String>> + Send + 'static,
+    {
+        self.tools.retain(|t| t.spec.name != name);
+        self.tools.push(Arc::new(RegisteredTool {
+            spec: ToolSpec {
+                name: name.to_string(),
+                description: description.to_string(),
+                parameters,
+            },
+            executor: Arc::new(move |args| Box::pin(executor(args))),
+        }));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    pub fn specs(&self) -> Vec<ToolSpec> {
+        self.tools.iter().map(|t| t.spec.clone()).collect()
+    }
+
+    /// Run a tool call; failures are returned as text so the model can react to them
+    pub async fn execute(&self, call: &ToolCall) -> String {
+        let Some(tool) = self.tools.iter().find(|t| t.spec.name == call.name) else {
+            tracing::warn!(target: "llm", "Model requested unknown tool '{}'", call.name);
+            return format!("Error: unknown tool '{}'", call.name);
+        };
+
+        let start_time = std::time::Instant::now();
+        let result = (tool.executor)(call.arguments.clone()).await;
+        tracing::debug!(
+            target: "llm",
+            "Tool '{}' finished in {}ms (ok: {})",
+            call.name,
+            start_time.elapsed().as_millis(),
+            result.is_ok()
+        );
+
+        match result {
+            Ok(output) if output.chars().count() > MAX_TOOL_RESULT_CHARS => {
+                output.chars().take(MAX_TOOL_RESULT_CHARS).collect()
+            }
+            Ok(output) => output,
+            Err(e) => format!("Error: {}", e),
+        }
+    }
+}
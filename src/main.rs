@@ -67,12 +67,29 @@ async fn main() {
     let _ = persona_forge::db::set_config(&db_pool, "vision_enabled", &config.vision_enabled.to_string()).await;
     let _ = persona_forge::db::set_config(&db_pool, "voice_enabled", &config.voice_enabled.to_string()).await;
     let _ = persona_forge::db::set_config(&db_pool, "web_search_enabled", &config.web_search_enabled.to_string()).await;
+    let _ = persona_forge::db::set_config(&db_pool, "tools_enabled", &config.tools_enabled.to_string()).await;
+    let _ = persona_forge::db::set_config(&db_pool, "auto_cancel_enabled", &config.auto_cancel_enabled.to_string()).await;
+    let _ = persona_forge::db::set_config(&db_pool, "query_rewrite_enabled", &config.query_rewrite_enabled.to_string()).await;
+    let _ = persona_forge::db::set_config(&db_pool, "vector_index_enabled", &config.vector_index_enabled.to_string()).await;
     tracing::debug!("Runtime config synced from environment");
 
+    // Chunks stored before embedding models were recorded came from the configured model
+    match persona_forge::db::adopt_unversioned_chunks(&db_pool, &config.ollama_embedding_model).await {
+        Ok(0) => {}
+        Ok(n) => tracing::info!("Tagged {} existing memory chunks with embedding model {}", n, config.ollama_embedding_model),
+        Err(e) => tracing::warn!("Failed to tag existing memory chunks: {}", e),
+    }
+
     let webapp_port = config.webapp_port;
     let bot = Bot::new(config.teloxide_token.clone());
     let app_state = AppState::new(config, db_pool);
 
+    // Resume or start re-embedding if chunks from another embedding model are left
+    persona_forge::memory::reembed::start(&app_state);
+
+    // Drop messages that outlived their chat's retention policy
+    persona_forge::memory::privacy::start_retention(&app_state);
+
     // Get bot info from Telegram API (with retry)
     for attempt in 1..=3 {
         match bot.get_me().await {
@@ -101,6 +118,10 @@ async fn main() {
         }
     }
 
+    // Run queued embeddings, summaries and fact extraction, including ones left from the last run.
+    // Started after bot info is known: fact extraction needs the bot's id to skip its own replies.
+    persona_forge::memory::jobs::start_workers(&app_state).await;
+
     // Start webapp server in background
     let webapp_state = app_state.clone();
     tokio::spawn(async move {
@@ -113,10 +134,13 @@ async fn main() {
 
     let handler = dptree::entry()
         .branch(Update::filter_message().endpoint(persona_forge::bot::handlers::messages::handle_message))
+        .branch(Update::filter_edited_message().endpoint(persona_forge::bot::handlers::messages::handle_edited_message))
         .branch(Update::filter_callback_query().endpoint(handle_callback_query));
 
+    // Updates of one chat are handled in order; /stop and button presses skip the line
     let mut dispatcher = Dispatcher::builder(bot, handler)
         .dependencies(dptree::deps![app_state])
+        .distribution_function(persona_forge::bot::handlers::distribution_key)
         .enable_ctrlc_handler()
         .build();
 
@@ -0,0 +1,207 @@
+use crate::db;
+use crate::llm::context::estimate_tokens;
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, HashMap};
+
+/// Largest chunk embedded as one vector
+pub const MAX_CHUNK_TOKENS: usize = 256;
+/// Text repeated at the start of the next chunk, so a thought split across
+/// chunks is still whole in one of them
+pub const OVERLAP_TOKENS: usize = 48;
+/// Chunks on each side of a found chunk added back when building a snippet
+const NEIGHBOUR_RADIUS: i64 = 1;
+
+/// Split text into chunks of at most `MAX_CHUNK_TOKENS` along sentence boundaries,
+/// each starting with up to `OVERLAP_TOKENS` of the previous one. Short text is one chunk.
+pub fn split(text: &str) -> Vec<String> {
+    split_with(text, MAX_CHUNK_TOKENS, OVERLAP_TOKENS)
+}
+
+fn split_with(text: &str, max_tokens: usize, overlap_tokens: usize) -> Vec<String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Vec::new();
+    }
+    if estimate_tokens(text) <= max_tokens {
+        return vec![text.to_string()];
+    }
+
+    let pieces = pieces(text, max_tokens);
+    let span = |from: usize, to: usize| &text[pieces[from].0..pieces[to].1];
+    let mut chunks = Vec::new();
+    let mut first = 0;
+    loop {
+        let mut last = first;
+        while last + 1 < pieces.len() && estimate_tokens(span(first, last + 1)) <= max_tokens {
+            last += 1;
+        }
+        chunks.push(span(first, last).to_string());
+        if last + 1 == pieces.len() {
+            break;
+        }
+
+        // Carry trailing pieces over while they fit the overlap and leave room for new text
+        let mut next = last + 1;
+        while next > first + 1
+            && estimate_tokens(span(next - 1, last)) <= overlap_tokens
+            && estimate_tokens(span(next - 1, last + 1)) <= max_tokens
+        {
+            next -= 1;
+        }
+        first = next;
+    }
+    chunks
+}
+
+/// Byte ranges of sentences; sentences longer than `max_tokens` are cut between words
+fn pieces(text: &str, max_tokens: usize) -> Vec<(usize, usize)> {
+    let mut pieces = Vec::new();
+    for (start, end) in sentences(text) {
+        if estimate_tokens(&text[start..end]) <= max_tokens {
+            pieces.push((start, end));
+            continue;
+        }
+        let mut from = start;
+        let mut to = start;
+        for (word_start, word_end) in words(text, start, end) {
+            if to > from && estimate_tokens(&text[from..word_end]) > max_tokens {
+                pieces.push((from, to));
+                from = word_start;
+            }
+            to = word_end;
+        }
+        pieces.push((from, to));
+    }
+    pieces
+}
+
+/// Byte ranges of sentences: text up to `.`, `!`, `?` or `…` followed by whitespace, or a line break
+fn sentences(text: &str) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let end = i + c.len_utf8();
+        let at_end = chars.peek().map_or(true, |&(_, next)| next.is_whitespace());
+        if c == '\n' || (matches!(c, '.' | '!' | '?' | '…') && at_end) {
+            push_trimmed(text, start, end, &mut ranges);
+            start = end;
+        }
+    }
+    push_trimmed(text, start, text.len(), &mut ranges);
+    ranges
+}
+
+fn words(text: &str, start: usize, end: usize) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut word_start = None;
+    for (i, c) in text[start..end].char_indices() {
+        match (c.is_whitespace(), word_start) {
+            (false, None) => word_start = Some(start + i),
+            (true, Some(ws)) => {
+                ranges.push((ws, start + i));
+                word_start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(ws) = word_start {
+        ranges.push((ws, end));
+    }
+    ranges
+}
+
+fn push_trimmed(text: &str, start: usize, end: usize, ranges: &mut Vec<(usize, usize)>) {
+    let slice = &text[start..end];
+    let trimmed = slice.trim();
+    if !trimmed.is_empty() {
+        let from = start + (slice.len() - slice.trim_start().len());
+        ranges.push((from, from + trimmed.len()));
+    }
+}
+
+/// Join consecutive chunks, dropping the overlap the second one repeats
+pub fn join_overlapping(a: &str, b: &str) -> String {
+    for (start, _) in a.char_indices() {
+        if start > 0 && !a[..start].ends_with(char::is_whitespace) {
+            continue;
+        }
+        let tail = &a[start..];
+        if let Some(rest) = b.strip_prefix(tail) {
+            if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+                return format!("{}{}", a, rest);
+            }
+        }
+    }
+    format!("{} {}", a, b)
+}
+
+/// Readable snippets for found chunks, in the order given: each chunk is widened with
+/// its neighbours from the same message, and chunks of one message become one snippet.
+pub async fn snippets(pool: &SqlitePool, chunk_ids: &[i64]) -> Result<Vec<String>, sqlx::Error> {
+    let rows = db::get_chunk_neighbours(pool, chunk_ids, NEIGHBOUR_RADIUS).await?;
+
+    let message_of: HashMap<i64, i64> = rows.iter().map(|row| (row.origin_id, row.message_id)).collect();
+    let mut messages: Vec<i64> = Vec::new();
+    for id in chunk_ids {
+        if let Some(&message_id) = message_of.get(id) {
+            if !messages.contains(&message_id) {
+                messages.push(message_id);
+            }
+        }
+    }
+
+    let mut parts: HashMap<i64, BTreeMap<i64, String>> = HashMap::new();
+    for row in rows {
+        parts.entry(row.message_id).or_default().insert(row.chunk_index, row.text);
+    }
+    Ok(messages.iter().filter_map(|id| parts.get(id)).map(assemble).collect())
+}
+
+/// Glue a message's chunks back together; gaps between non-adjacent chunks become "…"
+fn assemble(parts: &BTreeMap<i64, String>) -> String {
+    let mut snippet = String::new();
+    let mut previous: Option<i64> = None;
+    for (&index, text) in parts {
+        snippet = match previous {
+            None => text.clone(),
+            Some(p) if p + 1 == index => join_overlapping(&snippet, text),
+            Some(_) => format!("{} … {}", snippet, text),
+        };
+        previous = Some(index);
+    }
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_respects_limits_and_overlaps() {
+        let text = (1..=40)
+            .map(|i| format!("Sentence number {} talks about topic {}.", i, i * 7))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let chunks = split_with(&text, 40, 12);
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| estimate_tokens(c) <= 40));
+        for pair in chunks.windows(2) {
+            let last_sentence = pair[0].rsplit(". ").next().unwrap();
+            assert!(pair[1].starts_with(last_sentence), "{:?} / {:?}", pair[0], pair[1]);
+        }
+
+        let joined = chunks.iter().skip(1).fold(chunks[0].clone(), |acc, c| join_overlapping(&acc, c));
+        assert_eq!(joined, text);
+    }
+
+    #[test]
+    fn test_split_cuts_long_sentences_between_words() {
+        let text = "слово ".repeat(200);
+        let chunks = split_with(&text, 50, 10);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| estimate_tokens(c) <= 50 && !c.starts_with(' ')));
+        assert_eq!(split_with("Коротко.", 50, 10), vec!["Коротко.".to_string()]);
+    }
+}
@@ -0,0 +1,276 @@
+use super::jobs::{self, Job};
+use crate::db::{self, DbMessage, UserFact};
+use crate::llm::client::ChatMessage;
+use crate::llm::context::SpeakerFacts;
+use crate::llm::queue::Priority;
+use crate::state::AppState;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+/// Facts about the person being answered that go into the prompt, most confident first
+pub const PROMPT_FACTS: u32 = 10;
+/// Facts kept per user in a chat; the least confident are dropped first
+const MAX_FACTS_PER_USER: u32 = 40;
+/// Messages handed to the model per extraction
+const BATCH_MESSAGES: u32 = 50;
+/// Facts the model is less sure about are not stored
+const MIN_CONFIDENCE: f64 = 0.3;
+/// Confidence gained when a stored fact is stated again
+const CONFIRM_BONUS: f64 = 0.1;
+/// Source message ids kept per fact
+const MAX_SOURCES: usize = 20;
+const MAX_KEY_CHARS: usize = 40;
+const MAX_FACT_CHARS: usize = 200;
+/// Longer messages are cut before extraction
+const MAX_MESSAGE_CHARS: usize = 600;
+const EXTRACTION_MAX_TOKENS: u32 = 1024;
+const EXTRACTION_TEMPERATURE: f64 = 0.1;
+
+const EXTRACTION_PROMPT: &str = "Ты извлекаешь из переписки устойчивые факты об участниках чата: \
+    работа, учёба, город, семья, питомцы, увлечения, вкусы, планы, отношение к чему-либо. \
+    Не записывай сиюминутное (настроение, что человек делает прямо сейчас), догадки и то, что говорят о других. \
+    Ответь JSON-объектом вида {\"facts\": [{\"user_id\": 123, \"key\": \"работа\", \"fact\": \"работает бэкендером\", \
+    \"confidence\": 0.9, \"sources\": [17, 18]}]}. \
+    key — тема в 1–2 слова в нижнем регистре; если факт уточняет или опровергает уже известный, возьми его key. \
+    fact — короткая фраза на языке переписки, без имени. confidence — от 0 до 1, насколько явно факт следует из сообщений. \
+    sources — номера сообщений, из которых он взят. Если новых фактов нет, верни {\"facts\": []}.";
+
+#[derive(Deserialize)]
+struct Extraction {
+    #[serde(default)]
+    facts: Vec<ExtractedFact>,
+}
+
+#[derive(Deserialize)]
+struct ExtractedFact {
+    user_id: i64,
+    key: String,
+    fact: String,
+    #[serde(default = "default_confidence")]
+    confidence: f64,
+    #[serde(default)]
+    sources: Vec<i64>,
+}
+
+fn default_confidence() -> f64 {
+    0.5
+}
+
+/// Queue a fact extraction once `fact_threshold` messages have arrived since the
+/// last one. A threshold of 0 turns extraction off.
+pub async fn maybe_extract(state: &AppState, chat_id: i64) {
+    let threshold = db::get_config_u32(&state.db_pool, "fact_threshold", state.config.fact_threshold).await;
+    if threshold == 0 {
+        return;
+    }
+    let Ok(after) = db::get_fact_progress(&state.db_pool, chat_id).await else {
+        return;
+    };
+    match db::count_messages_after(&state.db_pool, chat_id, after).await {
+        Ok(count) if count >= threshold as i64 => {}
+        _ => return,
+    }
+    if let Err(e) = jobs::enqueue(state, &Job::ExtractFacts { chat_id }).await {
+        tracing::warn!(target: "db", "Failed to queue fact extraction for chat {}: {}", chat_id, e);
+    }
+}
+
+/// Extract facts from the next batch of unseen messages; returns how many facts were stored.
+/// Does nothing if an extraction is already running for the chat.
+pub async fn extract_facts(state: &AppState, chat_id: i64) -> Result<usize, String> {
+    if !state.extracting_facts.lock().unwrap().insert(chat_id) {
+        return Ok(0);
+    }
+    let result = run_extraction(state, chat_id).await;
+    state.extracting_facts.lock().unwrap().remove(&chat_id);
+    result
+}
+
+/// What is known about the user being answered, or `None` if nothing is
+pub async fn speaker_facts(state: &AppState, chat_id: i64, user_id: i64, name: &str) -> Option<SpeakerFacts> {
+    let facts = match db::get_user_facts(&state.db_pool, chat_id, user_id, PROMPT_FACTS).await {
+        Ok(facts) => facts,
+        Err(e) => {
+            tracing::warn!(target: "db", "Failed to load facts about user {}: {}", user_id, e);
+            return None;
+        }
+    };
+    if facts.is_empty() {
+        return None;
+    }
+    Some(SpeakerFacts {
+        name: name.to_string(),
+        facts: facts.iter().map(|fact| format!("{}: {}", fact.key, fact.fact)).collect(),
+    })
+}
+
+async fn run_extraction(state: &AppState, chat_id: i64) -> Result<usize, String> {
+    let pool = &state.db_pool;
+    let after = db::get_fact_progress(pool, chat_id).await.map_err(|e| e.to_string())?;
+    let messages = db::get_messages_after(pool, chat_id, after, BATCH_MESSAGES).await.map_err(|e| e.to_string())?;
+    let Some(last) = messages.last() else {
+        return Ok(0);
+    };
+    let last_id = last.id;
+
+    // Facts are only kept about people who wrote in this batch, never about the bot.
+    // Until the bot knows its own id its replies would pass for a user's, so retry later.
+    let Some(bot_id) = state.bot_info.lock().await.as_ref().map(|info| info.id as i64) else {
+        return Err("bot info is not loaded yet".to_string());
+    };
+    let mut authors: Vec<(i64, String)> = Vec::new();
+    for message in &messages {
+        if let Some(user_id) = message.user_id.filter(|id| *id != bot_id) {
+            if !authors.iter().any(|(id, _)| *id == user_id) {
+                authors.push((user_id, message.username.clone().unwrap_or_else(|| user_id.to_string())));
+            }
+        }
+    }
+    if authors.is_empty() {
+        db::set_fact_progress(pool, chat_id, last_id).await.map_err(|e| e.to_string())?;
+        return Ok(0);
+    }
+
+    // Stored facts are the only per-user context. The /dossier helpers (`get_user_dossier`,
+    // `get_user_recent_messages`) span every chat, and seeding from them would let what
+    // someone said in one chat end up as a fact in another.
+    let mut known: HashMap<i64, Vec<UserFact>> = HashMap::new();
+    for (user_id, _) in &authors {
+        let facts = db::get_user_facts(pool, chat_id, *user_id, MAX_FACTS_PER_USER).await.map_err(|e| e.to_string())?;
+        known.insert(*user_id, facts);
+    }
+
+    let mut prompt = String::from("Участники:\n");
+    for (user_id, name) in &authors {
+        prompt.push_str(&format!("- user_id {} ({})\n", user_id, name));
+        for fact in &known[user_id] {
+            prompt.push_str(&format!("  уже известно — {}: {}\n", fact.key, fact.fact));
+        }
+    }
+    prompt.push_str("\nСообщения:\n");
+    for message in &messages {
+        prompt.push_str(&format_message(message, bot_id));
+        prompt.push('\n');
+    }
+
+    let request = [ChatMessage::system(EXTRACTION_PROMPT), ChatMessage::user(prompt)];
+    let model = state.chat_model().await;
+
+    // Lowest priority: replies to people always go first
+    let permit = state.acquire_llm_permit(Priority::Background).await.map_err(|e| e.to_string())?;
+    let reply = state.llm_client.chat_json(&model, &request, EXTRACTION_TEMPERATURE, EXTRACTION_MAX_TOKENS).await;
+    drop(permit);
+    let reply = reply.map_err(|e| e.to_string())?;
+
+    let extraction: Extraction = match serde_json::from_str(reply.trim()) {
+        Ok(extraction) => extraction,
+        Err(e) => {
+            // Skip the batch rather than retrying the same unparseable answer forever
+            db::set_fact_progress(pool, chat_id, last_id).await.map_err(|e| e.to_string())?;
+            return Err(format!("bad JSON from model: {}", e));
+        }
+    };
+
+    let batch_ids: HashSet<i64> = messages.iter().map(|m| m.id).collect();
+    let mut stored = 0;
+    for extracted in extraction.facts {
+        let Some(facts) = known.get(&extracted.user_id) else {
+            continue;
+        };
+        let key = normalize_key(&extracted.key);
+        let fact = clip(extracted.fact.trim(), MAX_FACT_CHARS);
+        let confidence = extracted.confidence.clamp(0.0, 1.0);
+        if key.is_empty() || fact.is_empty() || confidence < MIN_CONFIDENCE {
+            continue;
+        }
+        let sources: Vec<i64> = extracted.sources.into_iter().filter(|id| batch_ids.contains(id)).collect();
+        let existing = facts.iter().find(|f| f.key == key);
+        let (confidence, sources) = merge(existing, &fact, confidence, sources);
+
+        if db::upsert_user_fact(pool, chat_id, extracted.user_id, &key, &fact, confidence, &sources)
+            .await
+            .map_err(|e| e.to_string())?
+        {
+            stored += 1;
+        }
+    }
+    for (user_id, _) in &authors {
+        db::trim_user_facts(pool, chat_id, *user_id, MAX_FACTS_PER_USER).await.map_err(|e| e.to_string())?;
+    }
+    db::set_fact_progress(pool, chat_id, last_id).await.map_err(|e| e.to_string())?;
+
+    tracing::info!(target: "memory", "Chat {}: stored {} facts from {} messages", chat_id, stored, messages.len());
+    Ok(stored)
+}
+
+/// Confidence and sources for a new fact under a key that may already hold one.
+/// Restating the stored fact raises its confidence; anything else replaces it.
+fn merge(existing: Option<&UserFact>, fact: &str, confidence: f64, sources: Vec<i64>) -> (f64, Vec<i64>) {
+    match existing {
+        Some(old) if old.fact.trim().to_lowercase() == fact.to_lowercase() => {
+            let confidence = (old.confidence.max(confidence) + CONFIRM_BONUS).min(1.0);
+            let mut merged = old.source_message_ids.clone();
+            merged.extend(sources.into_iter().filter(|id| !old.source_message_ids.contains(id)));
+            let skip = merged.len().saturating_sub(MAX_SOURCES);
+            (confidence, merged.into_iter().skip(skip).collect())
+        }
+        _ => (confidence, sources),
+    }
+}
+
+fn normalize_key(key: &str) -> String {
+    let key = key.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+    clip(&key, MAX_KEY_CHARS)
+}
+
+fn clip(text: &str, max_chars: usize) -> String {
+    if text.chars().count() > max_chars {
+        text.chars().take(max_chars).chain("…".chars()).collect()
+    } else {
+        text.to_string()
+    }
+}
+
+fn format_message(message: &DbMessage, bot_id: i64) -> String {
+    let text = clip(message.text.as_deref().unwrap_or("").trim(), MAX_MESSAGE_CHARS);
+    match message.user_id {
+        Some(user_id) if user_id != bot_id => {
+            let author = message.username.as_deref().unwrap_or("?");
+            format!("#{} [user_id {}] {}: {}", message.id, user_id, author, text)
+        }
+        _ => format!("#{} [бот]: {}", message.id, text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stored(fact: &str, confidence: f64, sources: Vec<i64>) -> UserFact {
+        UserFact {
+            id: 1,
+            chat_id: 1,
+            user_id: 7,
+            key: "работа".to_string(),
+            fact: fact.to_string(),
+            confidence,
+            source_message_ids: sources,
+            updated_at: chrono::Utc::now().naive_utc(),
+        }
+    }
+
+    #[test]
+    fn test_merge_confirms_or_overrides() {
+        let old = stored("работает бэкендером", 0.6, vec![1, 2]);
+
+        let (confidence, sources) = merge(Some(&old), "Работает бэкендером", 0.5, vec![2, 9]);
+        assert!((confidence - 0.7).abs() < 1e-9);
+        assert_eq!(sources, vec![1, 2, 9]);
+
+        let (confidence, sources) = merge(Some(&old), "работает тимлидом", 0.8, vec![12]);
+        assert!((confidence - 0.8).abs() < 1e-9);
+        assert_eq!(sources, vec![12]);
+
+        assert_eq!(normalize_key("  Любимый   ЯЗЫК "), "любимый язык");
+    }
+}
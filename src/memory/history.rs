@@ -0,0 +1,211 @@
+use crate::db::{self, DbMessage};
+use sqlx::SqlitePool;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use teloxide::types::{ChatId, Message, ThreadId};
+
+/// Most turns kept per chat/thread; a larger `context_depth` gets this many
+pub const MAX_TURNS: usize = 50;
+/// Chats/threads kept in memory; the least recently used are evicted
+const MAX_CACHED: usize = 1000;
+
+/// A chat, or a forum thread within it
+pub type HistoryKey = (ChatId, Option<ThreadId>);
+
+/// One message of the short-term history
+#[derive(Clone, Debug)]
+pub struct Turn {
+    /// Telegram message id, unique within the chat
+    pub message_id: i64,
+    pub user_id: Option<i64>,
+    pub from_bot: bool,
+    pub name: String,
+    pub text: String,
+}
+
+impl Turn {
+    /// A turn for a live message; `text` may differ from the message text (media descriptions)
+    pub fn from_message(msg: &Message, text: &str, bot_id: Option<i64>) -> Self {
+        let user = msg.from.as_ref();
+        let user_id = user.map(|u| u.id.0 as i64);
+        Self {
+            message_id: msg.id.0 as i64,
+            user_id,
+            from_bot: is_own(user_id, bot_id),
+            name: user.map(|u| u.full_name()).unwrap_or_default(),
+            text: text.to_string(),
+        }
+    }
+
+    fn from_db(msg: DbMessage, bot_id: Option<i64>) -> Self {
+        Self {
+            message_id: msg.message_id,
+            user_id: msg.user_id,
+            from_bot: is_own(msg.user_id, bot_id),
+            name: msg.username.unwrap_or_default(),
+            text: msg.text.unwrap_or_default(),
+        }
+    }
+}
+
+/// Only this bot's own messages are assistant turns; other bots and anonymous
+/// senders (channels, anonymous admins) are chat members like everyone else
+fn is_own(user_id: Option<i64>, bot_id: Option<i64>) -> bool {
+    user_id.is_some() && user_id == bot_id
+}
+
+struct Entry {
+    turns: VecDeque<Turn>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Cache {
+    entries: HashMap<HistoryKey, Entry>,
+    clock: u64,
+}
+
+impl Cache {
+    fn touch(&mut self, key: &HistoryKey) -> Option<&mut Entry> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(key).map(|entry| {
+            entry.last_used = clock;
+            entry
+        })
+    }
+
+    fn insert(&mut self, key: HistoryKey, turns: VecDeque<Turn>) {
+        if !self.entries.contains_key(&key) && self.entries.len() >= MAX_CACHED {
+            let idle = self.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(key, _)| *key);
+            if let Some(idle) = idle {
+                self.entries.remove(&idle);
+            }
+        }
+        self.clock += 1;
+        self.entries.insert(key, Entry { turns, last_used: self.clock });
+    }
+}
+
+/// Append a turn; a message already there (saved twice, or edited) only gets the new text
+fn push(turns: &mut VecDeque<Turn>, turn: Turn) {
+    if let Some(existing) = turns.iter_mut().find(|t| t.message_id == turn.message_id) {
+        existing.text = turn.text;
+        return;
+    }
+    turns.push_back(turn);
+    while turns.len() > MAX_TURNS {
+        turns.pop_front();
+    }
+}
+
+/// Short-term dialogue history per chat and forum thread.
+///
+/// A cache over the `messages` table: a chat/thread not in memory (after a restart
+/// or eviction) is rebuilt from SQLite on first use, and messages saved while it is
+/// cached are appended to it.
+#[derive(Clone, Default)]
+pub struct DialogueHistory {
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl DialogueHistory {
+    /// Append a message to its chat/thread history, or update it if edited,
+    /// when that history is loaded
+    pub fn record(&self, key: HistoryKey, turn: Turn) {
+        if let Some(entry) = self.cache.lock().unwrap().touch(&key) {
+            push(&mut entry.turns, turn);
+        }
+    }
+
+    /// The last `depth` turns of a chat/thread ending with `latest`, oldest first;
+    /// loaded from SQLite if not cached
+    pub async fn recent(
+        &self,
+        pool: &SqlitePool,
+        key: HistoryKey,
+        latest: Turn,
+        depth: usize,
+        bot_id: Option<i64>,
+    ) -> Vec<Turn> {
+        let cached = self.cache.lock().unwrap().touch(&key).is_some();
+        if !cached {
+            let thread_id = key.1.map(|t| t.0 .0 as i64);
+            let turns = match db::get_thread_history(pool, key.0 .0, thread_id, MAX_TURNS as u32).await {
+                Ok(messages) => messages.into_iter().map(|m| Turn::from_db(m, bot_id)).collect(),
+                Err(e) => {
+                    tracing::warn!(target: "db", "Failed to load history for chat {}: {}", key.0, e);
+                    VecDeque::new()
+                }
+            };
+            let mut cache = self.cache.lock().unwrap();
+            if cache.touch(&key).is_none() {
+                cache.insert(key, turns);
+            }
+        }
+
+        let mut cache = self.cache.lock().unwrap();
+        let Some(entry) = cache.touch(&key) else {
+            return vec![latest];
+        };
+        push(&mut entry.turns, latest);
+        let skip = entry.turns.len().saturating_sub(depth.max(1));
+        entry.turns.iter().skip(skip).cloned().collect()
+    }
+
+    /// Drop every cached thread of a chat; they are rebuilt from SQLite on next use
+    pub fn invalidate(&self, chat_id: i64) {
+        self.cache.lock().unwrap().entries.retain(|key, _| key.0 .0 != chat_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turn(message_id: i64) -> Turn {
+        Turn { message_id, user_id: Some(1), from_bot: false, name: "Оля".to_string(), text: message_id.to_string() }
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used_and_dedups() {
+        let mut cache = Cache::default();
+        for chat in 0..MAX_CACHED as i64 {
+            cache.insert((ChatId(chat), None), VecDeque::new());
+        }
+        cache.touch(&(ChatId(0), None));
+        cache.insert((ChatId(-1), None), VecDeque::new());
+
+        assert_eq!(cache.entries.len(), MAX_CACHED);
+        assert!(cache.entries.contains_key(&(ChatId(0), None)));
+        assert!(!cache.entries.contains_key(&(ChatId(1), None)));
+
+        let mut turns = VecDeque::new();
+        for id in 0..MAX_TURNS as i64 + 5 {
+            push(&mut turns, turn(id));
+        }
+        let mut edited = turn(MAX_TURNS as i64);
+        edited.text = "исправлено".to_string();
+        push(&mut turns, edited);
+        assert_eq!(turns.len(), MAX_TURNS);
+        assert_eq!(turns.front().unwrap().message_id, 5);
+        assert!(turns.iter().any(|t| t.text == "исправлено"));
+    }
+
+    #[test]
+    fn test_only_own_messages_are_bot_turns() {
+        let message = |user_id: Option<i64>| DbMessage {
+            id: 1,
+            message_id: 1,
+            chat_id: -10,
+            user_id,
+            username: None,
+            text: None,
+            sent_at: chrono::Utc::now().naive_utc(),
+        };
+        assert!(Turn::from_db(message(Some(7)), Some(7)).from_bot);
+        assert!(!Turn::from_db(message(Some(8)), Some(7)).from_bot);
+        assert!(!Turn::from_db(message(None), Some(7)).from_bot);
+        assert!(!Turn::from_db(message(None), None).from_bot);
+    }
+}
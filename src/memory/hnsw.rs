@@ -0,0 +1,282 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+
+/// Links per node on upper layers; layer 0 keeps twice as many
+const M: usize = 16;
+const EF_CONSTRUCTION: usize = 100;
+
+/// Hierarchical navigable small world graph over cosine similarity.
+///
+/// Vectors are stored normalized as `f32`, so similarity is a dot product.
+/// Nodes can only be added; deleted chunks are dropped by rebuilding.
+pub struct Hnsw {
+    dim: usize,
+    nodes: Vec<Node>,
+    ids: HashSet<i64>,
+    entry: Option<usize>,
+    rng: u64,
+}
+
+struct Node {
+    id: i64,
+    vector: Vec<f32>,
+    /// Neighbours per layer, `links[0]` is the bottom layer
+    links: Vec<Vec<u32>>,
+}
+
+/// Candidate during search, ordered by similarity
+#[derive(Clone, Copy)]
+struct Scored {
+    sim: f32,
+    node: u32,
+}
+
+impl PartialEq for Scored {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sim.total_cmp(&other.sim).then(self.node.cmp(&other.node))
+    }
+}
+
+impl Hnsw {
+    pub fn new(dim: usize) -> Self {
+        Self {
+            dim,
+            nodes: Vec::new(),
+            ids: HashSet::new(),
+            entry: None,
+            rng: 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Add a vector; ignored if `id` is already indexed or the dimension differs
+    pub fn insert(&mut self, id: i64, vector: &[f64]) -> bool {
+        if vector.len() != self.dim || self.ids.contains(&id) {
+            return false;
+        }
+        let vector = normalize(vector);
+        let level = self.random_level();
+        let index = self.nodes.len() as u32;
+        self.nodes.push(Node { id, vector, links: vec![Vec::new(); level + 1] });
+        self.ids.insert(id);
+
+        let Some(entry) = self.entry else {
+            self.entry = Some(index as usize);
+            return true;
+        };
+
+        let query = self.nodes[index as usize].vector.clone();
+        let top = self.nodes[entry].links.len() - 1;
+        let mut current = Scored { sim: dot(&query, &self.nodes[entry].vector), node: entry as u32 };
+
+        // Greedy descent through the layers above the new node
+        for layer in (level + 1..=top).rev() {
+            current = self.greedy(&query, current, layer);
+        }
+
+        let mut entry_points = vec![current];
+        for layer in (0..=level.min(top)).rev() {
+            let found = self.search_layer(&query, &entry_points, EF_CONSTRUCTION, layer);
+            let neighbours: Vec<u32> = found.iter().take(M).map(|s| s.node).collect();
+            self.nodes[index as usize].links[layer] = neighbours.clone();
+
+            let max_links = if layer == 0 { M * 2 } else { M };
+            for neighbour in neighbours {
+                self.link(neighbour, index, layer, max_links);
+            }
+            entry_points = found;
+        }
+
+        if level > top {
+            self.entry = Some(index as usize);
+        }
+        true
+    }
+
+    /// Up to `k` nearest ids with their cosine similarity, best first.
+    /// `ef` (at least `k`) trades speed for recall.
+    pub fn search(&self, query: &[f64], k: usize, ef: usize) -> Vec<(i64, f64)> {
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+        if query.len() != self.dim || k == 0 {
+            return Vec::new();
+        }
+        let query = normalize(query);
+
+        let mut current = Scored { sim: dot(&query, &self.nodes[entry].vector), node: entry as u32 };
+        for layer in (1..self.nodes[entry].links.len()).rev() {
+            current = self.greedy(&query, current, layer);
+        }
+
+        self.search_layer(&query, &[current], ef.max(k), 0)
+            .into_iter()
+            .take(k)
+            .map(|s| (self.nodes[s.node as usize].id, s.sim as f64))
+            .collect()
+    }
+
+    /// Follow the best neighbour on `layer` until no neighbour is closer
+    fn greedy(&self, query: &[f32], mut current: Scored, layer: usize) -> Scored {
+        loop {
+            let mut improved = false;
+            for &neighbour in &self.nodes[current.node as usize].links[layer] {
+                let sim = dot(query, &self.nodes[neighbour as usize].vector);
+                if sim > current.sim {
+                    current = Scored { sim, node: neighbour };
+                    improved = true;
+                }
+            }
+            if !improved {
+                return current;
+            }
+        }
+    }
+
+    /// Beam search on one layer; returns up to `ef` nodes, best first
+    fn search_layer(&self, query: &[f32], entry_points: &[Scored], ef: usize, layer: usize) -> Vec<Scored> {
+        let mut visited: HashSet<u32> = entry_points.iter().map(|s| s.node).collect();
+        let mut candidates: BinaryHeap<Scored> = entry_points.iter().copied().collect();
+        // Min-heap of the best `ef` so far
+        let mut found: BinaryHeap<std::cmp::Reverse<Scored>> = entry_points.iter().map(|s| std::cmp::Reverse(*s)).collect();
+
+        while let Some(candidate) = candidates.pop() {
+            let worst = found.peek().map(|r| r.0.sim).unwrap_or(f32::MIN);
+            if candidate.sim < worst && found.len() >= ef {
+                break;
+            }
+            let Some(links) = self.nodes[candidate.node as usize].links.get(layer) else {
+                continue;
+            };
+            for &neighbour in links {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                let sim = dot(query, &self.nodes[neighbour as usize].vector);
+                let worst = found.peek().map(|r| r.0.sim).unwrap_or(f32::MIN);
+                if found.len() < ef || sim > worst {
+                    let scored = Scored { sim, node: neighbour };
+                    candidates.push(scored);
+                    found.push(std::cmp::Reverse(scored));
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+
+        let mut result: Vec<Scored> = found.into_iter().map(|r| r.0).collect();
+        result.sort_by(|a, b| b.cmp(a));
+        result
+    }
+
+    /// Link `from` to `to` on `layer`, keeping only the closest `max_links`
+    fn link(&mut self, from: u32, to: u32, layer: usize, max_links: usize) {
+        let links = &mut self.nodes[from as usize].links[layer];
+        links.push(to);
+        if links.len() <= max_links {
+            return;
+        }
+        let base = self.nodes[from as usize].vector.clone();
+        let mut scored: Vec<Scored> = self.nodes[from as usize].links[layer]
+            .iter()
+            .map(|&n| Scored { sim: dot(&base, &self.nodes[n as usize].vector), node: n })
+            .collect();
+        scored.sort_by(|a, b| b.cmp(a));
+        scored.truncate(max_links);
+        self.nodes[from as usize].links[layer] = scored.into_iter().map(|s| s.node).collect();
+    }
+
+    /// Exponentially distributed level with factor 1/ln(M); deterministic per index
+    fn random_level(&mut self) -> usize {
+        // xorshift64*
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let bits = self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11;
+        let uniform = (bits as f64 + 1.0) / (1u64 << 53) as f64;
+        ((-uniform.ln()) / (M as f64).ln()) as usize
+    }
+}
+
+fn normalize(vector: &[f64]) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm == 0.0 {
+        return vec![0.0; vector.len()];
+    }
+    vector.iter().map(|x| (x / norm) as f32).collect()
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic pseudo-random vectors; different seeds give unrelated sets
+    fn vectors(seed: u64, count: usize, dim: usize) -> Vec<Vec<f64>> {
+        let mut state = seed;
+        (0..count)
+            .map(|_| {
+                (0..dim)
+                    .map(|_| {
+                        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                        ((state >> 33) as f64 / (1u64 << 31) as f64) - 0.5
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_recall_against_exact() {
+        let data = vectors(42, 2000, 32);
+        let mut index = Hnsw::new(32);
+        for (i, v) in data.iter().enumerate() {
+            assert!(index.insert(i as i64, v));
+        }
+        assert!(!index.insert(0, &data[0]));
+
+        let mut hits = 0;
+        // Not the first data points, which would be found exactly
+        let queries = vectors(7, 20, 32);
+        for query in &queries {
+            let q = normalize(query);
+            let mut exact: Vec<(i64, f32)> = data.iter().enumerate().map(|(i, v)| (i as i64, dot(&q, &normalize(v)))).collect();
+            exact.sort_by(|a, b| b.1.total_cmp(&a.1));
+            let expected: HashSet<i64> = exact.iter().take(10).map(|(id, _)| *id).collect();
+
+            let found = index.search(query, 10, 64);
+            hits += found.iter().filter(|(id, _)| expected.contains(id)).count();
+        }
+        // Recall@10 well above 90% on uniform data
+        assert!(hits >= queries.len() * 10 * 9 / 10, "recall too low: {}", hits);
+    }
+}
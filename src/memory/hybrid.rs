@@ -0,0 +1,109 @@
+use super::index;
+use crate::db::{self, ScoredChunk};
+use crate::state::AppState;
+use std::collections::HashMap;
+
+/// Candidates taken from each ranking before fusion
+const CANDIDATES: u32 = 20;
+/// Reciprocal rank fusion constant; damps the weight of the very top ranks
+const RRF_K: f64 = 60.0;
+/// Most terms put into one full-text query
+const MAX_TERMS: usize = 16;
+
+/// Words that match almost everything and only add noise to BM25
+const STOPWORDS: &[&str] = &[
+    "что", "как", "это", "так", "там", "тут", "вот", "все", "всё", "уже", "еще", "ещё", "или", "если",
+    "его", "она", "они", "оно", "мне", "меня", "тебя", "тебе", "нас", "вас", "был", "была", "было",
+    "для", "при", "про", "над", "под", "без", "кто", "где", "когда", "тоже", "только", "очень",
+    "the", "and", "for", "are", "was", "you", "your", "that", "this", "with", "what", "have", "not",
+];
+
+/// FTS5 query for free text: significant words OR-ed together, longer words
+/// matched by prefix as a crude stand-in for stemming. `None` if nothing is left.
+pub fn fts_query(text: &str) -> Option<String> {
+    let mut terms: Vec<String> = Vec::new();
+    for word in text.split(|c: char| !c.is_alphanumeric()) {
+        let word = word.to_lowercase();
+        let len = word.chars().count();
+        let has_digit = word.chars().any(|c| c.is_ascii_digit());
+        if (len < 3 && !has_digit) || STOPWORDS.contains(&word.as_str()) {
+            continue;
+        }
+        let term = if len >= 6 && !has_digit {
+            let stem: String = word.chars().take(len - 2).collect();
+            format!("\"{}\"*", stem)
+        } else {
+            format!("\"{}\"", word)
+        };
+        if !terms.contains(&term) {
+            terms.push(term);
+        }
+        if terms.len() == MAX_TERMS {
+            break;
+        }
+    }
+    (!terms.is_empty()).then(|| terms.join(" OR "))
+}
+
+/// Memories ranked by fusing the vector ranking (similarity × decay × importance)
+/// with the BM25 full-text ranking via reciprocal rank fusion, best first.
+///
+/// Exact names, numbers and rare words surface through BM25 even when their
+/// embedding similarity is low.
+pub async fn find_hybrid(
+    state: &AppState,
+    chat_id: i64,
+    model: &str,
+    query_text: &str,
+    query_embedding: &[f64],
+    limit: u32,
+    decay_rate: f64,
+) -> Result<Vec<ScoredChunk>, sqlx::Error> {
+    let vector = index::find_similar(state, chat_id, model, query_embedding, CANDIDATES, decay_rate).await?;
+    let lexical = match fts_query(query_text) {
+        Some(query) => db::search_chunks_fts(&state.db_pool, chat_id, &query, CANDIDATES).await.unwrap_or_else(|e| {
+            tracing::warn!(target: "rag", "Full-text search failed: {}", e);
+            Vec::new()
+        }),
+        None => Vec::new(),
+    };
+
+    let mut chunks: HashMap<i64, ScoredChunk> = HashMap::new();
+    for (rank, mut chunk) in vector.into_iter().enumerate() {
+        chunk.fused = Some(rrf(rank));
+        chunks.insert(chunk.id, chunk);
+    }
+
+    let missing: Vec<i64> = lexical.iter().copied().filter(|id| !chunks.contains_key(id)).collect();
+    for chunk in db::score_chunks_by_id(&state.db_pool, &missing, model, query_embedding, decay_rate).await? {
+        chunks.insert(chunk.id, chunk);
+    }
+    for (rank, id) in lexical.iter().enumerate() {
+        if let Some(chunk) = chunks.get_mut(id) {
+            chunk.lexical_rank = Some(rank + 1);
+            chunk.fused = Some(chunk.fused.unwrap_or(0.0) + rrf(rank));
+        }
+    }
+
+    let mut fused: Vec<ScoredChunk> = chunks.into_values().collect();
+    fused.sort_by(|a, b| b.fused.partial_cmp(&a.fused).unwrap_or(std::cmp::Ordering::Equal));
+    fused.truncate(limit as usize);
+    Ok(fused)
+}
+
+/// Contribution of a 0-based rank
+fn rrf(rank: usize) -> f64 {
+    1.0 / (RRF_K + rank as f64 + 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fts_query_keeps_names_and_numbers() {
+        let query = fts_query("Что там у Оли с кодом 4417, помнишь пятницу?").unwrap();
+        assert_eq!(query, "\"оли\" OR \"кодом\" OR \"4417\" OR \"помни\"* OR \"пятни\"*");
+        assert_eq!(fts_query("и что это?"), None);
+    }
+}
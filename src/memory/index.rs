@@ -0,0 +1,184 @@
+use super::hnsw::Hnsw;
+use crate::db::{self, ScoredChunk};
+use crate::state::AppState;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+/// Chats with fewer chunks are searched exactly; a full scan is fast enough there
+const ANN_MIN_CHUNKS: i64 = 500;
+/// Similarity candidates fetched per requested result, re-ranked by decay and importance
+const CANDIDATES_PER_RESULT: usize = 10;
+const MIN_CANDIDATES: usize = 100;
+const EF_SEARCH: usize = 128;
+
+enum ChatEntry {
+    /// Too few chunks for an index; counted up by inserts so searches skip the database count
+    Small { model: String, count: i64 },
+    /// Being built; chunks saved meanwhile are buffered here
+    Loading { model: String, pending: Vec<(i64, Vec<f64>)> },
+    Ready { model: String, index: Arc<RwLock<Hnsw>> },
+}
+
+/// In-memory ANN indexes of chunk embeddings, one per chat.
+///
+/// Loaded lazily from SQLite on the first search and kept up to date as chunks
+/// are saved. An index holds embeddings of one model and is rebuilt when the
+/// embedding model changes. Deleting chunks doesn't touch the graph: stale hits
+/// are dropped when scoring, and [`invalidate`](Self::invalidate) forces a rebuild.
+#[derive(Clone, Default)]
+pub struct VectorIndex {
+    chats: Arc<RwLock<HashMap<i64, ChatEntry>>>,
+    /// One build at a time per chat; other chats build in parallel
+    build_locks: Arc<std::sync::Mutex<HashMap<i64, Arc<tokio::sync::Mutex<()>>>>>,
+}
+
+impl VectorIndex {
+    /// Add a freshly saved chunk to its chat's index, if that index is loaded for `model`
+    pub fn insert(&self, chat_id: i64, model: &str, chunk_id: i64, embedding: &[f64]) {
+        let mut chats = self.chats.write().unwrap();
+        match chats.get_mut(&chat_id) {
+            Some(ChatEntry::Small { model: m, count }) if m == model => *count += 1,
+            Some(ChatEntry::Loading { model: m, pending }) if m == model => pending.push((chunk_id, embedding.to_vec())),
+            Some(ChatEntry::Ready { model: m, index }) if m == model => {
+                index.write().unwrap().insert(chunk_id, embedding);
+            }
+            _ => {}
+        }
+    }
+
+    /// Drop a chat's index; it is rebuilt on the next search
+    pub fn invalidate(&self, chat_id: i64) {
+        self.chats.write().unwrap().remove(&chat_id);
+    }
+
+    /// Drop all indexes
+    pub fn clear(&self) {
+        self.chats.write().unwrap().clear();
+    }
+
+    /// Loaded chat indexes and the vectors they hold
+    pub fn stats(&self) -> (usize, usize) {
+        let chats = self.chats.read().unwrap();
+        chats.values().fold((0, 0), |(loaded, vectors), entry| match entry {
+            ChatEntry::Ready { index, .. } => (loaded + 1, vectors + index.read().unwrap().len()),
+            ChatEntry::Loading { .. } => (loaded + 1, vectors),
+            ChatEntry::Small { .. } => (loaded, vectors),
+        })
+    }
+
+    /// The chat's index, built from SQLite if needed; `None` if the chat is small
+    /// enough for exact search
+    async fn get_or_build(&self, pool: &SqlitePool, chat_id: i64, model: &str) -> Result<Option<Arc<RwLock<Hnsw>>>, anyhow::Error> {
+        match self.cached(chat_id, model) {
+            Cached::Index(index) => return Ok(Some(index)),
+            Cached::Small => return Ok(None),
+            Cached::Missing => {}
+        }
+
+        let build_lock = self.build_locks.lock().unwrap().entry(chat_id).or_default().clone();
+        let _build = build_lock.lock().await;
+        match self.cached(chat_id, model) {
+            Cached::Index(index) => return Ok(Some(index)),
+            Cached::Small => return Ok(None),
+            Cached::Missing => {}
+        }
+        let count = db::count_chat_embeddings(pool, chat_id, model).await?;
+        if count < ANN_MIN_CHUNKS {
+            self.chats.write().unwrap().insert(chat_id, ChatEntry::Small { model: model.to_string(), count });
+            return Ok(None);
+        }
+
+        let loading = ChatEntry::Loading { model: model.to_string(), pending: Vec::new() };
+        self.chats.write().unwrap().insert(chat_id, loading);
+        let started = Instant::now();
+        let built = match db::get_chat_embeddings(pool, chat_id, model).await {
+            Ok(rows) => tokio::task::spawn_blocking(move || build(rows)).await.map_err(anyhow::Error::from),
+            Err(e) => Err(e.into()),
+        };
+
+        let mut chats = self.chats.write().unwrap();
+        let pending = match chats.remove(&chat_id) {
+            Some(ChatEntry::Loading { model: m, pending }) if m == model => pending,
+            // Invalidated while building
+            _ => return Ok(None),
+        };
+        let mut index = built?;
+        for (chunk_id, embedding) in pending {
+            index.insert(chunk_id, &embedding);
+        }
+
+        tracing::info!(
+            target: "rag",
+            "Built vector index for chat {}: {} chunks in {}ms",
+            chat_id,
+            index.len(),
+            started.elapsed().as_millis()
+        );
+        let index = Arc::new(RwLock::new(index));
+        chats.insert(chat_id, ChatEntry::Ready { model: model.to_string(), index: index.clone() });
+        Ok(Some(index))
+    }
+
+    fn cached(&self, chat_id: i64, model: &str) -> Cached {
+        match self.chats.read().unwrap().get(&chat_id) {
+            Some(ChatEntry::Ready { model: m, index }) if m == model => Cached::Index(index.clone()),
+            // Once inserts push the count over the threshold, the next search recounts and builds
+            Some(ChatEntry::Small { model: m, count }) if m == model && *count < ANN_MIN_CHUNKS => Cached::Small,
+            _ => Cached::Missing,
+        }
+    }
+}
+
+/// What a search can use without touching the database
+enum Cached {
+    Index(Arc<RwLock<Hnsw>>),
+    Small,
+    Missing,
+}
+
+fn build(rows: Vec<(i64, Vec<f64>)>) -> Hnsw {
+    let dim = rows.first().map_or(0, |(_, embedding)| embedding.len());
+    let mut index = Hnsw::new(dim);
+    for (chunk_id, embedding) in rows {
+        index.insert(chunk_id, &embedding);
+    }
+    index
+}
+
+/// Chunks of a chat most relevant to `query_embedding` by similarity × time decay × importance,
+/// best first. Only chunks embedded by `model` (the model of the query) are considered.
+///
+/// Uses the chat's ANN index when `vector_index_enabled` is on and the chat is large;
+/// otherwise, or if the index can't serve the query, falls back to an exact scan.
+pub async fn find_similar(
+    state: &AppState,
+    chat_id: i64,
+    model: &str,
+    query_embedding: &[f64],
+    limit: u32,
+    decay_rate: f64,
+) -> Result<Vec<ScoredChunk>, sqlx::Error> {
+    let enabled = db::get_config_bool(&state.db_pool, "vector_index_enabled", state.config.vector_index_enabled).await;
+    if enabled {
+        match state.vector_index.get_or_build(&state.db_pool, chat_id, model).await {
+            Ok(Some(index)) => {
+                let candidates = (limit as usize * CANDIDATES_PER_RESULT).max(MIN_CANDIDATES);
+                let hits = {
+                    let index = index.read().unwrap();
+                    (index.dim() == query_embedding.len())
+                        .then(|| index.search(query_embedding, candidates, EF_SEARCH.max(candidates)))
+                };
+                match hits {
+                    Some(hits) => return db::score_chunks(&state.db_pool, &hits, limit, decay_rate).await,
+                    None => tracing::debug!(target: "rag", "Vector index of chat {} has another dimension, searching exactly", chat_id),
+                }
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!(target: "rag", "Vector index for chat {} unavailable: {}", chat_id, e),
+        }
+    }
+
+    db::find_similar_chunks_with_decay(&state.db_pool, chat_id, model, query_embedding, limit, decay_rate).await
+}
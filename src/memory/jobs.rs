@@ -0,0 +1,193 @@
+use super::{chunker, facts, summarizer};
+use crate::db::{self, QueuedJob};
+use crate::state::AppState;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Attempts before a job is left failed
+const MAX_ATTEMPTS: u32 = 5;
+/// Delay before the first retry; doubles with every failed attempt
+const RETRY_BASE_SECS: u64 = 10;
+const RETRY_MAX_SECS: u64 = 60 * 60;
+/// How often idle workers look for jobs whose retry delay has passed
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// Messages per job queued by /backfill_embeddings
+pub const BACKFILL_BATCH: u32 = 32;
+
+/// Background work kept in the `jobs` table until it succeeds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Job {
+    /// Chunk and embed messages (row ids); `replace` drops existing chunks first,
+    /// otherwise messages that already have chunks are skipped
+    Embed { message_ids: Vec<i64>, replace: bool },
+    /// Summarize the oldest unsummarized messages of a chat
+    Summarize { chat_id: i64 },
+    /// Extract facts about chat members from the next batch of messages
+    ExtractFacts { chat_id: i64 },
+}
+
+impl Job {
+    fn kind(&self) -> &'static str {
+        match self {
+            Job::Embed { .. } => "embed",
+            Job::Summarize { .. } => "summarize",
+            Job::ExtractFacts { .. } => "extract_facts",
+        }
+    }
+
+    /// Jobs with the same key are queued once; a chat needs one summarization at a time
+    fn dedup_key(&self) -> Option<String> {
+        match self {
+            Job::Embed { .. } => None,
+            Job::Summarize { chat_id } => Some(format!("summarize:{}", chat_id)),
+            Job::ExtractFacts { chat_id } => Some(format!("facts:{}", chat_id)),
+        }
+    }
+}
+
+/// Queue a job and wake a worker; `false` if the same job is already queued
+pub async fn enqueue(state: &AppState, job: &Job) -> Result<bool, sqlx::Error> {
+    let payload = serde_json::to_string(job).expect("job serializes");
+    let dedup_key = job.dedup_key();
+    let queued = db::enqueue_job(&state.db_pool, job.kind(), &payload, dedup_key.as_deref(), MAX_ATTEMPTS).await?;
+    if queued {
+        state.job_signal.notify_one();
+    }
+    Ok(queued)
+}
+
+/// Start `job_workers` workers; jobs left running by a previous process are retried
+pub async fn start_workers(state: &AppState) {
+    match db::requeue_running_jobs(&state.db_pool).await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!(target: "memory", "Requeued {} interrupted jobs", count),
+        Err(e) => tracing::warn!(target: "memory", "Failed to requeue interrupted jobs: {}", e),
+    }
+    for worker in 0..state.config.job_workers.max(1) {
+        let state = state.clone();
+        tokio::spawn(async move { work(&state, worker).await });
+    }
+}
+
+async fn work(state: &AppState, worker: usize) {
+    loop {
+        let job = match db::claim_job(&state.db_pool).await {
+            Ok(Some(job)) => job,
+            Ok(None) => {
+                let _ = tokio::time::timeout(POLL_INTERVAL, state.job_signal.notified()).await;
+                continue;
+            }
+            Err(e) => {
+                tracing::warn!(target: "db", "Job worker {} failed to claim a job: {}", worker, e);
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue;
+            }
+        };
+        let result = match serde_json::from_str::<Job>(&job.payload) {
+            Ok(parsed) => run(state, &parsed).await,
+            Err(e) => Err(format!("bad payload: {}", e)),
+        };
+        if let Err(e) = settle(state, &job, result).await {
+            tracing::warn!(target: "db", "Failed to update job {}: {}", job.id, e);
+        }
+    }
+}
+
+/// Remove a finished job, or schedule a retry with exponential backoff until attempts run out
+async fn settle(state: &AppState, job: &QueuedJob, result: Result<(), String>) -> Result<(), sqlx::Error> {
+    let error = match result {
+        Ok(()) => return db::finish_job(&state.db_pool, job.id).await,
+        Err(e) => e,
+    };
+    if job.attempts >= job.max_attempts {
+        tracing::warn!(target: "memory", "Job {} ({}) failed for good: {}", job.id, job.kind, error);
+        db::fail_job(&state.db_pool, job.id, &error, None).await
+    } else {
+        let delay = backoff(job.attempts);
+        tracing::warn!(target: "memory", "Job {} ({}) failed, retrying in {}s: {}", job.id, job.kind, delay, error);
+        db::fail_job(&state.db_pool, job.id, &error, Some(delay)).await
+    }
+}
+
+fn backoff(attempts: i64) -> u64 {
+    let doublings = attempts.clamp(1, 20) as u32 - 1;
+    (RETRY_BASE_SECS << doublings).min(RETRY_MAX_SECS)
+}
+
+async fn run(state: &AppState, job: &Job) -> Result<(), String> {
+    match job {
+        Job::Embed { message_ids, replace } => embed(state, message_ids, *replace).await,
+        Job::Summarize { chat_id } => {
+            let threshold = db::get_config_u32(&state.db_pool, "summary_threshold", state.config.summary_threshold).await;
+            if threshold == 0 {
+                return Ok(());
+            }
+            summarizer::summarize_chat(state, *chat_id, threshold).await.map(|_| ())
+        }
+        Job::ExtractFacts { chat_id } => facts::extract_facts(state, *chat_id).await.map(|_| ()),
+    }
+}
+
+/// Embed the messages' chunks in one batch, then store them and add them to the vector index.
+/// Chunks are upserted by (message, position), so two jobs racing over one message
+/// can't store it twice. Replaced chunks stay in the index and are skipped when scoring.
+async fn embed(state: &AppState, message_ids: &[i64], replace: bool) -> Result<(), String> {
+    let pool = &state.db_pool;
+    let messages = db::get_messages_for_embedding(pool, message_ids).await.map_err(|e| e.to_string())?;
+
+    let mut targets = Vec::new();
+    let mut chunks = Vec::new();
+    for (message, has_chunks) in messages {
+        if has_chunks && !replace {
+            continue;
+        }
+        let split = chunker::split(message.text.as_deref().unwrap_or(""));
+        targets.push((message.id, message.chat_id, has_chunks, split.len()));
+        chunks.extend(split);
+    }
+    if targets.is_empty() {
+        return Ok(());
+    }
+
+    let model = state.embedding_model().await;
+    let embeddings = if chunks.is_empty() {
+        Vec::new()
+    } else {
+        state.embed_batch_with(&model, &chunks).await.map_err(|e| e.to_string())?
+    };
+
+    let mut next = 0;
+    for (db_id, chat_id, has_chunks, count) in targets {
+        if has_chunks {
+            db::delete_message_chunks(pool, db_id).await.map_err(|e| e.to_string())?;
+        }
+        for chunk_index in 0..count {
+            let (chunk, embedding) = (&chunks[next], &embeddings[next]);
+            let chunk_id = db::save_embedding(pool, db_id, chunk_index, chunk, &model, embedding)
+                .await
+                .map_err(|e| e.to_string())?;
+            state.vector_index.insert(chat_id, &model, chunk_id, embedding);
+            next += 1;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_round_trip_and_backoff() {
+        let job = Job::Embed { message_ids: vec![3, 4], replace: false };
+        let payload = serde_json::to_string(&job).unwrap();
+        assert!(payload.contains("\"kind\":\"embed\""));
+        assert_eq!(serde_json::from_str::<Job>(&payload).unwrap(), job);
+        assert_eq!(Job::ExtractFacts { chat_id: -5 }.dedup_key().as_deref(), Some("facts:-5"));
+
+        assert_eq!(backoff(1), 10);
+        assert_eq!(backoff(3), 40);
+        assert_eq!(backoff(30), RETRY_MAX_SECS);
+    }
+}
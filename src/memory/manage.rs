@@ -0,0 +1,63 @@
+use super::{hybrid, index};
+use crate::db::{self, MemoryEntry, ScoredChunk};
+use crate::state::AppState;
+
+/// Search a chat's memory the way replies do (vector or hybrid, with time decay), best first
+pub async fn search(state: &AppState, chat_id: i64, query: &str, limit: u32) -> Result<Vec<ScoredChunk>, String> {
+    let decay_rate = db::get_config_f64(&state.db_pool, "rag_decay_rate", state.config.rag_decay_rate).await;
+    let hybrid = db::get_or_create_chat_settings(&state.db_pool, chat_id).await
+        .map(|s| s.hybrid_search_enabled)
+        .unwrap_or(false);
+
+    let model = state.embedding_model().await;
+    let embedding = state.embed_with(&model, query).await.map_err(|e| format!("Failed to generate embeddings: {}", e))?;
+    if hybrid {
+        hybrid::find_hybrid(state, chat_id, &model, query, &embedding, limit, decay_rate).await
+    } else {
+        index::find_similar(state, chat_id, &model, &embedding, limit, decay_rate).await
+    }
+    .map_err(|e| format!("Failed to retrieve chunks: {}", e))
+}
+
+/// Delete a memory chunk; returns it, or `None` if there was no such chunk.
+/// The chat's vector index keeps the stale entry, which is skipped when scoring.
+pub async fn forget(state: &AppState, chunk_id: i64) -> Result<Option<MemoryEntry>, sqlx::Error> {
+    let Some(entry) = db::get_memory_entry(&state.db_pool, chunk_id).await? else {
+        return Ok(None);
+    };
+    db::delete_memory_chunk(&state.db_pool, chunk_id).await?;
+    Ok(Some(entry))
+}
+
+/// Pin a chunk (raise its importance) or unpin it (restore the importance it had)
+pub async fn set_pinned(state: &AppState, chunk_id: i64, pinned: bool) -> Result<Option<MemoryEntry>, sqlx::Error> {
+    let Some(mut entry) = db::get_memory_entry(&state.db_pool, chunk_id).await? else {
+        return Ok(None);
+    };
+    let Some(importance) = db::set_chunk_pinned(&state.db_pool, chunk_id, pinned).await? else {
+        return Ok(None);
+    };
+    entry.importance = importance;
+    Ok(Some(entry))
+}
+
+/// Replace a chunk's text and re-embed it. The edit lasts until the source message is
+/// edited in Telegram: its chunks are then rebuilt from the new message text, which
+/// replaces the edited text (and drops a pin).
+pub async fn edit(state: &AppState, chunk_id: i64, text: &str) -> Result<Option<MemoryEntry>, String> {
+    let Some(mut entry) = db::get_memory_entry(&state.db_pool, chunk_id).await.map_err(|e| e.to_string())? else {
+        return Ok(None);
+    };
+    let model = state.embedding_model().await;
+    let embedding = state.embed_with(&model, text).await.map_err(|e| e.to_string())?;
+    db::update_chunk_text(&state.db_pool, chunk_id, text, &model, &embedding).await.map_err(|e| e.to_string())?;
+    // The graph still holds the old vector under this id
+    state.vector_index.invalidate(entry.chat_id);
+    entry.text = text.to_string();
+    Ok(Some(entry))
+}
+
+/// Whether a chunk counts as pinned
+pub fn is_pinned(importance: f64) -> bool {
+    importance >= db::PINNED_IMPORTANCE
+}
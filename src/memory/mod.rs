@@ -0,0 +1,13 @@
+pub mod chunker;
+pub mod facts;
+pub mod history;
+pub mod hnsw;
+pub mod hybrid;
+pub mod index;
+pub mod jobs;
+pub mod manage;
+pub mod privacy;
+pub mod reembed;
+pub mod rerank;
+pub mod rewrite;
+pub mod summarizer;
@@ -0,0 +1,97 @@
+use super::summarizer;
+use crate::db::{self, DeletedData};
+use crate::state::AppState;
+use std::time::Duration;
+use teloxide::types::ChatId;
+
+/// How often retention policies are enforced
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Delete everything stored about a user in every chat (/forget_me): messages,
+/// their memory chunks and cached embeddings, extracted facts and the summaries that
+/// covered their messages, plus what is cached in memory. The remaining messages are
+/// summarized again.
+pub async fn forget_user(state: &AppState, user_id: u64) -> Result<DeletedData, sqlx::Error> {
+    let deleted = db::delete_user_data(&state.db_pool, user_id as i64).await?;
+
+    forget_cached(state, &deleted.chats).await;
+
+    tracing::info!(
+        target: "memory",
+        "Forgot user {}: {} messages, {} chunks, {} facts, {} summaries in {} chats",
+        user_id,
+        deleted.messages,
+        deleted.chunks,
+        deleted.facts,
+        deleted.summaries,
+        deleted.chats.len()
+    );
+    Ok(deleted)
+}
+
+/// Enforce per-chat retention policies in the background, once at startup and then hourly
+pub fn start_retention(state: &AppState) {
+    let state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = enforce_retention(&state).await {
+                tracing::warn!(target: "memory", "Retention pass failed: {}", e);
+            }
+        }
+    });
+}
+
+/// Drop what is held in memory about `chats` after rows were deleted and queue new
+/// summaries of the messages left
+async fn forget_cached(state: &AppState, chats: &[i64]) {
+    // Graphs, cached history and the last retrieval traces still hold their vectors and texts
+    let mut traces = state.last_retrievals.lock().await;
+    for &chat_id in chats {
+        state.vector_index.invalidate(chat_id);
+        state.dialogues.invalidate(chat_id);
+        traces.remove(&ChatId(chat_id));
+    }
+    drop(traces);
+    for &chat_id in chats {
+        summarizer::maybe_summarize(state, chat_id).await;
+    }
+}
+
+/// Delete messages older than each chat's retention period together with what was
+/// made from them (see [`db::delete_messages_before`]); returns the totals
+pub async fn enforce_retention(state: &AppState) -> Result<DeletedData, sqlx::Error> {
+    let mut total = DeletedData::default();
+    for (chat_id, days) in db::get_retention_policies(&state.db_pool).await? {
+        // Values written before the cap existed may not fit a date
+        let cutoff = chrono::Duration::try_days(days)
+            .and_then(|period| chrono::Utc::now().naive_utc().checked_sub_signed(period));
+        let Some(cutoff) = cutoff else {
+            tracing::warn!(target: "memory", "Retention: chat {} has an out-of-range period of {} days, skipped", chat_id, days);
+            continue;
+        };
+        let deleted = db::delete_messages_before(&state.db_pool, chat_id, cutoff).await?;
+        if deleted.messages == 0 {
+            continue;
+        }
+        tracing::info!(
+            target: "memory",
+            "Retention: chat {} dropped {} messages, {} chunks, {} summaries and {} facts older than {} days",
+            chat_id,
+            deleted.messages,
+            deleted.chunks,
+            deleted.summaries,
+            deleted.facts,
+            days
+        );
+        total.chats.push(chat_id);
+        total.messages += deleted.messages;
+        total.chunks += deleted.chunks;
+        total.summaries += deleted.summaries;
+        total.facts += deleted.facts;
+        total.cached_embeddings += deleted.cached_embeddings;
+    }
+    forget_cached(state, &total.chats).await;
+    Ok(total)
+}
@@ -0,0 +1,112 @@
+use crate::db;
+use crate::llm::queue::Priority;
+use crate::state::AppState;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+/// Chunks embedded per batch request
+const BATCH_SIZE: u32 = 64;
+/// Attempts per batch before the job is marked failed
+const MAX_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Start re-embedding in the background if chunks from other embedding models exist.
+///
+/// Resumes an unfinished job for the current model from where it stopped. Only one
+/// runner exists at a time; if the model changes while it works, it switches over.
+pub fn start(state: &AppState) {
+    if state.reembedding.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let state = state.clone();
+    tokio::spawn(async move {
+        loop {
+            let model = state.embedding_model().await;
+            match run(&state, &model).await {
+                Ok(()) if state.embedding_model().await == model => break,
+                Ok(()) => continue,
+                Err(e) => {
+                    tracing::warn!(target: "rag", "Re-embedding for {} stopped: {}", model, e);
+                    break;
+                }
+            }
+        }
+        state.reembedding.store(false, Ordering::SeqCst);
+    });
+}
+
+/// Re-embed every chunk not made by `model`; returns early if the model changes
+async fn run(state: &AppState, model: &str) -> Result<(), String> {
+    let pool = &state.db_pool;
+    db::cancel_other_reembed_jobs(pool, model).await.map_err(|e| e.to_string())?;
+
+    let mut job = match db::get_resumable_reembed_job(pool, model).await.map_err(|e| e.to_string())? {
+        Some(job) => job,
+        None => {
+            let total = db::count_chunks_to_reembed(pool, model).await.map_err(|e| e.to_string())?;
+            if total == 0 {
+                return Ok(());
+            }
+            let id = db::create_reembed_job(pool, model, total).await.map_err(|e| e.to_string())?;
+            db::get_latest_reembed_job(pool).await.map_err(|e| e.to_string())?
+                .filter(|job| job.id == id)
+                .ok_or("re-embedding job vanished")?
+        }
+    };
+    tracing::info!(
+        target: "rag",
+        "Re-embedding chunks with {}: {}/{} done",
+        model,
+        job.processed,
+        job.total
+    );
+
+    loop {
+        if state.embedding_model().await != model {
+            db::finish_reembed_job(pool, job.id, "cancelled", None).await.map_err(|e| e.to_string())?;
+            return Ok(());
+        }
+
+        let chunks = db::get_chunks_to_reembed(pool, model, job.last_chunk_id, BATCH_SIZE).await.map_err(|e| e.to_string())?;
+        let Some(last) = chunks.last() else {
+            break;
+        };
+        let last_id = last.id;
+        let texts: Vec<String> = chunks.iter().map(|c| c.text.clone()).collect();
+
+        let mut attempt = 0;
+        let embeddings = loop {
+            attempt += 1;
+            let permit = state.acquire_llm_permit(Priority::Background).await;
+            let result = match permit {
+                Ok(_permit) => state.embed_batch_with(model, &texts).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(embeddings) => break embeddings,
+                Err(e) if attempt < MAX_ATTEMPTS => {
+                    tracing::debug!(target: "rag", "Re-embedding batch failed (attempt {}): {}", attempt, e);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+                Err(e) => {
+                    let error = e.to_string();
+                    db::finish_reembed_job(pool, job.id, "failed", Some(&error)).await.map_err(|e| e.to_string())?;
+                    return Err(error);
+                }
+            }
+        };
+
+        for (chunk, embedding) in chunks.iter().zip(&embeddings) {
+            db::update_chunk_embedding(pool, chunk.id, model, embedding).await.map_err(|e| e.to_string())?;
+            state.vector_index.insert(chunk.chat_id, model, chunk.id, embedding);
+        }
+
+        job.processed += chunks.len() as i64;
+        job.last_chunk_id = last_id;
+        db::update_reembed_progress(pool, job.id, job.processed, job.last_chunk_id).await.map_err(|e| e.to_string())?;
+    }
+
+    db::finish_reembed_job(pool, job.id, "done", None).await.map_err(|e| e.to_string())?;
+    tracing::info!(target: "rag", "Re-embedding with {} finished: {} chunks", model, job.processed);
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use crate::db::ScoredChunk;
+use crate::llm::client::ChatMessage;
+use crate::llm::queue::Priority;
+use crate::state::AppState;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Candidates retrieved for reranking, instead of the few a plain search keeps
+pub const CANDIDATES: u32 = 12;
+/// Reranked memories judged less relevant than this are not used
+pub const MIN_RELEVANCE: f64 = 0.5;
+/// Longer candidates are cut before judging
+const MAX_CANDIDATE_CHARS: usize = 400;
+const RERANK_MAX_TOKENS: u32 = 512;
+const RERANK_TEMPERATURE: f64 = 0.0;
+
+const RERANK_PROMPT: &str = "Ты оцениваешь, какие фрагменты переписки помогут ответить на последнее сообщение. \
+    Для каждого фрагмента поставь relevance от 0 до 1: 1 — прямо касается темы сообщения, \
+    0 — не связан с ней (приветствия, болтовня, случайно совпавшие слова). \
+    Ответь JSON-объектом вида {\"scores\": [{\"id\": 1, \"relevance\": 0.8}]} с оценкой для каждого id.";
+
+#[derive(Deserialize)]
+struct Judgement {
+    #[serde(default)]
+    scores: Vec<Score>,
+}
+
+#[derive(Deserialize)]
+struct Score {
+    id: usize,
+    relevance: f64,
+}
+
+/// Have the rerank model judge how relevant each candidate is to `query`, then reorder
+/// them most relevant first. Waiting for an LLM slot counts against `budget`; when it
+/// runs out, or the model answers nonsense, the candidates are left as they were.
+pub async fn rerank(
+    state: &AppState,
+    query: &str,
+    candidates: &mut [ScoredChunk],
+    priority: Priority,
+    budget: Duration,
+) -> Result<(), String> {
+    if candidates.is_empty() {
+        return Ok(());
+    }
+    let reply = tokio::time::timeout(budget, judge(state, query, candidates, priority))
+        .await
+        .map_err(|_| format!("over the {} ms budget", budget.as_millis()))??;
+    let judgement: Judgement = serde_json::from_str(reply.trim()).map_err(|e| format!("bad JSON from model: {}", e))?;
+    apply(candidates, &judgement.scores);
+    Ok(())
+}
+
+async fn judge(state: &AppState, query: &str, candidates: &[ScoredChunk], priority: Priority) -> Result<String, String> {
+    let mut prompt = format!("Сообщение: {}\n\nФрагменты:\n", query.trim());
+    for (i, chunk) in candidates.iter().enumerate() {
+        let text: String = chunk.text.chars().take(MAX_CANDIDATE_CHARS).collect();
+        prompt.push_str(&format!("#{} {}\n", i + 1, text.replace('\n', " ")));
+    }
+    let request = [ChatMessage::system(RERANK_PROMPT), ChatMessage::user(prompt)];
+    let model = state.rerank_model().await;
+
+    let permit = state.acquire_llm_permit(priority).await.map_err(|e| e.to_string())?;
+    let reply = state.llm_client.chat_json(&model, &request, RERANK_TEMPERATURE, RERANK_MAX_TOKENS).await;
+    drop(permit);
+    reply.map_err(|e| e.to_string())
+}
+
+/// Attach relevance to candidates (1-based ids; unscored ones get 0) and sort by it.
+/// The sort is stable, so equally relevant candidates keep their retrieval order.
+fn apply(candidates: &mut [ScoredChunk], scores: &[Score]) {
+    for chunk in candidates.iter_mut() {
+        chunk.relevance = Some(0.0);
+    }
+    for score in scores {
+        if let Some(chunk) = score.id.checked_sub(1).and_then(|i| candidates.get_mut(i)) {
+            chunk.relevance = Some(score.relevance.clamp(0.0, 1.0));
+        }
+    }
+    candidates.sort_by(|a, b| b.relevance.partial_cmp(&a.relevance).unwrap_or(std::cmp::Ordering::Equal));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(id: i64) -> ScoredChunk {
+        ScoredChunk {
+            id,
+            text: id.to_string(),
+            similarity: 0.9,
+            decay: 1.0,
+            importance: 1.0,
+            score: 0.9,
+            sent_at: chrono::Utc::now().naive_utc(),
+            lexical_rank: None,
+            fused: None,
+            relevance: None,
+        }
+    }
+
+    #[test]
+    fn test_apply_orders_by_relevance() {
+        let mut candidates: Vec<ScoredChunk> = (1..=4).map(candidate).collect();
+        let judgement: Judgement = serde_json::from_str(
+            r#"{"scores": [{"id": 3, "relevance": 0.9}, {"id": 1, "relevance": 0.2}, {"id": 4, "relevance": 1.7}, {"id": 9, "relevance": 1.0}]}"#,
+        )
+        .unwrap();
+        apply(&mut candidates, &judgement.scores);
+
+        let order: Vec<i64> = candidates.iter().map(|c| c.id).collect();
+        assert_eq!(order, vec![4, 3, 1, 2]);
+        assert_eq!(candidates[0].relevance, Some(1.0));
+        assert_eq!(candidates[3].relevance, Some(0.0));
+    }
+}
@@ -0,0 +1,109 @@
+use super::history::Turn;
+use crate::db::ScoredChunk;
+use crate::llm::client::ChatMessage;
+use crate::llm::queue::Priority;
+use crate::state::AppState;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Earlier turns shown to the model to resolve references
+const CONTEXT_TURNS: usize = 6;
+/// Most search queries taken from one rewrite
+pub const MAX_QUERIES: usize = 3;
+/// Longer turns are cut before rewriting
+const MAX_TURN_CHARS: usize = 300;
+/// Past this (queue wait included) the message is searched as is
+const REWRITE_TIMEOUT: Duration = Duration::from_secs(5);
+const REWRITE_MAX_TOKENS: u32 = 256;
+const REWRITE_TEMPERATURE: f64 = 0.0;
+
+const REWRITE_PROMPT: &str = "Ты превращаешь последнее сообщение чата в самостоятельный запрос для поиска по памяти переписки. \
+    Раскрой местоимения и отсылки («он», «это», «а сколько стоит?») по предыдущим сообщениям, \
+    сохрани имена, числа и язык сообщения, не добавляй того, чего в переписке нет. \
+    Если в сообщении несколько разных вопросов, дай отдельный запрос на каждый, не больше трёх. \
+    Если сообщение понятно и без контекста, верни его как есть. \
+    Ответь JSON-объектом вида {\"queries\": [\"запрос\"]}.";
+
+#[derive(Deserialize)]
+struct Rewrite {
+    #[serde(default)]
+    queries: Vec<String>,
+}
+
+/// Turn the latest message into standalone search queries using the turns before it.
+/// Fails if the model is too slow or its answer has no usable query.
+pub async fn rewrite(state: &AppState, history: &[Turn], text: &str, priority: Priority) -> Result<Vec<String>, String> {
+    let skip = history.len().saturating_sub(CONTEXT_TURNS);
+    let mut prompt = String::from("Предыдущие сообщения:\n");
+    for turn in &history[skip..] {
+        let author = if turn.from_bot { "бот" } else { turn.name.as_str() };
+        let text: String = turn.text.chars().take(MAX_TURN_CHARS).collect();
+        prompt.push_str(&format!("{}: {}\n", author, text.replace('\n', " ")));
+    }
+    prompt.push_str(&format!("\nПоследнее сообщение: {}", text.trim()));
+
+    let request = [ChatMessage::system(REWRITE_PROMPT), ChatMessage::user(prompt)];
+    let reply = tokio::time::timeout(REWRITE_TIMEOUT, async {
+        let model = state.chat_model().await;
+        let permit = state.acquire_llm_permit(priority).await.map_err(|e| e.to_string())?;
+        let reply = state.llm_client.chat_json(&model, &request, REWRITE_TEMPERATURE, REWRITE_MAX_TOKENS).await;
+        drop(permit);
+        reply.map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|_| format!("no answer within {} s", REWRITE_TIMEOUT.as_secs()))??;
+
+    let rewrite: Rewrite = serde_json::from_str(reply.trim()).map_err(|e| format!("bad JSON from model: {}", e))?;
+    let queries = clean(rewrite.queries);
+    if queries.is_empty() {
+        return Err("model returned no queries".to_string());
+    }
+    Ok(queries)
+}
+
+/// Trimmed, non-empty, distinct queries, at most `MAX_QUERIES`
+fn clean(queries: Vec<String>) -> Vec<String> {
+    let mut cleaned: Vec<String> = Vec::new();
+    for query in queries {
+        let query = query.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !query.is_empty() && !cleaned.iter().any(|q| q.to_lowercase() == query.to_lowercase()) {
+            cleaned.push(query);
+        }
+    }
+    cleaned.truncate(MAX_QUERIES);
+    cleaned
+}
+
+/// Merge the rankings of several queries: each chunk once with its best result, best first
+/// (by fused rank in hybrid retrieval, otherwise by score), at most `limit`
+pub fn merge(rankings: Vec<Vec<ScoredChunk>>, limit: usize) -> Vec<ScoredChunk> {
+    let rank = |chunk: &ScoredChunk| chunk.fused.unwrap_or(chunk.score);
+    let mut merged: Vec<ScoredChunk> = Vec::new();
+    for chunk in rankings.into_iter().flatten() {
+        match merged.iter_mut().find(|c| c.id == chunk.id) {
+            Some(existing) if rank(&chunk) > rank(existing) => *existing = chunk,
+            Some(_) => {}
+            None => merged.push(chunk),
+        }
+    }
+    merged.sort_by(|a, b| rank(b).partial_cmp(&rank(a)).unwrap_or(std::cmp::Ordering::Equal));
+    merged.truncate(limit);
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_queries() {
+        let rewrite: Rewrite = serde_json::from_str(
+            r#"{"queries": ["  сколько стоит  аренда в Казани ", "", "Сколько стоит аренда в Казани", "что сказал Паша про переезд", "a", "b"]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            clean(rewrite.queries),
+            vec!["сколько стоит аренда в Казани", "что сказал Паша про переезд", "a"]
+        );
+    }
+}
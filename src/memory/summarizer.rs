@@ -0,0 +1,142 @@
+use super::jobs::{self, Job};
+use crate::db::{self, ChatSummary, DbMessage};
+use crate::llm::client::ChatMessage;
+use crate::llm::queue::Priority;
+use crate::state::AppState;
+
+/// Summaries that go into the prompt, newest first
+pub const PROMPT_SUMMARIES: u32 = 3;
+/// Longer messages are cut before summarizing
+const MAX_MESSAGE_CHARS: usize = 600;
+const SUMMARY_MAX_TOKENS: u32 = 512;
+const SUMMARY_TEMPERATURE: f64 = 0.3;
+
+const SUMMARY_PROMPT: &str = "Ты ведёшь краткую хронику группового чата. \
+    Сожми переписку в сводку на 5–10 пунктов: о чём говорили, что решили, кто что о себе рассказал, \
+    какие вопросы остались открытыми. Пиши на языке переписки, в третьем лице, без вступлений. \
+    Не повторяй то, что уже есть в предыдущей сводке.";
+
+/// Queue a summarization once `summary_threshold` messages have piled up since the
+/// last summary. A threshold of 0 turns summarization off.
+pub async fn maybe_summarize(state: &AppState, chat_id: i64) {
+    let threshold = db::get_config_u32(&state.db_pool, "summary_threshold", state.config.summary_threshold).await;
+    if threshold == 0 {
+        return;
+    }
+    match db::count_unsummarized_messages(&state.db_pool, chat_id).await {
+        Ok(count) if count >= threshold as i64 => {}
+        _ => return,
+    }
+    if let Err(e) = jobs::enqueue(state, &Job::Summarize { chat_id }).await {
+        tracing::warn!(target: "db", "Failed to queue summarization of chat {}: {}", chat_id, e);
+    }
+}
+
+/// Summarize up to `limit` of the oldest unsummarized messages; returns the new summary id,
+/// or `None` if there was nothing to do or another summarization is running for the chat
+pub async fn summarize_chat(state: &AppState, chat_id: i64, limit: u32) -> Result<Option<i64>, String> {
+    summarize_locked(state, chat_id, limit, None).await
+}
+
+/// Replace the latest summary with a fresh one over the same messages. The old one is
+/// only overwritten once the new one is ready, so a failed attempt loses nothing.
+pub async fn regenerate_latest(state: &AppState, chat_id: i64) -> Result<Option<i64>, String> {
+    let latest = db::get_chat_summaries(&state.db_pool, chat_id, 1).await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .next();
+
+    match latest {
+        Some(summary) => summarize_locked(state, chat_id, summary.message_count.max(1) as u32, Some(&summary)).await,
+        None => {
+            let threshold = db::get_config_u32(&state.db_pool, "summary_threshold", state.config.summary_threshold).await;
+            summarize_chat(state, chat_id, threshold.max(1)).await
+        }
+    }
+}
+
+async fn summarize_locked(state: &AppState, chat_id: i64, limit: u32, replacing: Option<&ChatSummary>) -> Result<Option<i64>, String> {
+    if !state.summarizing.lock().unwrap().insert(chat_id) {
+        return Ok(None);
+    }
+    let result = run_summary(state, chat_id, limit, replacing).await;
+    state.summarizing.lock().unwrap().remove(&chat_id);
+    result
+}
+
+async fn run_summary(state: &AppState, chat_id: i64, limit: u32, replacing: Option<&ChatSummary>) -> Result<Option<i64>, String> {
+    let pool = &state.db_pool;
+    let (messages, previous) = match replacing {
+        // The replaced summary's messages, continuing the summary before it
+        Some(old) => {
+            let mut messages = db::get_messages_after(pool, chat_id, old.messages_from - 1, limit).await.map_err(|e| e.to_string())?;
+            messages.retain(|m| m.id <= old.messages_to);
+            let previous = db::get_chat_summaries(pool, chat_id, 2).await
+                .map_err(|e| e.to_string())?
+                .into_iter()
+                .find(|summary| summary.id != old.id);
+            (messages, previous)
+        }
+        None => {
+            let messages = db::get_messages_for_summary(pool, chat_id, limit).await.map_err(|e| e.to_string())?;
+            let previous = db::get_chat_summaries(pool, chat_id, 1).await
+                .map_err(|e| e.to_string())?
+                .into_iter()
+                .next();
+            (messages, previous)
+        }
+    };
+    let (Some(first), Some(last)) = (messages.first(), messages.last()) else {
+        return Ok(None);
+    };
+    let (from, to) = (first.id, last.id);
+
+    let mut prompt = String::new();
+    if let Some(previous) = &previous {
+        prompt.push_str(&format!("Предыдущая сводка:\n{}\n\n", previous.summary_text.trim()));
+    }
+    prompt.push_str("Новые сообщения:\n");
+    for message in &messages {
+        prompt.push_str(&format_message(message));
+        prompt.push('\n');
+    }
+
+    let request = [ChatMessage::system(SUMMARY_PROMPT), ChatMessage::user(prompt)];
+    let model = state.chat_model().await;
+
+    // Lowest priority: replies to people always go first
+    let permit = state.acquire_llm_permit(Priority::Background).await.map_err(|e| e.to_string())?;
+    let summary = state.llm_client.chat(&model, &request, SUMMARY_TEMPERATURE, SUMMARY_MAX_TOKENS).await;
+    drop(permit);
+
+    let summary = summary.map_err(|e| e.to_string())?;
+    let summary = summary.trim();
+    if summary.is_empty() {
+        return Err("empty summary".to_string());
+    }
+
+    let count = messages.len() as i64;
+    let id = match replacing {
+        Some(old) => {
+            if !db::update_chat_summary(pool, old.id, summary, from, to, count).await.map_err(|e| e.to_string())? {
+                // Deleted while the new one was being written, e.g. by /forget_me
+                return Ok(None);
+            }
+            old.id
+        }
+        None => db::save_chat_summary(pool, chat_id, summary, from, to, count).await.map_err(|e| e.to_string())?,
+    };
+    tracing::info!(target: "memory", "Chat {}: summarized {} messages ({}..={})", chat_id, messages.len(), from, to);
+    Ok(Some(id))
+}
+
+fn format_message(message: &DbMessage) -> String {
+    let author = message.username.as_deref().unwrap_or("?");
+    let text = message.text.as_deref().unwrap_or("").trim();
+    let text: String = if text.chars().count() > MAX_MESSAGE_CHARS {
+        text.chars().take(MAX_MESSAGE_CHARS).chain("…".chars()).collect()
+    } else {
+        text.to_string()
+    };
+    format!("[{}] {}: {}", message.sent_at.format("%d.%m %H:%M"), author, text)
+}
@@ -1,22 +1,28 @@
 use crate::config::Config;
-use crate::llm::client::LlmClient;
+use crate::db;
+use crate::llm::cancel::{CancelReason, CancelToken};
+use crate::llm::client::{LlmClient, LlmError, EMBED_BATCH_SIZE};
+use crate::llm::queue::{LlmPermit, LlmQueue, Priority};
+use crate::memory::history::DialogueHistory;
+use crate::memory::index::VectorIndex;
+use crate::memory::rerank;
 use crate::security::{SecurityConfig, SecurityTracker};
 use crate::voice::VoiceClient;
 use crate::web::search::WebSearchClient;
 use sqlx::SqlitePool;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::Instant;
 use teloxide::prelude::*;
-use tokio::sync::{Mutex, Semaphore};
+use tokio::sync::Mutex;
 
-pub type DialogueState = Arc<Mutex<HashMap<ChatId, Vec<Message>>>>;
 pub type AdminCache = Arc<Mutex<HashMap<ChatId, Vec<UserId>>>>;
 pub type RateLimiter = Arc<Mutex<HashMap<ChatId, Instant>>>;
 pub type WizardStates = Arc<Mutex<HashMap<ChatId, WizardState>>>;
 pub type PendingMessages = Arc<Mutex<HashMap<(ChatId, Option<teloxide::types::ThreadId>), PendingBatch>>>;
 pub type UserRateLimit = Arc<Mutex<HashMap<u64, Vec<Instant>>>>;
+pub type GenerationKey = (ChatId, Option<teloxide::types::ThreadId>);
 
 /// Pending message batch for debounce
 #[derive(Clone, Debug)]
@@ -58,6 +64,139 @@ pub enum WizardState {
     Broadcasting,
 }
 
+/// A reply being generated in some chat/thread
+#[derive(Clone)]
+pub struct ActiveGeneration {
+    pub id: u64,
+    pub user_id: Option<u64>,
+    pub cancel: CancelToken,
+}
+
+/// In-flight generations per chat/thread, so they can be stopped or superseded
+#[derive(Clone, Default)]
+pub struct Generations {
+    next_id: Arc<AtomicU64>,
+    active: Arc<std::sync::Mutex<HashMap<GenerationKey, Vec<ActiveGeneration>>>>,
+}
+
+impl Generations {
+    /// Register a generation; it is unregistered when the guard is dropped
+    pub fn start(&self, key: GenerationKey, user_id: Option<u64>) -> GenerationGuard {
+        let generation = ActiveGeneration {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            user_id,
+            cancel: CancelToken::new(),
+        };
+        self.active.lock().unwrap().entry(key).or_default().push(generation.clone());
+        GenerationGuard { generations: self.clone(), key, generation }
+    }
+
+    /// Cancel generations in `key` (only those of `user_id` if given); returns how many
+    pub fn cancel(&self, key: GenerationKey, user_id: Option<u64>, reason: CancelReason) -> usize {
+        let active = self.active.lock().unwrap();
+        let mut cancelled = 0;
+        for generation in active.get(&key).into_iter().flatten() {
+            if user_id.is_none() || generation.user_id == user_id {
+                generation.cancel.cancel(reason);
+                cancelled += 1;
+            }
+        }
+        cancelled
+    }
+
+    pub fn find(&self, id: u64) -> Option<ActiveGeneration> {
+        let active = self.active.lock().unwrap();
+        active.values().flatten().find(|g| g.id == id).cloned()
+    }
+}
+
+/// Registration of a running generation; unregisters on drop
+pub struct GenerationGuard {
+    generations: Generations,
+    key: GenerationKey,
+    generation: ActiveGeneration,
+}
+
+impl GenerationGuard {
+    pub fn id(&self) -> u64 {
+        self.generation.id
+    }
+
+    pub fn token(&self) -> &CancelToken {
+        &self.generation.cancel
+    }
+}
+
+impl Drop for GenerationGuard {
+    fn drop(&mut self) {
+        let mut active = self.generations.active.lock().unwrap();
+        if let Some(list) = active.get_mut(&self.key) {
+            list.retain(|g| g.id != self.generation.id);
+            if list.is_empty() {
+                active.remove(&self.key);
+            }
+        }
+    }
+}
+
+/// Memory retrieval for one message, kept for /why
+#[derive(Clone, Debug)]
+pub struct RetrievalTrace {
+    pub query: String,
+    /// Standalone queries the message was rewritten into; empty if it was searched as is
+    pub rewritten: Vec<String>,
+    pub min_score: f64,
+    pub decay_rate: f64,
+    /// Most memories that go into the prompt
+    pub max_chosen: usize,
+    /// Vector and full-text rankings were fused
+    pub hybrid: bool,
+    /// Time the rerank model had; 0 when reranking is off for the chat
+    pub rerank_budget_ms: i64,
+    /// Why reranking was skipped, leaving the score order
+    pub rerank_error: Option<String>,
+    /// Ranked candidates, best first
+    pub candidates: Vec<db::ScoredChunk>,
+    pub at: Instant,
+}
+
+impl RetrievalTrace {
+    /// Whether the candidate at `index` made it into the prompt: it qualifies and fewer than
+    /// `max_chosen` qualifying candidates rank above it. Reranked candidates qualify with
+    /// enough relevance, the rest by clearing the similarity cutoff.
+    pub fn is_chosen(&self, index: usize) -> bool {
+        let qualifies = |chunk: &db::ScoredChunk| match chunk.relevance {
+            Some(relevance) => relevance >= rerank::MIN_RELEVANCE,
+            None => self.passes_cutoff(chunk),
+        };
+        qualifies(&self.candidates[index])
+            && self.candidates[..index].iter().filter(|chunk| qualifies(chunk)).count() < self.max_chosen
+    }
+
+    /// Whether a candidate is similar enough to the query (or is a full-text match).
+    /// The cutoff applies to raw similarity so that old memories aren't dropped just for
+    /// their age; decay and importance only decide the order.
+    pub fn passes_cutoff(&self, chunk: &db::ScoredChunk) -> bool {
+        chunk.similarity >= self.min_score || chunk.lexical_rank.is_some()
+    }
+
+    /// Memories used for the prompt, best first
+    pub fn chosen(&self) -> Vec<String> {
+        (0..self.candidates.len())
+            .filter(|&i| self.is_chosen(i))
+            .map(|i| self.candidates[i].text.clone())
+            .collect()
+    }
+
+    /// Chunk ids of the memories used for the prompt, best first
+    pub fn chosen_ids(&self) -> Vec<i64> {
+        (0..self.candidates.len())
+            .filter(|&i| self.is_chosen(i))
+            .map(|i| self.candidates[i].id)
+            .collect()
+    }
+}
+
 /// Queue statistics for monitoring
 #[derive(Clone, Debug, Default)]
 pub struct QueueStats {
@@ -82,12 +221,12 @@ pub struct AppState {
     pub llm_client: LlmClient,
     pub web_search: WebSearchClient,
     pub voice_client: VoiceClient,
-    pub dialogues: DialogueState,
+    pub dialogues: DialogueHistory,
     pub db_pool: SqlitePool,
     pub admin_cache: AdminCache,
     pub rate_limiter: RateLimiter,
     pub wizard_states: WizardStates,
-    pub llm_semaphore: Arc<Semaphore>,
+    pub llm_queue: LlmQueue,
     pub queue_stats: Arc<Mutex<QueueStats>>,
     pub keyword_triggers: Arc<Mutex<HashMap<ChatId, Vec<String>>>>,
     pub security_tracker: Arc<SecurityTracker>,
@@ -95,6 +234,17 @@ pub struct AppState {
     pub bot_info: Arc<Mutex<Option<BotInfo>>>,
     pub pending_messages: PendingMessages,
     pub user_rate_limits: UserRateLimit,
+    pub generations: Generations,
+    pub last_retrievals: Arc<Mutex<HashMap<ChatId, RetrievalTrace>>>,
+    /// Chats with a summarization in progress
+    pub summarizing: Arc<std::sync::Mutex<HashSet<i64>>>,
+    /// Chats with a fact extraction in progress
+    pub extracting_facts: Arc<std::sync::Mutex<HashSet<i64>>>,
+    pub vector_index: VectorIndex,
+    /// Set while the re-embedding job runs
+    pub reembedding: Arc<AtomicBool>,
+    /// Wakes a job worker when a job is queued
+    pub job_signal: Arc<tokio::sync::Notify>,
 }
 
 impl AppState {
@@ -112,15 +262,15 @@ impl AppState {
         
         Self {
             config: config_arc.clone(),
-            llm_client: LlmClient::new(config_arc.ollama_url.clone()),
+            llm_client: LlmClient::from_config(&config_arc),
             web_search: WebSearchClient::new(),
             voice_client: VoiceClient::new(config_arc.whisper_url.clone()),
-            dialogues: Arc::new(Mutex::new(HashMap::new())),
+            dialogues: DialogueHistory::default(),
             db_pool,
             admin_cache: Arc::new(Mutex::new(HashMap::new())),
             rate_limiter: Arc::new(Mutex::new(HashMap::new())),
             wizard_states: Arc::new(Mutex::new(HashMap::new())),
-            llm_semaphore: Arc::new(Semaphore::new(max_concurrent_llm)),
+            llm_queue: LlmQueue::new(max_concurrent_llm),
             queue_stats: Arc::new(Mutex::new(QueueStats::default())),
             keyword_triggers: Arc::new(Mutex::new(HashMap::new())),
             security_tracker: Arc::new(SecurityTracker::new(security_config)),
@@ -128,6 +278,13 @@ impl AppState {
             bot_info: Arc::new(Mutex::new(None)),
             pending_messages: Arc::new(Mutex::new(HashMap::new())),
             user_rate_limits: Arc::new(Mutex::new(HashMap::new())),
+            generations: Generations::default(),
+            last_retrievals: Arc::new(Mutex::new(HashMap::new())),
+            summarizing: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            extracting_facts: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            vector_index: VectorIndex::default(),
+            reembedding: Arc::new(AtomicBool::new(false)),
+            job_signal: Arc::new(tokio::sync::Notify::new()),
         }
     }
 
@@ -206,6 +363,141 @@ impl AppState {
         states.remove(&chat_id);
     }
 
+    /// Chat model chosen in the config menu, falling back to `OLLAMA_CHAT_MODEL`
+    pub async fn chat_model(&self) -> String {
+        db::get_config(&self.db_pool, "ollama_chat_model").await.ok().flatten()
+            .unwrap_or_else(|| self.config.ollama_chat_model.clone())
+    }
+
+    /// Vision model chosen in the config menu, falling back to `OLLAMA_VISION_MODEL`
+    pub async fn vision_model(&self) -> String {
+        db::get_config(&self.db_pool, "ollama_vision_model").await.ok().flatten()
+            .unwrap_or_else(|| self.config.ollama_vision_model.clone())
+    }
+
+    /// Model that reranks memories: `RERANK_MODEL`, falling back to the chat model
+    pub async fn rerank_model(&self) -> String {
+        match self.config.rerank_model.as_deref().map(str::trim).filter(|m| !m.is_empty()) {
+            Some(model) => model.to_string(),
+            None => self.chat_model().await,
+        }
+    }
+
+    /// Prompt context size: `LLM_CONTEXT_TOKENS`, capped by the model's own context length
+    pub async fn context_tokens(&self, model: &str) -> usize {
+        let configured = self.config.llm_context_tokens;
+        let limit = match self.llm_client.model_info(model).await {
+            Ok(info) => info.context_length.map_or(configured, |ctx| ctx.min(configured)),
+            Err(_) => configured,
+        };
+        limit as usize
+    }
+
+    /// Embedding model chosen in the web app, falling back to `OLLAMA_EMBEDDING_MODEL`
+    pub async fn embedding_model(&self) -> String {
+        db::get_config(&self.db_pool, "ollama_embedding_model").await.ok().flatten()
+            .unwrap_or_else(|| self.config.ollama_embedding_model.clone())
+    }
+
+    /// Embedding of `text` with the current embedding model
+    pub async fn embed(&self, text: &str) -> Result<Vec<f64>, LlmError> {
+        self.embed_with(&self.embedding_model().await, text).await
+    }
+
+    /// Embedding of `text` with `model`, cached in SQLite by content hash
+    pub async fn embed_with(&self, model: &str, text: &str) -> Result<Vec<f64>, LlmError> {
+        let hash = db::embedding_text_hash(text);
+        if let Ok(Some(embedding)) = db::get_cached_embedding(&self.db_pool, model, &hash).await {
+            return Ok(embedding);
+        }
+
+        let embedding = self.llm_client.generate_embeddings(model, text).await?;
+        if let Err(e) = db::cache_embedding(&self.db_pool, model, &hash, &embedding).await {
+            tracing::warn!(target: "db", "Failed to cache embedding: {}", e);
+        }
+        Ok(embedding)
+    }
+
+    /// Embeddings of many texts with the current embedding model
+    pub async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f64>>, LlmError> {
+        self.embed_batch_with(&self.embedding_model().await, texts).await
+    }
+
+    /// Embeddings of many texts with `model` in input order. Texts are handled
+    /// `EMBED_BATCH_SIZE` at a time: one cache lookup per batch, one request for its misses.
+    pub async fn embed_batch_with(&self, model: &str, texts: &[String]) -> Result<Vec<Vec<f64>>, LlmError> {
+        let mut result = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(EMBED_BATCH_SIZE) {
+            let hashes: Vec<String> = batch.iter().map(|t| db::embedding_text_hash(t)).collect();
+            let cached = db::get_cached_embeddings(&self.db_pool, model, &hashes).await.unwrap_or_else(|e| {
+                tracing::warn!(target: "db", "Failed to read cached embeddings: {}", e);
+                HashMap::new()
+            });
+            let mut embeddings: Vec<Option<Vec<f64>>> = hashes.iter().map(|hash| cached.get(hash).cloned()).collect();
+
+            let missing: Vec<usize> = (0..batch.len()).filter(|&i| embeddings[i].is_none()).collect();
+            if !missing.is_empty() {
+                let inputs: Vec<String> = missing.iter().map(|&i| batch[i].clone()).collect();
+                let generated = self.llm_client.generate_embeddings_batch(model, &inputs).await?;
+                fill_embeddings(&mut embeddings, &missing, generated)?;
+                for &i in &missing {
+                    let Some(embedding) = &embeddings[i] else { continue };
+                    if let Err(e) = db::cache_embedding(&self.db_pool, model, &hashes[i], embedding).await {
+                        tracing::warn!(target: "db", "Failed to cache embedding: {}", e);
+                    }
+                }
+            }
+            result.extend(embeddings.into_iter().flatten());
+        }
+        Ok(result)
+    }
+
+    /// Queue priority: owner first, then private chats, then groups
+    pub fn llm_priority(&self, user_id: Option<u64>, is_private: bool) -> Priority {
+        if user_id == Some(self.config.owner_id) {
+            Priority::Owner
+        } else if is_private {
+            Priority::Private
+        } else {
+            Priority::Group
+        }
+    }
+
+    /// Wait for an LLM slot, giving up after `queue_timeout_seconds`
+    pub async fn acquire_llm_permit(&self, priority: Priority) -> Result<LlmPermit, LlmError> {
+        let timeout = std::time::Duration::from_secs(self.config.queue_timeout_seconds);
+        let result = self.llm_queue.acquire(priority, timeout).await;
+        if result.is_err() {
+            self.record_queue_timeout(priority, timeout).await;
+        }
+        result
+    }
+
+    /// Like [`acquire_llm_permit`](Self::acquire_llm_permit), calling `on_notice` with the
+    /// queue position if the request is still waiting after `notice_after`
+    pub async fn acquire_llm_permit_with_notice<F, Fut>(
+        &self,
+        priority: Priority,
+        notice_after: std::time::Duration,
+        on_notice: F,
+    ) -> Result<LlmPermit, LlmError>
+    where
+        F: FnOnce(usize) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+    {
+        let timeout = std::time::Duration::from_secs(self.config.queue_timeout_seconds);
+        let result = self.llm_queue.acquire_with_notice(priority, timeout, notice_after, on_notice).await;
+        if result.is_err() {
+            self.record_queue_timeout(priority, timeout).await;
+        }
+        result
+    }
+
+    async fn record_queue_timeout(&self, priority: Priority, timeout: std::time::Duration) {
+        self.queue_stats.lock().await.queue_timeouts += 1;
+        tracing::warn!(target: "llm", "LLM queue timeout after {}s ({:?})", timeout.as_secs(), priority);
+    }
+
     /// Update queue statistics
     pub async fn update_queue_stats(&self, success: bool, response_time_ms: u64) {
         let mut stats = self.queue_stats.lock().await;
@@ -219,3 +511,40 @@ impl AppState {
         stats.avg_response_time_ms = (stats.avg_response_time_ms * (stats.total_requests - 1) + response_time_ms) / stats.total_requests;
     }
 }
+
+/// Put the embeddings generated for the texts at `positions` into their slots
+fn fill_embeddings(
+    embeddings: &mut [Option<Vec<f64>>],
+    positions: &[usize],
+    generated: Vec<Vec<f64>>,
+) -> Result<(), LlmError> {
+    if generated.len() != positions.len() {
+        return Err(LlmError::InvalidResponse(format!(
+            "expected {} embeddings, got {}",
+            positions.len(),
+            generated.len()
+        )));
+    }
+    for (&i, embedding) in positions.iter().zip(generated) {
+        embeddings[i] = Some(embedding);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_embeddings_keeps_input_order() {
+        // Texts 0 and 2 came from the cache, 1 and 3 were generated
+        let mut embeddings = vec![Some(vec![0.0]), None, Some(vec![2.0]), None];
+        fill_embeddings(&mut embeddings, &[1, 3], vec![vec![1.0], vec![3.0]]).unwrap();
+        let embeddings: Vec<Vec<f64>> = embeddings.into_iter().flatten().collect();
+        assert_eq!(embeddings, vec![vec![0.0], vec![1.0], vec![2.0], vec![3.0]]);
+
+        let mut embeddings = vec![None, None];
+        let result = fill_embeddings(&mut embeddings, &[0, 1], vec![vec![1.0]]);
+        assert!(matches!(result, Err(LlmError::InvalidResponse(_))));
+    }
+}
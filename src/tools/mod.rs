@@ -0,0 +1,133 @@
+use crate::db;
+use crate::llm::tools::ToolRegistry;
+use crate::memory::{chunker, index};
+use crate::security;
+use crate::state::AppState;
+use serde_json::{json, Value};
+use teloxide::types::ChatId;
+
+const WEB_SEARCH_MAX_RESULTS: usize = 5;
+const MEMORY_SEARCH_LIMIT: u32 = 5;
+const HISTORY_DEFAULT_LIMIT: u32 = 10;
+const HISTORY_MAX_LIMIT: u32 = 30;
+
+/// Built-in tools for a conversation in `chat_id`
+pub fn builtin_registry(state: &AppState, chat_id: ChatId, web_search_enabled: bool) -> ToolRegistry {
+    let mut registry = ToolRegistry::new();
+
+    if web_search_enabled {
+        let web = state.web_search.clone();
+        registry.register(
+            "web_search",
+            "Search the web for current information (news, facts, definitions). Use when the answer needs fresh or external data.",
+            json!({
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "Search query" }
+                },
+                "required": ["query"]
+            }),
+            move |args| {
+                let web = web.clone();
+                async move {
+                    let query = required_str(&args, "query")?;
+                    let context = web.search_for_context(&query, WEB_SEARCH_MAX_RESULTS).await;
+                    if context.is_empty() {
+                        return Ok("No results found.".to_string());
+                    }
+                    // Search results are untrusted text
+                    Ok(security::sanitize_external_content(&context, 4000))
+                }
+            },
+        );
+    }
+
+    let memory_state = state.clone();
+    registry.register(
+        "memory_search",
+        "Search long-term memory of this chat for past messages related to a topic.",
+        json!({
+            "type": "object",
+            "properties": {
+                "query": { "type": "string", "description": "What to look for" }
+            },
+            "required": ["query"]
+        }),
+        move |args| {
+            let state = memory_state.clone();
+            async move {
+                let query = required_str(&args, "query")?;
+                let model = state.embedding_model().await;
+                let embedding = state.embed_with(&model, &query).await.map_err(|e| e.to_string())?;
+                // No time decay: the model asked about a topic, old matches are as good as new ones
+                let chunks = index::find_similar(&state, chat_id.0, &model, &embedding, MEMORY_SEARCH_LIMIT, 0.0)
+                    .await
+                    .map_err(|e| e.to_string())?;
+
+                let ids: Vec<i64> = chunks.iter().map(|c| c.id).collect();
+                let snippets = chunker::snippets(&state.db_pool, &ids).await.map_err(|e| e.to_string())?;
+
+                if snippets.is_empty() {
+                    return Ok("Nothing found in memory.".to_string());
+                }
+                Ok(snippets.iter().map(|s| format!("- {}", s.trim())).collect::<Vec<_>>().join("\n"))
+            }
+        },
+    );
+
+    let history_state = state.clone();
+    registry.register(
+        "chat_history",
+        "Look up recent messages of this chat, optionally only those containing a word or phrase.",
+        json!({
+            "type": "object",
+            "properties": {
+                "query": { "type": "string", "description": "Optional text the messages must contain" },
+                "limit": { "type": "integer", "description": "How many messages to return (max 30)" }
+            }
+        }),
+        move |args| {
+            let state = history_state.clone();
+            async move {
+                let query = args.get("query").and_then(Value::as_str).filter(|q| !q.trim().is_empty());
+                let limit = args
+                    .get("limit")
+                    .and_then(Value::as_u64)
+                    .map(|l| (l as u32).clamp(1, HISTORY_MAX_LIMIT))
+                    .unwrap_or(HISTORY_DEFAULT_LIMIT);
+
+                let messages = db::search_chat_messages(&state.db_pool, chat_id.0, query, limit)
+                    .await
+                    .map_err(|e| e.to_string())?;
+
+                if messages.is_empty() {
+                    return Ok("No matching messages.".to_string());
+                }
+                // Oldest first reads naturally
+                Ok(messages
+                    .iter()
+                    .rev()
+                    .map(|m| {
+                        format!(
+                            "[{}] {}: {}",
+                            m.sent_at.format("%Y-%m-%d %H:%M"),
+                            m.username.as_deref().unwrap_or("?"),
+                            m.text.as_deref().unwrap_or("")
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+        },
+    );
+
+    registry
+}
+
+fn required_str(args: &Value, key: &str) -> Result<String, String> {
+    args.get(key)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .filter(|s| !s.trim().is_empty())
+        .ok_or_else(|| format!("missing '{}' argument", key))
+}
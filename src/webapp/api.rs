@@ -1,10 +1,13 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::{HeaderMap, StatusCode},
     Json,
 };
 use serde::{Deserialize, Serialize};
 use crate::db;
+use crate::llm::models::{ModelInfo, ModelKind};
+use crate::llm::pool::EndpointStatus;
+use crate::memory::{manage, reembed};
 use crate::state::AppState;
 use super::auth::{validate_init_data, TelegramUser};
 
@@ -204,6 +207,9 @@ pub struct ChatSettingsResponse {
     pub cooldown_seconds: i64,
     pub context_depth: i64,
     pub rag_enabled: bool,
+    pub hybrid_search_enabled: bool,
+    pub retention_days: i64,
+    pub rerank_budget_ms: i64,
 }
 
 #[derive(Deserialize)]
@@ -213,6 +219,9 @@ pub struct UpdateChatSettingsRequest {
     pub cooldown_seconds: Option<i64>,
     pub context_depth: Option<i64>,
     pub rag_enabled: Option<bool>,
+    pub hybrid_search_enabled: Option<bool>,
+    pub retention_days: Option<i64>,
+    pub rerank_budget_ms: Option<i64>,
 }
 
 // --- Chat Settings endpoints ---
@@ -234,6 +243,9 @@ pub async fn list_chats(
                     cooldown_seconds: c.cooldown_seconds,
                     context_depth: c.context_depth,
                     rag_enabled: c.rag_enabled,
+                    hybrid_search_enabled: c.hybrid_search_enabled,
+                    retention_days: c.retention_days,
+                    rerank_budget_ms: c.rerank_budget_ms,
                 })
                 .collect();
             Ok(Json(ApiResponse::ok(data)))
@@ -260,6 +272,9 @@ pub async fn get_chat_settings(
             cooldown_seconds: settings.cooldown_seconds,
             context_depth: settings.context_depth,
             rag_enabled: settings.rag_enabled,
+            hybrid_search_enabled: settings.hybrid_search_enabled,
+            retention_days: settings.retention_days,
+            rerank_budget_ms: settings.rerank_budget_ms,
         }))),
         Err(e) => {
             log::error!("Failed to get chat settings: {}", e);
@@ -285,6 +300,10 @@ pub async fn update_chat_settings(
         }
     };
 
+    if req.retention_days.is_some_and(|days| !(0..=db::MAX_RETENTION_DAYS).contains(&days)) {
+        return Ok(Json(ApiResponse::err("retention_days must be between 0 and 36500")));
+    }
+
     // Apply updates
     if let Some(enabled) = req.auto_reply_enabled {
         let _ = db::toggle_auto_reply_for_chat(&state.db_pool, chat_id, enabled).await;
@@ -298,6 +317,15 @@ pub async fn update_chat_settings(
     if let Some(rag) = req.rag_enabled {
         let _ = db::toggle_rag_for_chat(&state.db_pool, chat_id, rag).await;
     }
+    if let Some(hybrid) = req.hybrid_search_enabled {
+        let _ = db::toggle_hybrid_search_for_chat(&state.db_pool, chat_id, hybrid).await;
+    }
+    if let Some(days) = req.retention_days {
+        let _ = db::set_retention_for_chat(&state.db_pool, chat_id, days).await;
+    }
+    if let Some(budget_ms) = req.rerank_budget_ms {
+        let _ = db::set_rerank_budget_for_chat(&state.db_pool, chat_id, budget_ms).await;
+    }
     if let Some(depth) = req.context_depth {
         let rag = req.rag_enabled.unwrap_or(current.rag_enabled);
         let _ = db::update_rag_settings(&state.db_pool, chat_id, rag, depth).await;
@@ -326,6 +354,8 @@ pub struct SystemStatus {
     pub voice_enabled: bool,
     pub web_search_enabled: bool,
     pub paused: bool,
+    /// Per-host state when several Ollama endpoints are configured
+    pub llm_endpoints: Vec<EndpointStatus>,
 }
 
 pub async fn get_status(
@@ -344,7 +374,7 @@ pub async fn get_status(
         .map(|p| p.name);
 
     let stats = state.queue_stats.lock().await.clone();
-    let queue_available = state.llm_semaphore.available_permits();
+    let queue_available = state.llm_queue.available();
     let queue_max = state.config.max_concurrent_llm_requests.unwrap_or(3);
 
     Ok(Json(ApiResponse::ok(SystemStatus {
@@ -364,6 +394,7 @@ pub async fn get_status(
         voice_enabled: state.config.voice_enabled,
         web_search_enabled: state.config.web_search_enabled,
         paused: state.is_paused(),
+        llm_endpoints: state.llm_client.endpoint_statuses(),
     })))
 }
 
@@ -371,21 +402,37 @@ pub async fn get_status(
 
 #[derive(Serialize)]
 pub struct ModelsResponse {
-    pub models: Vec<String>,
+    pub models: Vec<ModelInfo>,
     pub current: String,
+    pub current_vision: String,
+}
+
+#[derive(Deserialize)]
+pub struct ModelsQuery {
+    /// `chat`, `vision` or `embedding`; all models when absent
+    pub capability: Option<String>,
 }
 
 pub async fn list_models(
     headers: HeaderMap,
     State(state): State<AppState>,
+    Query(query): Query<ModelsQuery>,
 ) -> Result<Json<ApiResponse<ModelsResponse>>, StatusCode> {
     extract_user(&headers, &state)?;
 
-    let models = state.llm_client.list_models().await.unwrap_or_default();
+    let mut models = state.llm_client.list_models_info().await.unwrap_or_default();
+    match query.capability.as_deref() {
+        Some("chat") => models.retain(ModelInfo::can_chat),
+        Some("vision") => models.retain(ModelInfo::can_see),
+        Some("embedding") => models.retain(|m| m.kind == ModelKind::Embedding || !m.discovered),
+        Some(_) => return Err(StatusCode::BAD_REQUEST),
+        None => {}
+    }
     
     Ok(Json(ApiResponse::ok(ModelsResponse {
         models,
-        current: state.config.ollama_chat_model.clone(),
+        current: state.chat_model().await,
+        current_vision: state.vision_model().await,
     })))
 }
 
@@ -436,6 +483,150 @@ pub async fn update_triggers(
     Ok(Json(ApiResponse::ok(())))
 }
 
+// --- Chat Memory ---
+
+/// Most memories returned per request
+const MEMORY_PAGE_MAX: u32 = 100;
+
+#[derive(Serialize)]
+pub struct MemoryEntryResponse {
+    pub id: i64,
+    pub text: String,
+    pub importance: f64,
+    pub pinned: bool,
+    pub username: Option<String>,
+    pub sent_at: String,
+    /// Ranking score (similarity × decay × importance), in search results
+    pub score: Option<f64>,
+}
+
+impl From<db::MemoryEntry> for MemoryEntryResponse {
+    fn from(entry: db::MemoryEntry) -> Self {
+        Self {
+            id: entry.id,
+            text: entry.text,
+            importance: entry.importance,
+            pinned: manage::is_pinned(entry.importance),
+            username: entry.username,
+            sent_at: entry.sent_at.format("%Y-%m-%d %H:%M").to_string(),
+            score: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MemoryQuery {
+    /// Search text; without it memories are listed newest first
+    pub q: Option<String>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateMemoryRequest {
+    pub text: Option<String>,
+    pub pinned: Option<bool>,
+}
+
+pub async fn list_memory(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Path(chat_id): Path<i64>,
+    Query(query): Query<MemoryQuery>,
+) -> Result<Json<ApiResponse<Vec<MemoryEntryResponse>>>, StatusCode> {
+    extract_user(&headers, &state)?;
+
+    let limit = query.limit.unwrap_or(20).clamp(1, MEMORY_PAGE_MAX);
+    match query.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+        Some(q) => match manage::search(&state, chat_id, q, limit).await {
+            Ok(chunks) => Ok(Json(ApiResponse::ok(
+                chunks
+                    .into_iter()
+                    .map(|c| MemoryEntryResponse {
+                        id: c.id,
+                        text: c.text,
+                        importance: c.importance,
+                        pinned: manage::is_pinned(c.importance),
+                        username: None,
+                        sent_at: c.sent_at.format("%Y-%m-%d %H:%M").to_string(),
+                        score: Some(c.score),
+                    })
+                    .collect(),
+            ))),
+            Err(e) => {
+                log::error!("Failed to search memory: {}", e);
+                Ok(Json(ApiResponse::err("Search failed")))
+            }
+        },
+        None => match db::get_chat_memory(&state.db_pool, chat_id, limit, query.offset.unwrap_or(0)).await {
+            Ok(entries) => Ok(Json(ApiResponse::ok(entries.into_iter().map(Into::into).collect()))),
+            Err(e) => {
+                log::error!("Failed to list memory: {}", e);
+                Ok(Json(ApiResponse::err("Database error")))
+            }
+        },
+    }
+}
+
+pub async fn update_memory(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Path((chat_id, chunk_id)): Path<(i64, i64)>,
+    Json(req): Json<UpdateMemoryRequest>,
+) -> Result<Json<ApiResponse<MemoryEntryResponse>>, StatusCode> {
+    extract_user(&headers, &state)?;
+
+    match db::get_memory_entry(&state.db_pool, chunk_id).await {
+        Ok(Some(entry)) if entry.chat_id == chat_id => {}
+        Ok(_) => return Ok(Json(ApiResponse::err("Memory not found"))),
+        Err(e) => {
+            log::error!("Failed to get memory chunk: {}", e);
+            return Ok(Json(ApiResponse::err("Database error")));
+        }
+    }
+
+    if let Some(text) = req.text.as_deref().map(str::trim) {
+        if text.is_empty() {
+            return Ok(Json(ApiResponse::err("Text is empty")));
+        }
+        if let Err(e) = manage::edit(&state, chunk_id, text).await {
+            log::error!("Failed to edit memory chunk {}: {}", chunk_id, e);
+            return Ok(Json(ApiResponse::err("Failed to re-embed text")));
+        }
+    }
+    if let Some(pinned) = req.pinned {
+        let _ = manage::set_pinned(&state, chunk_id, pinned).await;
+    }
+
+    match db::get_memory_entry(&state.db_pool, chunk_id).await {
+        Ok(Some(entry)) => Ok(Json(ApiResponse::ok(entry.into()))),
+        _ => Ok(Json(ApiResponse::err("Database error"))),
+    }
+}
+
+pub async fn delete_memory(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Path((chat_id, chunk_id)): Path<(i64, i64)>,
+) -> Result<Json<ApiResponse<()>>, StatusCode> {
+    extract_user(&headers, &state)?;
+
+    match db::get_memory_entry(&state.db_pool, chunk_id).await {
+        Ok(Some(entry)) if entry.chat_id == chat_id => match manage::forget(&state, chunk_id).await {
+            Ok(_) => Ok(Json(ApiResponse::ok(()))),
+            Err(e) => {
+                log::error!("Failed to delete memory chunk: {}", e);
+                Ok(Json(ApiResponse::err("Database error")))
+            }
+        },
+        Ok(_) => Ok(Json(ApiResponse::err("Memory not found"))),
+        Err(e) => {
+            log::error!("Failed to get memory chunk: {}", e);
+            Ok(Json(ApiResponse::err("Database error")))
+        }
+    }
+}
+
 // --- Broadcast ---
 
 #[derive(Deserialize)]
@@ -516,7 +707,9 @@ pub struct RuntimeConfigResponse {
     pub voice_enabled: bool,
     pub web_search_enabled: bool,
     pub rag_decay_rate: f64,
+    pub rag_min_score: f64,
     pub summary_threshold: u32,
+    pub fact_threshold: u32,
     pub max_concurrent_llm_requests: u32,
     pub llm_timeout_seconds: u64,
     pub random_reply_probability: f64,
@@ -533,7 +726,9 @@ pub struct UpdateConfigRequest {
     pub voice_enabled: Option<bool>,
     pub web_search_enabled: Option<bool>,
     pub rag_decay_rate: Option<f64>,
+    pub rag_min_score: Option<f64>,
     pub summary_threshold: Option<u32>,
+    pub fact_threshold: Option<u32>,
     pub max_concurrent_llm_requests: Option<u32>,
     pub llm_timeout_seconds: Option<u64>,
     pub random_reply_probability: Option<f64>,
@@ -558,7 +753,9 @@ pub async fn get_config(
         voice_enabled: db::get_config_bool(&state.db_pool, "voice_enabled", state.config.voice_enabled).await,
         web_search_enabled: db::get_config_bool(&state.db_pool, "web_search_enabled", state.config.web_search_enabled).await,
         rag_decay_rate: db::get_config_f64(&state.db_pool, "rag_decay_rate", state.config.rag_decay_rate).await,
+        rag_min_score: db::get_config_f64(&state.db_pool, "rag_min_score", state.config.rag_min_score).await,
         summary_threshold: db::get_config_u32(&state.db_pool, "summary_threshold", state.config.summary_threshold).await,
+        fact_threshold: db::get_config_u32(&state.db_pool, "fact_threshold", state.config.fact_threshold).await,
         max_concurrent_llm_requests: db::get_config_u32(&state.db_pool, "max_concurrent_llm_requests", 
             state.config.max_concurrent_llm_requests.unwrap_or(3) as u32).await,
         llm_timeout_seconds: db::get_config(&state.db_pool, "llm_timeout_seconds")
@@ -582,6 +779,8 @@ pub async fn update_config(
     }
     if let Some(v) = req.ollama_embedding_model {
         let _ = db::set_config(&state.db_pool, "ollama_embedding_model", &v).await;
+        // Old chunks are re-embedded with the new model in the background
+        reembed::start(&state);
     }
     if let Some(v) = req.ollama_vision_model {
         let _ = db::set_config(&state.db_pool, "ollama_vision_model", &v).await;
@@ -604,9 +803,15 @@ pub async fn update_config(
     if let Some(v) = req.rag_decay_rate {
         let _ = db::set_config(&state.db_pool, "rag_decay_rate", &v.to_string()).await;
     }
+    if let Some(v) = req.rag_min_score {
+        let _ = db::set_config(&state.db_pool, "rag_min_score", &v.to_string()).await;
+    }
     if let Some(v) = req.summary_threshold {
         let _ = db::set_config(&state.db_pool, "summary_threshold", &v.to_string()).await;
     }
+    if let Some(v) = req.fact_threshold {
+        let _ = db::set_config(&state.db_pool, "fact_threshold", &v.to_string()).await;
+    }
     if let Some(v) = req.max_concurrent_llm_requests {
         let _ = db::set_config(&state.db_pool, "max_concurrent_llm_requests", &v.to_string()).await;
     }
@@ -63,6 +63,9 @@ pub fn create_router(state: AppState) -> Router {
         .route("/chats/{chat_id}", get(api::get_chat_settings).put(api::update_chat_settings))
         // Triggers
         .route("/chats/{chat_id}/triggers", get(api::get_triggers).put(api::update_triggers))
+        // Memory
+        .route("/chats/{chat_id}/memory", get(api::list_memory))
+        .route("/chats/{chat_id}/memory/{chunk_id}", put(api::update_memory).delete(api::delete_memory))
         // Security
         .route("/security", get(api::get_security_config))
         .route("/security/users/{user_id}", get(api::get_user_security_status))
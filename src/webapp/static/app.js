@@ -81,11 +81,23 @@ async function loadStatus() {
         if (status.voice_enabled) features.push('🎤 Voice');
         if (status.web_search_enabled) features.push('🌐 Web');
         document.getElementById('features-list').textContent = features.join(' • ') || 'Нет дополнительных функций';
+
+        renderEndpoints(status.llm_endpoints || []);
     } catch (e) {
         console.error('Failed to load status:', e);
     }
 }
 
+function renderEndpoints(endpoints) {
+    const card = document.getElementById('llm-endpoints-card');
+    const list = document.getElementById('llm-endpoints');
+    card.style.display = endpoints.length ? '' : 'none';
+    list.innerHTML = endpoints.map(ep => {
+        const icon = ep.circuit === 'open' ? '🔴' : (ep.healthy && ep.circuit === 'closed' ? '🟢' : '🟡');
+        return `<div>${icon} ${escapeHtml(ep.url)} — в работе ${ep.in_flight}, запросов ${ep.total_requests} (❌${ep.total_failures})</div>`;
+    }).join('');
+}
+
 // Pause functionality
 function updatePauseButton(isPaused) {
     const btn = document.getElementById('pause-btn');
@@ -321,6 +333,7 @@ async function loadChats() {
                 <div class="list-item-actions">
                     <button class="btn btn-small btn-secondary" onclick="editChat(${c.chat_id})">Настройки</button>
                     <button class="btn btn-small btn-secondary" onclick="editTriggers(${c.chat_id})">Триггеры</button>
+                    <button class="btn btn-small btn-secondary" onclick="openMemory(${c.chat_id})">Память</button>
                 </div>
             </div>
         `).join('');
@@ -348,6 +361,17 @@ async function editChat(chatId) {
                     <span class="toggle-slider"></span>
                 </label>
             </div>
+            <div class="toggle-row">
+                <span>Гибридный поиск</span>
+                <label class="toggle">
+                    <input type="checkbox" id="hybrid-search" ${settings.hybrid_search_enabled ? 'checked' : ''}>
+                    <span class="toggle-slider"></span>
+                </label>
+            </div>
+            <div class="form-group">
+                <label>Реранк воспоминаний LLM (бюджет, мс; 0 — выкл)</label>
+                <input type="number" id="rerank-budget" value="${settings.rerank_budget_ms}" min="0" max="30000" step="500">
+            </div>
             <div class="form-group">
                 <label>Режим ответов</label>
                 <select id="reply-mode">
@@ -363,6 +387,10 @@ async function editChat(chatId) {
                 <label>Глубина контекста</label>
                 <input type="number" id="context-depth" value="${settings.context_depth}" min="1" max="50">
             </div>
+            <div class="form-group">
+                <label>Хранить сообщения (дней, 0 — всегда)</label>
+                <input type="number" id="retention-days" value="${settings.retention_days}" min="0">
+            </div>
             <button class="btn btn-primary" onclick="saveChatSettings(${chatId})">Сохранить</button>
         `);
     } catch (e) {}
@@ -373,9 +401,12 @@ async function saveChatSettings(chatId) {
         await api.put(`/chats/${chatId}`, {
             auto_reply_enabled: document.getElementById('auto-reply').checked,
             rag_enabled: document.getElementById('rag-enabled').checked,
+            hybrid_search_enabled: document.getElementById('hybrid-search').checked,
+            rerank_budget_ms: Math.max(0, parseInt(document.getElementById('rerank-budget').value) || 0),
             reply_mode: document.getElementById('reply-mode').value,
             cooldown_seconds: parseInt(document.getElementById('cooldown').value) || 5,
-            context_depth: parseInt(document.getElementById('context-depth').value) || 10
+            context_depth: parseInt(document.getElementById('context-depth').value) || 10,
+            retention_days: Math.max(0, parseInt(document.getElementById('retention-days').value) || 0)
         });
         closeModal();
         await loadChats();
@@ -419,6 +450,89 @@ async function clearTriggers(chatId) {
     } catch (e) {}
 }
 
+// Chat memory
+let memoryEntries = [];
+
+async function openMemory(chatId) {
+    showModal(`Память чата ${chatId}`, `
+        <div class="form-group">
+            <input type="text" id="memory-query" placeholder="Поиск по смыслу (пусто — последние)">
+        </div>
+        <button class="btn btn-primary" onclick="loadMemory(${chatId})">Найти</button>
+        <div id="memory-list" class="list"><div class="loading">Загрузка...</div></div>
+    `);
+    await loadMemory(chatId);
+}
+
+async function loadMemory(chatId) {
+    const list = document.getElementById('memory-list');
+    const query = document.getElementById('memory-query').value.trim();
+    try {
+        const params = query ? `?q=${encodeURIComponent(query)}&limit=20` : '?limit=30';
+        const entries = await api.get(`/chats/${chatId}/memory${params}`);
+        memoryEntries = entries;
+        if (entries.length === 0) {
+            list.innerHTML = '<div class="empty">Ничего не найдено</div>';
+            return;
+        }
+        list.innerHTML = entries.map(m => `
+            <div class="list-item">
+                <div class="list-item-header">
+                    <span class="list-item-title">#${m.id} ${m.pinned ? '📌' : ''}</span>
+                    <span class="badge">${m.score !== null ? m.score.toFixed(3) : m.sent_at}</span>
+                </div>
+                <div class="list-item-subtitle">${m.username ? escapeHtml(m.username) + ': ' : ''}${escapeHtml(m.text)}</div>
+                <div class="list-item-actions">
+                    <button class="btn btn-small btn-secondary" onclick="pinMemory(${chatId}, ${m.id}, ${!m.pinned})">${m.pinned ? 'Открепить' : 'Закрепить'}</button>
+                    <button class="btn btn-small btn-secondary" onclick="editMemory(${chatId}, ${m.id})">Изменить</button>
+                    <button class="btn btn-small btn-danger" onclick="deleteMemory(${chatId}, ${m.id})">Удалить</button>
+                </div>
+            </div>
+        `).join('');
+    } catch (e) {
+        list.innerHTML = '<div class="empty">Ошибка загрузки</div>';
+    }
+}
+
+async function pinMemory(chatId, id, pinned) {
+    try {
+        await api.put(`/chats/${chatId}/memory/${id}`, { pinned });
+        await loadMemory(chatId);
+    } catch (e) {}
+}
+
+function editMemory(chatId, id) {
+    const entry = memoryEntries.find(m => m.id === id);
+    showModal(`Воспоминание #${id}`, `
+        <div class="form-group">
+            <label>Текст (будет пересчитан эмбеддинг)</label>
+            <textarea id="memory-text">${escapeHtml(entry ? entry.text : '')}</textarea>
+        </div>
+        <button class="btn btn-primary" onclick="saveMemory(${chatId}, ${id})">Сохранить</button>
+        <button class="btn btn-secondary" onclick="openMemory(${chatId})">Назад</button>
+    `);
+}
+
+async function saveMemory(chatId, id) {
+    const text = document.getElementById('memory-text').value.trim();
+    if (!text) return;
+    try {
+        await api.put(`/chats/${chatId}/memory/${id}`, { text });
+        await openMemory(chatId);
+    } catch (e) {}
+}
+
+async function deleteMemory(chatId, id) {
+    tg.showConfirm(`Удалить воспоминание #${id}?`, async (confirmed) => {
+        if (confirmed) {
+            try {
+                await api.delete(`/chats/${chatId}/memory/${id}`);
+                await loadMemory(chatId);
+            } catch (e) {}
+        }
+    });
+}
+
 // Settings tab
 async function loadConfig() {
     await loadConfigForm();
@@ -437,7 +551,9 @@ async function loadConfigForm() {
         document.getElementById('cfg-llm-timeout').value = cfg.llm_timeout_seconds;
         document.getElementById('cfg-max-concurrent').value = cfg.max_concurrent_llm_requests;
         document.getElementById('cfg-decay-rate').value = cfg.rag_decay_rate;
+        document.getElementById('cfg-min-score').value = cfg.rag_min_score;
         document.getElementById('cfg-summary-threshold').value = cfg.summary_threshold;
+        document.getElementById('cfg-fact-threshold').value = cfg.fact_threshold;
         document.getElementById('cfg-vision-enabled').checked = cfg.vision_enabled;
         document.getElementById('cfg-voice-enabled').checked = cfg.voice_enabled;
         document.getElementById('cfg-web-search').checked = cfg.web_search_enabled;
@@ -458,7 +574,9 @@ async function saveConfig() {
             llm_timeout_seconds: parseInt(document.getElementById('cfg-llm-timeout').value),
             max_concurrent_llm_requests: parseInt(document.getElementById('cfg-max-concurrent').value),
             rag_decay_rate: parseFloat(document.getElementById('cfg-decay-rate').value),
+            rag_min_score: parseFloat(document.getElementById('cfg-min-score').value),
             summary_threshold: parseInt(document.getElementById('cfg-summary-threshold').value),
+            fact_threshold: parseInt(document.getElementById('cfg-fact-threshold').value),
             vision_enabled: document.getElementById('cfg-vision-enabled').checked,
             voice_enabled: document.getElementById('cfg-voice-enabled').checked,
             web_search_enabled: document.getElementById('cfg-web-search').checked,